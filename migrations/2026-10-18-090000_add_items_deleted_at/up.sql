@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- soft delete: removed items stay in the table (the trash) until purged
+ALTER TABLE items ADD COLUMN deleted_at BIGINT;
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- Undo/Redo journal of the item mutations.
+--     Each entry keeps the item before and after the change (as toml text).
+--     NULL snapshot means the item did not exist (created/deleted).
+--     Entries with the same action number are undone/redone together.
+
+CREATE TABLE if not exists journal (
+    id                  INTEGER PRIMARY KEY NOT NULL,
+    action              INTEGER NOT NULL,
+    label               TEXT NOT NULL,
+    item_id             INTEGER NOT NULL,
+    before              TEXT,
+    after               TEXT,
+    undone              BOOL NOT NULL
+);
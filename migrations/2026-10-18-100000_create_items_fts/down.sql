@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER items_fts_update;
+DROP TRIGGER items_fts_delete;
+DROP TRIGGER items_fts_insert;
+DROP TABLE items_fts;
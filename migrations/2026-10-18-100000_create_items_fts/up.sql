@@ -0,0 +1,28 @@
+-- Your SQL goes here
+-- Full-text search index over the items title and note.
+--     The trigram tokenizer does not depend on spaces between the words,
+--     so it works the same for Latin, Persian/Arabic and Chinese texts.
+--     The index is kept in sync with the items table by triggers.
+
+CREATE VIRTUAL TABLE if not exists items_fts USING fts5(
+    title,
+    note,
+    content='items',
+    content_rowid='id',
+    tokenize='trigram remove_diacritics 1'
+);
+
+INSERT INTO items_fts(items_fts) VALUES('rebuild');
+
+CREATE TRIGGER if not exists items_fts_insert AFTER INSERT ON items BEGIN
+    INSERT INTO items_fts(rowid, title, note) VALUES (new.id, new.title, new.note);
+END;
+
+CREATE TRIGGER if not exists items_fts_delete AFTER DELETE ON items BEGIN
+    INSERT INTO items_fts(items_fts, rowid, title, note) VALUES ('delete', old.id, old.title, old.note);
+END;
+
+CREATE TRIGGER if not exists items_fts_update AFTER UPDATE OF title, note ON items BEGIN
+    INSERT INTO items_fts(items_fts, rowid, title, note) VALUES ('delete', old.id, old.title, old.note);
+    INSERT INTO items_fts(rowid, title, note) VALUES (new.id, new.title, new.note);
+END;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX item_history_item_id;
+DROP TABLE item_history;
+ALTER TABLE items DROP COLUMN completed_at;
+ALTER TABLE items DROP COLUMN updated_at;
+ALTER TABLE items DROP COLUMN created_at;
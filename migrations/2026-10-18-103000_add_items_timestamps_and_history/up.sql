@@ -0,0 +1,17 @@
+-- Your SQL goes here
+-- Timestamps (unix seconds) of the items, and the field level history of their changes.
+
+ALTER TABLE items ADD COLUMN created_at BIGINT;
+ALTER TABLE items ADD COLUMN updated_at BIGINT;
+ALTER TABLE items ADD COLUMN completed_at BIGINT;
+
+CREATE TABLE if not exists item_history (
+    id                  INTEGER PRIMARY KEY NOT NULL,
+    item_id             INTEGER NOT NULL,
+    field               TEXT NOT NULL,
+    old_value           TEXT,
+    new_value           TEXT,
+    changed_at          BIGINT NOT NULL
+);
+
+CREATE INDEX if not exists item_history_item_id ON item_history (item_id);
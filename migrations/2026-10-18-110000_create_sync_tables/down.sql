@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE sync_state;
+DROP TABLE sync_clock;
+DROP TABLE sync_log;
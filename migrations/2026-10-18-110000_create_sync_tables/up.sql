@@ -0,0 +1,24 @@
+-- Your SQL goes here
+-- Sync: the local changes not pushed yet, the last write of each item field,
+-- and the sync state (like the pull cursor of each remote).
+
+CREATE TABLE if not exists sync_log (
+    id                  INTEGER PRIMARY KEY NOT NULL,
+    uuid                TEXT NOT NULL,
+    field               TEXT NOT NULL,
+    value               TEXT,
+    changed_at          BIGINT NOT NULL
+);
+
+CREATE TABLE if not exists sync_clock (
+    uuid                TEXT NOT NULL,
+    field               TEXT NOT NULL,
+    changed_at          BIGINT NOT NULL,
+    device              TEXT NOT NULL,
+    PRIMARY KEY (uuid, field)
+);
+
+CREATE TABLE if not exists sync_state (
+    key                 TEXT PRIMARY KEY NOT NULL,
+    value               TEXT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- why the item has its status, like what it is blocked by
+ALTER TABLE items ADD COLUMN status_reason TEXT;
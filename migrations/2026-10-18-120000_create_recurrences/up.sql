@@ -0,0 +1,19 @@
+-- Your SQL goes here
+-- Recurrence rules of the goals and notes. their occurrences are items,
+-- with the uuid `<rule uuid>:<occurrence key>`.
+
+CREATE TABLE if not exists recurrences (
+    uuid                TEXT PRIMARY KEY NOT NULL,
+    kind                INTEGER NOT NULL,
+    text                TEXT NOT NULL,
+    calendar            INTEGER NOT NULL,
+    frequency           INTEGER NOT NULL,
+    interval            INTEGER NOT NULL DEFAULT 1,
+    weekdays            INTEGER,
+    month               INTEGER,
+    month_day           INTEGER,
+    start_day           INTEGER NOT NULL,
+    end_day             INTEGER,
+    created_at          BIGINT,
+    updated_at          BIGINT
+);
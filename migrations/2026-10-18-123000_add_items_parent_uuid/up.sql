@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- the uuid of the parent item, for the subitems of a goal
+ALTER TABLE items ADD COLUMN parent_uuid TEXT;
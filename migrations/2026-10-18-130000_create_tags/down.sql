@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX item_tags_tag_uuid;
+DROP TABLE item_tags;
+DROP TABLE tags;
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+-- Tags of the items. an item and a tag are linked by their uuids, so the
+-- links stay right when the items are exported, imported or synced.
+
+CREATE TABLE if not exists tags (
+    uuid                TEXT PRIMARY KEY NOT NULL,
+    name                TEXT NOT NULL,
+    created_at          BIGINT,
+    updated_at          BIGINT
+);
+
+CREATE TABLE if not exists item_tags (
+    item_uuid           TEXT NOT NULL,
+    tag_uuid            TEXT NOT NULL,
+    PRIMARY KEY (item_uuid, tag_uuid)
+);
+
+CREATE INDEX if not exists item_tags_tag_uuid ON item_tags (tag_uuid);
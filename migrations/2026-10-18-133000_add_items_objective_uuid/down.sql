@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE items DROP COLUMN objective_uuid;
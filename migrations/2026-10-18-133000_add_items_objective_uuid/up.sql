@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- the uuid of the objective a weekly goal is for
+ALTER TABLE items ADD COLUMN objective_uuid TEXT;
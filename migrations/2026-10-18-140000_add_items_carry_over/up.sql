@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- how many times a goal is carried over to the next week, and for a copy,
+-- the uuid of the goal it's copied from
+ALTER TABLE items ADD COLUMN carry_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE items ADD COLUMN carried_from TEXT;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+-- Week templates: named sets of goals and notes, the items kept as toml.
+
+CREATE TABLE if not exists templates (
+    uuid                TEXT PRIMARY KEY NOT NULL,
+    name                TEXT NOT NULL,
+    items               TEXT NOT NULL,
+    created_at          BIGINT,
+    updated_at          BIGINT
+);
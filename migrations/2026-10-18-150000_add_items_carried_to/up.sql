@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- for a goal copied to another week, the uuid of its copy. the goals copied
+-- before get the uuid of their first copy.
+ALTER TABLE items ADD COLUMN carried_to TEXT;
+UPDATE items SET carried_to = (
+    SELECT copy.uuid FROM items AS copy
+    WHERE copy.carried_from = items.uuid
+    ORDER BY copy.id LIMIT 1
+) WHERE uuid IS NOT NULL;
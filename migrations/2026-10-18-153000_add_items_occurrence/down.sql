@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE recurrences DROP COLUMN skipped;
+DROP INDEX IF EXISTS items_occurrence;
+ALTER TABLE items DROP COLUMN occurrence_key;
+ALTER TABLE items DROP COLUMN recurrence_uuid;
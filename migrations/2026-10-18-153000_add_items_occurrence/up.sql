@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- the recurrence rule of an occurrence and its key in the rule. the
+-- occurrences made before have them in their uuid, `<rule uuid>:<key>`.
+ALTER TABLE items ADD COLUMN recurrence_uuid TEXT;
+ALTER TABLE items ADD COLUMN occurrence_key TEXT;
+UPDATE items SET
+    recurrence_uuid = substr(uuid, 1, instr(uuid, ':') - 1),
+    occurrence_key = substr(uuid, instr(uuid, ':') + 1)
+WHERE instr(uuid, ':') > 0;
+CREATE INDEX IF NOT EXISTS items_occurrence ON items (recurrence_uuid, occurrence_key);
+
+-- the keys of the occurrences removed for good, separated by spaces.
+-- they are not made again.
+ALTER TABLE recurrences ADD COLUMN skipped TEXT NOT NULL DEFAULT '';
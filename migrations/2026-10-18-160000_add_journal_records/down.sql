@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM journal WHERE record != 'item';
+ALTER TABLE journal DROP COLUMN record_key;
+ALTER TABLE journal DROP COLUMN record;
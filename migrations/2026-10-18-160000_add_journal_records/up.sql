@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- the journal keeps the other records an action changes too: the tags, their
+-- links to the items, the recurrence rules and the templates. they are
+-- identified by their key (the uuid, or "<item uuid> <tag uuid>" of a link),
+-- the item entries keep the item id.
+ALTER TABLE journal ADD COLUMN record TEXT NOT NULL DEFAULT 'item';
+ALTER TABLE journal ADD COLUMN record_key TEXT;
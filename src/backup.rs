@@ -0,0 +1,296 @@
+/* Backups */
+
+// backups are made with the SQLite online backup API, so they are consistent
+// even while the database is in use. the files are named like:
+//     <database file name>.<timestamp>.backup
+// and are kept next to the database file, or in the configured directory.
+
+use crate::config;
+use crate::db_sqlite;
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::time;
+use rusqlite::backup::Progress;
+use rusqlite::{Connection, DatabaseName};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const BACKUP_EXTENSION: &str = ".backup";
+const DAY_SECONDS: i64 = 24 * 3600;
+const SCHEDULE_CHECK_SECONDS: i64 = 3600;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct BackupInfo {
+    pub path: String,
+    // unix timestamp (seconds) of the backup
+    pub created_at: i64,
+    pub size: u64,
+    // checked with `db_sqlite::is_correct_db()`
+    pub valid: bool,
+}
+
+fn backup_directory(config: &config::Config) -> AppResult<PathBuf> {
+    if let Some(dir) = &config.backup_directory {
+        return Ok(PathBuf::from(dir));
+    }
+    Path::new(&config.database)
+        .parent()
+        .map(|p| p.to_path_buf())
+        .ok_or(AppError::DatabaseBackupError(
+            "invalid database path".into(),
+        ))
+}
+
+fn backup_file_prefix(config: &config::Config) -> AppResult<String> {
+    Path::new(&config.database)
+        .file_name()
+        .map(|name| format!("{}.", name.to_string_lossy()))
+        .ok_or(AppError::DatabaseBackupError(
+            "invalid database path".into(),
+        ))
+}
+
+// "2024-09-30T10-17-50+03-30" (maybe followed by "_1", "_2", ...) -> unix timestamp
+fn parse_backup_timestamp(text: &str) -> Option<i64> {
+    let text = text.get(0..25)?;
+    let date = text.get(0..10)?;
+    let time = text.get(11..19)?.replace('-', ":");
+    let offset = text.get(19..25)?.replace('-', ":");
+    chrono::DateTime::parse_from_rfc3339(&format!("{date}T{time}{offset}"))
+        .ok()
+        .map(|datetime| datetime.timestamp())
+}
+
+fn new_backup_path(config: &config::Config) -> AppResult<PathBuf> {
+    let mut timestamp = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false);
+    timestamp = timestamp.replace(':', "-");
+    let prefix = backup_file_prefix(config)?;
+    let directory = backup_directory(config)?;
+    let mut path = directory.join(format!("{prefix}{timestamp}{BACKUP_EXTENSION}"));
+    // more than one backup in a second
+    let mut n = 1;
+    while path.exists() {
+        path = directory.join(format!("{prefix}{timestamp}_{n}{BACKUP_EXTENSION}"));
+        n += 1;
+    }
+    Ok(path)
+}
+
+/// make a backup of the current database now.
+/// returns the path of the backup file.
+pub fn backup_now() -> AppResult<PathBuf> {
+    let target = make_backup(&config::get_config())?;
+    prune_backups()?;
+    Ok(target)
+}
+
+// a new backup file of the database, without pruning the old ones
+fn make_backup(config: &config::Config) -> AppResult<PathBuf> {
+    let target = new_backup_path(config)?;
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(|e| AppError::DatabaseBackupError(e.to_string()))?;
+    }
+    let source = Connection::open(&config.database)
+        .map_err(|e| AppError::DatabaseBackupError(e.to_string()))?;
+    source
+        .backup(DatabaseName::Main, &target, None)
+        .map_err(|e| AppError::DatabaseBackupError(e.to_string()))?;
+    Ok(target)
+}
+
+/// all the backup files of the current database, the newest first
+pub fn list_backups() -> AppResult<Vec<BackupInfo>> {
+    let config = config::get_config();
+    let directory = backup_directory(&config)?;
+    let prefix = backup_file_prefix(&config)?;
+    let Ok(entries) = fs::read_dir(&directory) else {
+        // no directory, no backups
+        return Ok(vec![]);
+    };
+
+    let mut backups: Vec<BackupInfo> = Vec::new();
+    for entry in entries.flatten() {
+        let filename = entry.file_name().to_string_lossy().to_string();
+        let Some(timestamp) = filename
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(BACKUP_EXTENSION))
+        else {
+            continue;
+        };
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let path = entry.path().to_string_lossy().to_string();
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        backups.push(BackupInfo {
+            created_at: parse_backup_timestamp(timestamp).unwrap_or(modified),
+            size: metadata.len(),
+            valid: db_sqlite::is_correct_db(&path),
+            path,
+        });
+    }
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.created_at));
+    Ok(backups)
+}
+
+/// delete the oldest backups, so only the configured number of them remain.
+/// returns the number of deleted files.
+pub fn prune_backups() -> AppResult<usize> {
+    // never delete the newest one
+    let retention = config::get_config().backup_retention.max(1);
+    let mut count = 0;
+    for backup in list_backups()?.iter().skip(retention) {
+        fs::remove_file(&backup.path).map_err(|e| AppError::DatabaseBackupError(e.to_string()))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// make a backup if the configured schedule says it's time for one.
+/// returns the path of the new backup, if any.
+pub fn run_scheduled_backup() -> AppResult<Option<PathBuf>> {
+    let interval = match config::get_config().backup_schedule.as_str() {
+        "daily" => DAY_SECONDS,
+        "weekly" => 7 * DAY_SECONDS,
+        _ => return Ok(None),
+    };
+    if !Path::new(&config::get_config().database).exists() {
+        return Ok(None);
+    }
+    let last_backup = list_backups()?
+        .into_iter()
+        .find(|backup| backup.valid)
+        .map(|backup| backup.created_at);
+    let now = time::get_current_timestamp();
+    match last_backup {
+        Some(last) if now - last < interval => Ok(None),
+        _ => backup_now().map(Some),
+    }
+}
+
+// the database whose schedule was checked last, and when to check it again
+static SCHEDULE_CHECK: Mutex<Option<(String, i64)>> = Mutex::new(None);
+
+/// run the scheduled backup of the database, if it's due. it's checked once
+/// in a while, on the database calls: so it's cheap and an app that stays
+/// open gets its backups too. a failed backup should not stop the app.
+pub(crate) fn run_scheduled_backup_if_due(database: &str) {
+    let now = time::get_current_timestamp();
+    {
+        let mut check = SCHEDULE_CHECK.lock().unwrap_or_else(|e| e.into_inner());
+        if matches!(&*check, Some((checked, next)) if checked == database && now < *next) {
+            return;
+        }
+        *check = Some((database.to_string(), now + SCHEDULE_CHECK_SECONDS));
+    }
+    if let Err(e) = run_scheduled_backup() {
+        println!("error! scheduled backup failed: {e}");
+    }
+}
+
+// replace the content of the database with the file.
+// nothing else uses the database until it's replaced and opened again.
+fn restore_file<P: AsRef<Path>>(database: &str, path: P) -> AppResult<()> {
+    db_sqlite::with_connection_closed(|| {
+        let mut target =
+            Connection::open(database).map_err(|e| AppError::DatabaseBackupError(e.to_string()))?;
+        target
+            .restore(DatabaseName::Main, path, None::<fn(Progress)>)
+            .map_err(|e| AppError::DatabaseBackupError(e.to_string()))
+    })
+}
+
+/// replace the content of the current database with the backup.
+/// a backup of the current state is made before, so this can be reverted.
+/// if the backup can not be migrated to this version, the current state is
+/// put back and the error is returned.
+pub fn restore_backup<P: AsRef<str>>(path: P) -> AppResult<()> {
+    let path = path.as_ref();
+    if !db_sqlite::is_correct_db(path) {
+        return Err(AppError::DatabaseFileInvalidError);
+    }
+    let config = config::get_config();
+    // not pruned before the restore, that may delete the backup itself
+    let safety_backup = make_backup(&config)?;
+    restore_file(&config.database, path)?;
+
+    // the backup may be from an older version
+    if let Err(e) = db_sqlite::run_migrations() {
+        restore_file(&config.database, &safety_backup)?;
+        return Err(e);
+    }
+    prune_backups()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_sqlite::{new_test_goal, TestDatabase};
+
+    fn titles() -> Vec<String> {
+        db_sqlite::read_items_between_days(20000, 20000, true)
+            .unwrap()
+            .into_iter()
+            .filter_map(|item| item.title)
+            .collect()
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let _db = TestDatabase::new();
+        config::set_config(config::Config {
+            backup_retention: 1,
+            ..config::get_config()
+        });
+        db_sqlite::create_item(&new_test_goal(20000, "before", "m")).unwrap();
+        let backup = backup_now().unwrap();
+        let backups = list_backups().unwrap();
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].valid);
+
+        db_sqlite::create_item(&new_test_goal(20000, "after", "t")).unwrap();
+        assert_eq!(titles(), vec!["before", "after"]);
+        // the only backup is kept until it's restored
+        restore_backup(backup.to_string_lossy()).unwrap();
+        assert_eq!(titles(), vec!["before"]);
+        assert_eq!(list_backups().unwrap().len(), 1);
+
+        // nothing is due right after a backup
+        config::set_config(config::Config {
+            backup_schedule: "daily".into(),
+            ..config::get_config()
+        });
+        assert_eq!(run_scheduled_backup().unwrap(), None);
+    }
+
+    #[test]
+    fn test_scheduled_backup_on_database_calls() {
+        let _db = TestDatabase::new();
+        config::set_config(config::Config {
+            backup_schedule: "daily".into(),
+            ..config::get_config()
+        });
+        db_sqlite::create_item(&new_test_goal(20000, "first", "m")).unwrap();
+        assert_eq!(list_backups().unwrap().len(), 1);
+        // not due again the same day
+        db_sqlite::create_item(&new_test_goal(20000, "second", "t")).unwrap();
+        assert_eq!(list_backups().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_restore_invalid_backup() {
+        let db = TestDatabase::new();
+        let path = db.file("broken.backup");
+        fs::write(&path, "not a database").unwrap();
+        assert!(restore_backup(&path).is_err());
+        assert!(db_sqlite::is_correct_db(&db.path));
+    }
+}
@@ -36,13 +36,13 @@ impl CalendarLanguagePair {
             let (text, r#type) = if let Some(season) = season {
                 let season = season - 1;
                 let season = calview.seasons_names[season as usize].clone();
-                (format!("{season} {year_string}"), OBJECTIVE_TYPE_SEASONAL)
+                (format!("{season} {year_string}"), ObjectiveType::Seasonal)
             } else if let Some(month) = month {
                 let month = month - 1;
                 let month = calview.months_names[month as usize].clone();
-                (format!("{month} {year_string}"), OBJECTIVE_TYPE_MONTHLY)
+                (format!("{month} {year_string}"), ObjectiveType::Monthly)
             } else {
-                (year_string.clone(), OBJECTIVE_TYPE_YEARLY)
+                (year_string.clone(), ObjectiveType::Yearly)
             };
             Some(ObjectiveTag {
                 calendar: calview.calendar,
@@ -0,0 +1,146 @@
+/* Carry over */
+
+// the goals not done in a week can be carried over to another week (the
+// next one, usually), all at once:
+//   - `CarryOverMode::Move` moves them, to the end of the week
+//   - `CarryOverMode::Copy` leaves them and adds their copies at the end of
+//     the week. a copy keeps the uuid of its goal in `carried_from`, and the
+//     goal the uuid of its copy in `carried_to`. a goal is copied only once,
+//     even when its copy is trashed or moved to another week. the copied
+//     goals are left out of the progress of their objectives, the copies
+//     count instead.
+// each carried item counts it in `carry_count`. the subitems go with their
+// goal, and the copies get the tags of their items, in the same action. the
+// occurrences of the recurrence rules are not carried, the rule makes them in
+// each week. the `carry_over` setting of the config says what to do when the
+// current week is shown: "move", "copy" or "ask" first.
+
+use crate::config;
+use crate::db_sqlite::BatchOp;
+use crate::models::{Item, ItemKind, NewItem};
+use crate::ordering;
+use crate::prelude::Result as AppResult;
+use crate::recurrence;
+use crate::storage::Storage;
+use crate::subitems;
+use crate::tags::ItemTag;
+use crate::weekdays::SEVEN_DAY_WEEK_SIZE;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum CarryOverMode {
+    Move,
+    Copy,
+}
+
+/// the mode of the `carry_over` setting, `None` to ask first
+pub fn configured_mode() -> Option<CarryOverMode> {
+    match config::get_config().carry_over.as_str() {
+        "move" => Some(CarryOverMode::Move),
+        "copy" => Some(CarryOverMode::Copy),
+        _ => None,
+    }
+}
+
+// the goals and notes of the week, as top items and subitems
+fn week_items(storage: &dyn Storage, start_day: i32) -> AppResult<(Vec<Item>, Vec<Item>)> {
+    let items: Vec<Item> = storage
+        .read_items_between_days(start_day, start_day + SEVEN_DAY_WEEK_SIZE - 1)?
+        .into_iter()
+        .filter(|item| item.kind != ItemKind::Event)
+        .collect();
+    Ok(subitems::split_subitems(items))
+}
+
+/// the unfinished goals of the week from `from_start_day`, that are not
+/// copied yet, in their order
+pub fn goals_to_carry_over(storage: &dyn Storage, from_start_day: i32) -> AppResult<Vec<Item>> {
+    let (goals, _) = week_items(storage, from_start_day)?;
+    Ok(goals
+        .into_iter()
+        .filter(|goal| goal.kind == ItemKind::Goal)
+        .filter(|goal| !goal.status.unwrap_or_default().is_closed())
+        .filter(|goal| recurrence::occurrence_of(goal).is_none())
+        .filter(|goal| goal.carried_to.is_none())
+        .collect())
+}
+
+/// carry the unfinished goals of the week from `from_start_day` over to the
+/// week of `to_start_day`, after its items, as one undoable action.
+/// returns the number of carried goals.
+pub fn carry_over(
+    storage: &dyn Storage,
+    from_start_day: i32,
+    to_start_day: i32,
+    mode: CarryOverMode,
+) -> AppResult<usize> {
+    let goals = goals_to_carry_over(storage, from_start_day)?;
+    if goals.is_empty() {
+        return Ok(0);
+    }
+    let (_, from_subitems) = week_items(storage, from_start_day)?;
+    let last_key = storage
+        .read_items_between_days(to_start_day, to_start_day + SEVEN_DAY_WEEK_SIZE - 1)?
+        .into_iter()
+        .filter_map(|item| item.order_in_week)
+        .max()
+        .unwrap_or_default();
+    let keys = ordering::ordering_keys_after(&last_key, goals.len());
+    let shift = to_start_day - from_start_day;
+
+    let mut ops: Vec<BatchOp> = Vec::new();
+    // the uuids of the copies, by the uuids of their items
+    let mut copies: HashMap<String, String> = HashMap::new();
+    for (goal, key) in goals.iter().zip(keys) {
+        let mut carried = vec![goal.clone()];
+        carried.extend(subitems::descendants_of(goal, &from_subitems));
+        for source in carried {
+            let mut item = source.clone();
+            item.day += shift;
+            item.carry_count += 1;
+            if item.id == goal.id {
+                item.order_in_week = Some(key.clone());
+            }
+            match mode {
+                CarryOverMode::Move => ops.push(BatchOp::Update(item)),
+                CarryOverMode::Copy => {
+                    let mut new_item = NewItem::from(&item);
+                    new_item.carried_from = source.uuid.clone();
+                    new_item.carried_to = None;
+                    if let (Some(uuid), Some(copy)) = (&source.uuid, &new_item.uuid) {
+                        copies.insert(uuid.clone(), copy.clone());
+                    }
+                    // the item stays in its week, marked as copied
+                    let mut original = source;
+                    original.carried_to = new_item.uuid.clone();
+                    ops.push(BatchOp::Update(original));
+                    ops.push(BatchOp::Insert(new_item));
+                }
+            }
+        }
+    }
+    if mode == CarryOverMode::Copy {
+        // the copied subitems are under the copies of their parents
+        for op in ops.iter_mut() {
+            if let BatchOp::Insert(new_item) = op {
+                if let Some(copy) = new_item.parent_uuid.as_ref().and_then(|p| copies.get(p)) {
+                    new_item.parent_uuid = Some(copy.clone());
+                }
+            }
+        }
+    }
+    if !copies.is_empty() {
+        let uuids: Vec<String> = copies.keys().cloned().collect();
+        for item_tag in storage.read_item_tags(&uuids)? {
+            if let Some(copy) = copies.get(&item_tag.item_uuid) {
+                ops.push(BatchOp::Tag(ItemTag {
+                    item_uuid: copy.clone(),
+                    tag_uuid: item_tag.tag_uuid,
+                }));
+            }
+        }
+    }
+    storage.apply_batch(Some("carry over goals"), &ops)?;
+    Ok(goals.len())
+}
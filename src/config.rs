@@ -1,9 +1,11 @@
 use crate::calendar::Calendar;
 use crate::calendar::CalendarLanguagePair;
 use crate::db_sqlite;
+use crate::integrity;
 use crate::language::Language;
 use crate::prelude::Error as AppError;
 use crate::prelude::Result as AppResult;
+use crate::profile::Profile;
 use arc_swap::ArcSwap;
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
@@ -23,6 +25,25 @@ pub fn set_config(new_cfg: Config) {
     }
 }
 
+// the tests share the global config (and the database connection of it), so
+// the ones that use it run one at a time, holding this lock
+#[cfg(test)]
+static TEST_CONFIG_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// set the config of a test. keep the guard until the end of the test.
+/// the config file is in a temporary directory, not the one of the user.
+#[cfg(test)]
+pub(crate) fn set_test_config(new_cfg: Config) -> std::sync::MutexGuard<'static, ()> {
+    let guard = TEST_CONFIG_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    set_config_path(Some(
+        std::env::temp_dir()
+            .join(format!("thisweek-test-config-{}", std::process::id()))
+            .join("config.toml"),
+    ));
+    set_config(new_cfg);
+    guard
+}
+
 pub fn reload_config_file() {
     println!("reloading config file...");
     let config_path = get_config_path();
@@ -94,13 +115,20 @@ pub fn move_database<P: AsRef<str>>(filepath: P) -> Result<(), AppError> {
         if !current_db_valid {
             // create new db
             println!("Attempting to creating new database file: {}", filepath);
-            db_sqlite::create_db(filepath)
+            db_sqlite::create_db(filepath)?;
+            // switch to the new database
+            config.database = filepath.to_string();
+            set_config(config.clone());
+            db_sqlite::close_connection();
+            save_config(config)
         } else {
             // move current db
             // ensure target directory exists
             if let Some(parent) = Path::new(filepath).parent() {
                 fs::create_dir_all(parent).map_err(|_| AppError::DatabaseFileCopyError)?;
             }
+            // close the shared connection, so the file is complete and not in use
+            db_sqlite::close_connection();
             // copy database file
             std::fs::copy(&current_db_path, filepath)
                 .map_err(|_| AppError::DatabaseFileCopyError)?;
@@ -128,11 +156,14 @@ pub fn open_database<P: AsRef<str>>(filepath: P) -> Result<(), AppError> {
         let db_valid = db_sqlite::is_correct_db(filepath);
         if !db_valid {
             Err(AppError::DatabaseFileInvalidError)
+        } else if !integrity::check_database(filepath)?.is_safe_to_open() {
+            Err(AppError::DatabaseFileNeedsRepairError)
         } else {
             // switch database
             // change and save config
             config.database = filepath.to_string();
             set_config(config.clone());
+            db_sqlite::close_connection();
             save_config(config)
         }
     }
@@ -151,13 +182,20 @@ pub fn set_database_file(filepath: String) -> Result<(), AppError> {
         if !current_db_valid {
             // create new db
             println!("Attempting to creating new database file: {}", filepath);
-            db_sqlite::create_db(&filepath)
+            db_sqlite::create_db(&filepath)?;
+            // switch to the new database
+            config.database = filepath;
+            set_config(config.clone());
+            db_sqlite::close_connection();
+            save_config(config)
         } else {
             // move current db
             // ensure target directory exists
             if let Some(parent) = Path::new(&filepath).parent() {
                 fs::create_dir_all(parent).map_err(|_| AppError::DatabaseFileCopyError)?;
             }
+            // close the shared connection, so the file is complete and not in use
+            db_sqlite::close_connection();
             // copy database file
             std::fs::copy(&current_db_path, &filepath)
                 .map_err(|_| AppError::DatabaseFileCopyError)?;
@@ -171,10 +209,14 @@ pub fn set_database_file(filepath: String) -> Result<(), AppError> {
                 .map_err(|_| AppError::DatabaseFileRemoveError)
         }
     } else if exists && valid {
+        if !integrity::check_database(&filepath)?.is_safe_to_open() {
+            return Err(AppError::DatabaseFileNeedsRepairError);
+        }
         // switch database
         // change and save config
         config.database = filepath;
         set_config(config.clone());
+        db_sqlite::close_connection();
         save_config(config)
     } else {
         Err(AppError::DatabaseFileInvalidError)
@@ -214,6 +256,19 @@ pub fn set_items_display_direction_config(items_direction: String) -> Result<(),
     save_config(config)
 }
 
+pub fn set_backup_config(
+    backup_schedule: String,
+    backup_retention: usize,
+    backup_directory: Option<String>,
+) -> Result<(), AppError> {
+    let mut config = get_config();
+    config.backup_schedule = backup_schedule;
+    config.backup_retention = backup_retention;
+    config.backup_directory = backup_directory;
+    set_config(config.clone());
+    save_config(config)
+}
+
 pub fn save_config(config: Config) -> Result<(), AppError> {
     let toml_str = toml::to_string(&config).map_err(|e| {
         println!("Failed to serialize config to TOML: {}", e);
@@ -297,6 +352,46 @@ pub struct Config {
     pub secondary_calendar_language: Option<String>,
     pub weekdates_display_direction: String,
     pub items_display_direction: String,
+    // automatic backups: "off", "daily" or "weekly"
+    #[serde(default = "default_backup_schedule")]
+    pub backup_schedule: String,
+    // number of backup files to keep
+    #[serde(default = "default_backup_retention")]
+    pub backup_retention: usize,
+    // where to put the backup files. None: next to the database file
+    #[serde(default)]
+    pub backup_directory: Option<String>,
+    // identifies this device in sync. generated on the first sync
+    #[serde(default)]
+    pub device_id: Option<String>,
+    // the name of the active profile. the database and calendar settings
+    // above are the ones of the active profile. see the `profile` module
+    #[serde(default = "default_profile_name")]
+    pub profile: String,
+    // the saved settings of all the profiles
+    #[serde(default)]
+    pub profiles: Vec<Profile>,
+    // the unfinished goals of last week, when the current week is shown:
+    // "move" or "copy" them to the current week, or "ask" first
+    #[serde(default = "default_carry_over")]
+    pub carry_over: String,
+}
+
+pub(crate) fn default_profile_name() -> String {
+    "default".into()
+}
+
+// off until the user picks a schedule
+fn default_backup_schedule() -> String {
+    "off".into()
+}
+
+fn default_backup_retention() -> usize {
+    10
+}
+
+fn default_carry_over() -> String {
+    "ask".into()
 }
 
 impl Config {
@@ -310,6 +405,13 @@ impl Config {
             secondary_calendar_language: self.secondary_calendar_language.clone(),
             weekdates_display_direction: self.weekdates_display_direction.clone(),
             items_display_direction: self.items_display_direction.clone(),
+            backup_schedule: self.backup_schedule.clone(),
+            backup_retention: self.backup_retention,
+            backup_directory: self.backup_directory.clone(),
+            device_id: self.device_id.clone(),
+            profile: self.profile.clone(),
+            profiles: self.profiles.clone(),
+            carry_over: self.carry_over.clone(),
         }
     }
 }
@@ -330,10 +432,33 @@ impl Default for Config {
             secondary_calendar_language: None,
             weekdates_display_direction: "ltr".into(),
             items_display_direction: "auto".into(),
+            backup_schedule: default_backup_schedule(),
+            backup_retention: default_backup_retention(),
+            backup_directory: None,
+            device_id: None,
+            profile: default_profile_name(),
+            profiles: vec![],
+            carry_over: default_carry_over(),
         }
     }
 }
 
+// the config file set by the app, instead of the default one
+static CONFIG_PATH: OnceCell<ArcSwap<Option<PathBuf>>> = OnceCell::new();
+
+/// use another config file. `None` goes back to the default one.
+pub fn set_config_path(path: Option<PathBuf>) {
+    CONFIG_PATH
+        .get_or_init(|| ArcSwap::from_pointee(None))
+        .store(Arc::new(path));
+}
+
 pub fn get_config_path() -> PathBuf {
-    default_config_data_path().unwrap().0
+    match CONFIG_PATH
+        .get()
+        .and_then(|path| path.load().as_ref().clone())
+    {
+        Some(path) => path,
+        None => default_config_data_path().unwrap().0,
+    }
 }
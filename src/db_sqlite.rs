@@ -1,30 +1,118 @@
+use std::fs;
 use std::path::Path;
+use std::sync::Mutex;
 
+use crate::backup;
 use crate::config;
+use crate::history;
+use crate::journal;
 use crate::models::Item;
+use crate::models::ItemStatus;
 use crate::models::NewItem;
-use crate::models::{ITEM_KIND_GOAL, ITEM_KIND_NOTE};
-use crate::models::{STATUS_DONE, STATUS_UNDONE};
 use crate::prelude::Error as AppError;
 use crate::prelude::Result as AppResult;
+use crate::recurrence::{self, Recurrence};
+use crate::storage::{SqliteStorage, Storage};
+use crate::tags::{self, ItemTag, Tag};
+use crate::templates::{self, Template};
+use crate::time;
+use crate::week::get_week_start_middle_end_unix_day;
 use diesel::dsl::sql;
 use diesel::prelude::*;
+use once_cell::sync::Lazy;
 
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 
-const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
+pub(crate) const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
 
-pub fn run_migrations() {
-    let connection = &mut establish_connection();
-    connection
-        .run_pending_migrations(MIGRATIONS)
-        .expect("Failed to run migrations");
+// milliseconds to wait for a locked database before giving up
+const BUSY_TIMEOUT_MS: u32 = 5000;
+
+// the long-lived connection shared by all the database functions.
+// it is opened on first use and re-opened whenever the configured
+// database path changes (see `close_connection()`).
+struct DbConnection {
+    path: String,
+    conn: SqliteConnection,
+}
+
+static CONNECTION: Lazy<Mutex<Option<DbConnection>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn run_migrations() -> AppResult<()> {
+    with_connection(|conn| {
+        conn.run_pending_migrations(MIGRATIONS)
+            .map(|_| ())
+            .map_err(|e| AppError::DatabaseMigrationError(e.to_string()))
+    })
+}
+
+fn open_connection(database_url: &str) -> AppResult<SqliteConnection> {
+    let mut conn = SqliteConnection::establish(database_url)
+        .map_err(|e| AppError::DatabaseConnectionError(e.to_string()))?;
+    let pragmas = [
+        "PRAGMA journal_mode = WAL;".to_string(),
+        "PRAGMA synchronous = NORMAL;".to_string(),
+        format!("PRAGMA busy_timeout = {BUSY_TIMEOUT_MS};"),
+    ];
+    for pragma in pragmas {
+        diesel::sql_query(pragma)
+            .execute(&mut conn)
+            .map_err(|e| AppError::DatabaseConnectionError(e.to_string()))?;
+    }
+    Ok(conn)
+}
+
+/// run `f` with the shared connection of the configured database.
+/// the connection is (re)opened if there is none or the path is changed.
+pub(crate) fn with_connection<T, F>(f: F) -> AppResult<T>
+where
+    F: FnOnce(&mut SqliteConnection) -> AppResult<T>,
+{
+    let database_url = config::get_config().database;
+    // before taking the connection, the backup doesn't use it
+    backup::run_scheduled_backup_if_due(&database_url);
+    let mut guard = CONNECTION.lock().unwrap_or_else(|e| e.into_inner());
+    let reopen = match guard.as_ref() {
+        Some(db) => db.path != database_url,
+        None => true,
+    };
+    if reopen {
+        // drop the old one first, so it is closed before opening the new file
+        *guard = None;
+        let conn = open_connection(&database_url)?;
+        *guard = Some(DbConnection {
+            path: database_url,
+            conn,
+        });
+    }
+    let db = guard.as_mut().ok_or(AppError::DatabaseFileInvalidError)?;
+    f(&mut db.conn)
 }
 
-fn establish_connection() -> SqliteConnection {
+/// run `f` while the shared connection is closed, like to replace the
+/// database file. the other database calls wait until the file is opened again.
+pub(crate) fn with_connection_closed<T, F>(f: F) -> AppResult<T>
+where
+    F: FnOnce() -> AppResult<T>,
+{
+    let mut guard = CONNECTION.lock().unwrap_or_else(|e| e.into_inner());
+    *guard = None;
+    let result = f();
     let database_url = config::get_config().database;
-    SqliteConnection::establish(&database_url)
-        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
+    let conn = open_connection(&database_url)?;
+    *guard = Some(DbConnection {
+        path: database_url,
+        conn,
+    });
+    result
+}
+
+/// close the shared connection (if any).
+/// should be called before moving, copying or replacing the database file.
+/// the next database call opens a new connection to the configured path.
+pub fn close_connection() {
+    let mut guard = CONNECTION.lock().unwrap_or_else(|e| e.into_inner());
+    *guard = None;
 }
 
 use diesel::sql_types::Integer;
@@ -33,47 +121,97 @@ fn last_inserted_id(conn: &mut SqliteConnection) -> Result<i32, diesel::result::
     diesel::select(sql::<Integer>("last_insert_rowid()")).get_result(conn)
 }
 
-pub fn create_item(new_item: &NewItem) -> AppResult<i32> {
-    use crate::schema::items::dsl::*;
-    let conn = &mut establish_connection();
+/// one write operation of a batch. see `apply_batch()`
+#[derive(Debug, Clone)]
+pub enum BatchOp {
+    Insert(NewItem),
+    Update(Item),
+    Delete(i32),
+    /// link an item to a tag
+    Tag(ItemTag),
+    /// add the tag, or replace the one with its uuid
+    SaveTag(Tag),
+    /// add the rule, or replace the one with its uuid
+    SaveRecurrence(Recurrence),
+    /// add the template, or replace the one with its uuid
+    SaveTemplate(Template),
+}
 
-    diesel::insert_into(items)
-        .values(new_item)
-        .execute(conn)
-        .map_err(|e| AppError::DatabaseInsertError(e.to_string()))?;
+impl BatchOp {
+    // the other record the operation writes, for the journal
+    fn record(&self) -> Option<(journal::Record, String)> {
+        match self {
+            BatchOp::Insert(_) | BatchOp::Update(_) | BatchOp::Delete(_) => None,
+            BatchOp::Tag(link) => Some((journal::Record::ItemTag, journal::item_tag_key(link))),
+            BatchOp::SaveTag(tag) => Some((journal::Record::Tag, tag.uuid.clone())),
+            BatchOp::SaveRecurrence(rule) => Some((journal::Record::Recurrence, rule.uuid.clone())),
+            BatchOp::SaveTemplate(template) => {
+                Some((journal::Record::Template, template.uuid.clone()))
+            }
+        }
+    }
+}
 
-    // Retrieve last inserted ID
-    last_inserted_id(conn).map_err(|e| AppError::DatabaseInsertError(e.to_string()))
+#[derive(Debug, Default, Clone)]
+pub struct BatchResult {
+    pub inserted_ids: Vec<i32>,
+    pub updated: usize,
+    pub deleted: usize,
 }
 
-pub fn remove_item(item_id: i32) -> Result<usize, String> {
+fn insert_item_on(conn: &mut SqliteConnection, new_item: &NewItem) -> AppResult<i32> {
     use crate::schema::items::dsl::*;
-    let conn = &mut establish_connection();
-
-    diesel::delete(items.filter(id.eq(item_id)))
+    let now = time::get_current_timestamp();
+    let mut new_item = new_item.clone();
+    // a copy of an existing item keeps its timestamps
+    let is_copy = new_item.updated_at.is_some();
+    new_item.created_at = new_item.created_at.or(Some(now));
+    new_item.updated_at = new_item.updated_at.or(Some(now));
+    if new_item.status == Some(ItemStatus::Done) {
+        if !is_copy {
+            new_item.completed_at = new_item.completed_at.or(Some(now));
+        }
+    } else {
+        new_item.completed_at = None;
+    }
+    new_item.sync = Some(crate::sync::SYNC_DIRTY);
+    diesel::insert_into(items)
+        .values(&new_item)
         .execute(conn)
-        .map_err(|err| err.to_string())
-}
+        .map_err(|e| AppError::DatabaseInsertError(e.to_string()))?;
 
-pub fn update_items(items: &Vec<Item>) -> Result<usize, String> {
-    println!("updating all self items in database...");
-    let mut count: usize = 0;
-    for item in items {
-        let result = update_item(item);
-        match result {
-            Ok(_) => {
-                count += 1;
-            }
-            Err(e) => {
-                println!("error! {e}");
-            }
-        }
+    // Retrieve last inserted ID
+    let new_id =
+        last_inserted_id(conn).map_err(|e| AppError::DatabaseInsertError(e.to_string()))?;
+    if let Some(item) = get_item_on(conn, new_id)? {
+        crate::sync::record_local_change_on(conn, None, Some(&item), now)?;
     }
-    Ok(count)
+    Ok(new_id)
 }
 
-pub fn update_item(item: &Item) -> Result<usize, String> {
-    let conn = &mut establish_connection();
+fn update_item_on(conn: &mut SqliteConnection, item: &Item) -> AppResult<usize> {
+    let Some(before) = get_item_on(conn, item.id)? else {
+        return Ok(0);
+    };
+    let now = time::get_current_timestamp();
+    let mut item = item.clone();
+    item.created_at = before.created_at;
+    item.updated_at = before.updated_at;
+    let was_done = before.status == Some(ItemStatus::Done);
+    let is_done = item.status == Some(ItemStatus::Done);
+    item.completed_at = match (was_done, is_done) {
+        (false, true) => item.completed_at.or(Some(now)),
+        (true, true) => before.completed_at,
+        (_, false) => None,
+    };
+    if item == before {
+        // nothing to write
+        return Ok(1);
+    }
+    item.updated_at = Some(now);
+    item.sync = Some(crate::sync::SYNC_DIRTY);
+    history::record_changes_on(conn, &before, &item, now)?;
+    crate::sync::record_local_change_on(conn, Some(&before), Some(&item), now)?;
 
     // for test
     // let query = diesel::update(item).set(item);
@@ -83,75 +221,374 @@ pub fn update_item(item: &Item) -> Result<usize, String> {
     // );
 
     // https://diesel.rs/guides/all-about-updates.html
-    diesel::update(item) // gets id from this object here
-        .set(item) // updates all the other fields from this object
+    diesel::update(&item) // gets id from this object here
+        .set(&item) // updates all the other fields from this object
         .execute(conn)
-        .map_err(|err| err.to_string())
+        .map_err(|e| AppError::DatabaseUpdateError(e.to_string()))
 }
 
-pub fn get_item(item_id: i32) -> Result<Item, String> {
+fn delete_item_on(conn: &mut SqliteConnection, item_id: i32) -> AppResult<usize> {
     use crate::schema::items::dsl::*;
-    let conn = &mut establish_connection();
+    if let Some(item) = get_item_on(conn, item_id)? {
+        crate::sync::record_local_change_on(
+            conn,
+            Some(&item),
+            None,
+            time::get_current_timestamp(),
+        )?;
+        if let Some(item_uuid) = &item.uuid {
+            tags::erase_links_of_item_on(conn, item_uuid)?;
+        }
+        recurrence::skip_occurrence_on(conn, &item)?;
+    }
+    history::remove_item_history_on(conn, item_id)?;
+    diesel::delete(items.filter(id.eq(item_id)))
+        .execute(conn)
+        .map_err(|e| AppError::DatabaseDeleteError(e.to_string()))
+}
 
+fn get_item_on(conn: &mut SqliteConnection, item_id: i32) -> AppResult<Option<Item>> {
+    use crate::schema::items::dsl::*;
     items
         .filter(id.eq(item_id))
+        .select(Item::as_select())
         .first(conn)
-        .map_err(|e| e.to_string())
+        .optional()
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
 }
 
-pub fn read_items_between_days(
-    start_day: i32,
-    end_day: i32,
-    week_order: bool,
-    /* for the future: resolution_order: bool */
-) -> AppResult<Vec<Item>> {
+/// make the stored item exactly like the snapshot.
+/// `None` means the item should not exist.
+pub(crate) fn write_item_snapshot_on(
+    conn: &mut SqliteConnection,
+    item_id: i32,
+    snapshot: Option<Item>,
+) -> AppResult<()> {
     use crate::schema::items::dsl::*;
-    let conn = &mut establish_connection();
+    let exists = get_item_on(conn, item_id)?.is_some();
+    match snapshot {
+        Some(item) if exists => update_item_on(conn, &item).map(|_| ()),
+        Some(item) => {
+            diesel::insert_into(items)
+                .values(&item)
+                .execute(conn)
+                .map_err(|e| AppError::DatabaseInsertError(e.to_string()))?;
+            crate::sync::record_local_change_on(
+                conn,
+                None,
+                Some(&item),
+                time::get_current_timestamp(),
+            )
+        }
+        None if exists => delete_item_on(conn, item_id).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+/// run `f` in a transaction and record what it changes in the undo journal.
+/// `ids` are the existing items that `f` touches, and `f` returns the ids
+/// of the items it creates along with its result.
+fn journaled<T, F>(label: &str, ids: &[i32], f: F) -> AppResult<T>
+where
+    F: FnOnce(&mut SqliteConnection) -> AppResult<(T, Vec<i32>)>,
+{
+    journaled_with_records(label, ids, &[], f)
+}
+
+/// same as `journaled()`, and `records` are the other records `f` touches
+fn journaled_with_records<T, F>(
+    label: &str,
+    ids: &[i32],
+    records: &[(journal::Record, String)],
+    f: F,
+) -> AppResult<T>
+where
+    F: FnOnce(&mut SqliteConnection) -> AppResult<(T, Vec<i32>)>,
+{
+    with_connection(|conn| {
+        conn.immediate_transaction(|conn| {
+            let mut changes: Vec<journal::ItemChange> = Vec::new();
+            for item_id in ids {
+                changes.push((*item_id, get_item_on(conn, *item_id)?, None));
+            }
+            let mut record_changes: Vec<journal::RecordChange> = Vec::new();
+            for (record, key) in records {
+                if !record_changes
+                    .iter()
+                    .any(|change| change.0 == *record && change.1 == *key)
+                {
+                    let before = journal::read_record_on(conn, *record, key)?;
+                    record_changes.push((*record, key.clone(), before, None));
+                }
+            }
+            let (result, created_ids) = f(conn)?;
+            changes.extend(created_ids.into_iter().map(|item_id| (item_id, None, None)));
+            for change in changes.iter_mut() {
+                change.2 = get_item_on(conn, change.0)?;
+            }
+            changes.retain(|(_, before, after)| before != after);
+            for change in record_changes.iter_mut() {
+                change.3 = journal::read_record_on(conn, change.0, &change.1)?;
+            }
+            record_changes.retain(|(_, _, before, after)| before != after);
+            journal::record_on(conn, label, &changes, &record_changes)?;
+            Ok(result)
+        })
+    })
+}
+
+// the caller should run this in a transaction
+fn apply_batch_on(conn: &mut SqliteConnection, ops: &[BatchOp]) -> AppResult<BatchResult> {
+    let mut result = BatchResult::default();
+    for op in ops {
+        match op {
+            BatchOp::Insert(new_item) => {
+                result.inserted_ids.push(insert_item_on(conn, new_item)?);
+            }
+            BatchOp::Update(item) => {
+                if update_item_on(conn, item)? == 0 {
+                    return Err(AppError::DatabaseUpdateError(format!(
+                        "item not found. id: {}",
+                        item.id
+                    )));
+                }
+                result.updated += 1;
+            }
+            BatchOp::Delete(item_id) => {
+                if delete_item_on(conn, *item_id)? == 0 {
+                    return Err(AppError::DatabaseDeleteError(format!(
+                        "item not found. id: {item_id}"
+                    )));
+                }
+                result.deleted += 1;
+            }
+            BatchOp::Tag(link) => tags::write_item_tag_on(conn, link)?,
+            BatchOp::SaveTag(tag) => tags::write_tag_on(conn, tag)?,
+            BatchOp::SaveRecurrence(rule) => recurrence::write_recurrence_on(conn, rule)?,
+            BatchOp::SaveTemplate(template) => templates::write_template_on(conn, template)?,
+        }
+    }
+    Ok(result)
+}
+
+/// apply all the operations in a single transaction.
+/// either all of them are written, or on the first error everything is
+/// rolled back and that error is returned.
+pub fn apply_batch(ops: &[BatchOp]) -> AppResult<BatchResult> {
+    with_connection(|conn| conn.immediate_transaction(|conn| apply_batch_on(conn, ops)))
+}
+
+/// same as `apply_batch()`, and the whole batch can be undone as one action.
+pub fn apply_batch_journaled(label: &str, ops: &[BatchOp]) -> AppResult<BatchResult> {
+    let ids: Vec<i32> = ops
+        .iter()
+        .filter_map(|op| match op {
+            BatchOp::Update(item) => Some(item.id),
+            BatchOp::Delete(item_id) => Some(*item_id),
+            _ => None,
+        })
+        .collect();
+    let records: Vec<(journal::Record, String)> = ops.iter().filter_map(BatchOp::record).collect();
+    journaled_with_records(label, &ids, &records, |conn| {
+        let result = apply_batch_on(conn, ops)?;
+        let created_ids = result.inserted_ids.clone();
+        Ok((result, created_ids))
+    })
+}
+
+pub fn create_item(new_item: &NewItem) -> AppResult<i32> {
+    journaled("add item", &[], |conn| {
+        let new_id = insert_item_on(conn, new_item)?;
+        Ok((new_id, vec![new_id]))
+    })
+}
+
+/// move the item to the trash (soft delete).
+/// it can be restored with `restore_item()` until the trash is purged.
+pub fn remove_item(item_id: i32) -> Result<usize, String> {
+    trash_item(item_id).map_err(|err| err.to_string())
+}
 
-    if week_order {
+pub(crate) fn trash_item(item_id: i32) -> AppResult<usize> {
+    journaled("remove item", &[item_id], |conn| {
+        Ok((trash_item_on(conn, item_id)?, vec![]))
+    })
+}
+
+fn trash_item_on(conn: &mut SqliteConnection, item_id: i32) -> AppResult<usize> {
+    match get_item_on(conn, item_id)? {
+        Some(mut item) if item.deleted_at.is_none() => {
+            item.deleted_at = Some(time::get_current_timestamp());
+            update_item_on(conn, &item)
+        }
+        _ => Ok(0),
+    }
+}
+
+/// all the removed items, the most recently removed first
+pub fn list_trash() -> AppResult<Vec<Item>> {
+    use crate::schema::items::dsl::*;
+    with_connection(|conn| {
         items
-            .filter(day.ge(start_day)) // >=
-            .filter(day.le(end_day)) // <=
-            .order(order_in_week.asc())
+            .filter(deleted_at.is_not_null())
+            .order(deleted_at.desc())
             .select(Item::as_select())
             .load(conn)
             .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
-    } else {
+    })
+}
+
+/// take the item out of the trash and put it at the end of its week or
+/// objective period list.
+pub fn restore_item(item_id: i32) -> AppResult<()> {
+    use crate::schema::items::dsl::*;
+    // the week range depends on the config, so get it before locking the connection
+    let item = find_item(item_id)?;
+    let week_range = get_week_start_middle_end_unix_day(item.day);
+    journaled("restore item", &[item_id], |conn| {
+        let mut item: Item = items
+            .filter(id.eq(item_id))
+            .select(Item::as_select())
+            .first(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))?;
+        if item.deleted_at.is_none() {
+            return Ok(((), vec![]));
+        }
+        if let Some(_year) = item.year {
+            let keys: Vec<Option<String>> = items
+                .filter(deleted_at.is_null())
+                .filter(calendar.eq(item.calendar))
+                .filter(year.eq(Some(_year)))
+                .select(order_in_resolution)
+                .load(conn)
+                .map_err(|e| AppError::DatabaseSelectError(e.to_string()))?;
+            item.order_in_resolution = Some(new_last_ordering_key(keys));
+        } else {
+            let (start_day, _, end_day) = week_range;
+            let keys: Vec<Option<String>> = items
+                .filter(deleted_at.is_null())
+                .filter(day.ge(start_day))
+                .filter(day.le(end_day))
+                .select(order_in_week)
+                .load(conn)
+                .map_err(|e| AppError::DatabaseSelectError(e.to_string()))?;
+            item.order_in_week = Some(new_last_ordering_key(keys));
+        }
+        item.deleted_at = None;
+        update_item_on(conn, &item).map(|_| ((), vec![]))
+    })
+}
+
+// a key that sorts after all the provided keys
+fn new_last_ordering_key(keys: Vec<Option<String>>) -> String {
+    let last_key = keys.into_iter().flatten().max().unwrap_or_default();
+    midstring::mid_string(&last_key, "")
+}
+
+/// permanently delete the items that are in the trash for longer than `older_than`.
+/// returns the number of deleted items.
+pub fn purge_trash(older_than: chrono::Duration) -> AppResult<usize> {
+    use crate::schema::items::dsl::*;
+    let limit = time::get_current_timestamp() - older_than.num_seconds();
+    with_connection(|conn| {
+        conn.immediate_transaction(|conn| {
+            let ids: Vec<i32> = items
+                .filter(deleted_at.le(limit))
+                .select(id)
+                .load(conn)
+                .map_err(|e| AppError::DatabaseSelectError(e.to_string()))?;
+            let mut count = 0;
+            for item_id in ids {
+                count += delete_item_on(conn, item_id)?;
+            }
+            Ok(count)
+        })
+    })
+}
+
+/// save the items with their repaired ordering keys. it's not an undoable
+/// action, undoing it would only bring the broken keys back.
+pub fn update_items(items: &[Item]) -> AppResult<usize> {
+    let ops: Vec<BatchOp> = items.iter().cloned().map(BatchOp::Update).collect();
+    apply_batch(&ops).map(|result| result.updated)
+}
+
+pub fn update_item(item: &Item) -> Result<usize, String> {
+    update_item_journaled("update item", item).map_err(|err| err.to_string())
+}
+
+/// update the item, the change can be undone as an action with this label.
+pub(crate) fn update_item_journaled(label: &str, item: &Item) -> AppResult<usize> {
+    journaled(label, &[item.id], |conn| {
+        Ok((update_item_on(conn, item)?, vec![]))
+    })
+}
+
+pub fn get_item(item_id: i32) -> Result<Item, String> {
+    find_item(item_id).map_err(|e| e.to_string())
+}
+
+pub(crate) fn find_item(item_id: i32) -> AppResult<Item> {
+    use crate::schema::items::dsl::*;
+    with_connection(|conn| {
         items
-            .filter(day.ge(start_day)) // >=
-            .filter(day.le(end_day)) // <=
+            .filter(id.eq(item_id))
             .select(Item::as_select())
-            .load(conn)
+            .first(conn)
             .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
-    }
+    })
 }
 
-pub fn read_items_in_calendar_year(_calendar: i32, _year: i32) -> Result<Vec<Item>, String> {
+pub fn read_items_between_days(
+    start_day: i32,
+    end_day: i32,
+    week_order: bool,
+    /* for the future: resolution_order: bool */
+) -> AppResult<Vec<Item>> {
     use crate::schema::items::dsl::*;
-    let conn = &mut establish_connection();
+    with_connection(|conn| {
+        if week_order {
+            items
+                .filter(deleted_at.is_null())
+                .filter(day.ge(start_day)) // >=
+                .filter(day.le(end_day)) // <=
+                .order(order_in_week.asc())
+                .select(Item::as_select())
+                .load(conn)
+                .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+        } else {
+            items
+                .filter(deleted_at.is_null())
+                .filter(day.ge(start_day)) // >=
+                .filter(day.le(end_day)) // <=
+                .select(Item::as_select())
+                .load(conn)
+                .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+        }
+    })
+}
 
-    items
-        .filter(calendar.eq(_calendar))
-        .filter(year.eq(Some(_year)))
-        .order(order_in_resolution.asc())
-        .select(Item::as_select())
-        .load(conn)
-        .map_err(|e| e.to_string())
+pub fn read_items_in_calendar_year(_calendar: i32, _year: i32) -> Result<Vec<Item>, String> {
+    find_items_in_calendar_year(_calendar, _year).map_err(|e| e.to_string())
+}
+
+pub(crate) fn find_items_in_calendar_year(_calendar: i32, _year: i32) -> AppResult<Vec<Item>> {
+    use crate::schema::items::dsl::*;
+    with_connection(|conn| {
+        items
+            .filter(deleted_at.is_null())
+            .filter(calendar.eq(_calendar))
+            .filter(year.eq(Some(_year)))
+            .order(order_in_resolution.asc())
+            .select(Item::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+    })
 }
 
+/// make a backup of the database file now. see the `backup` module.
 pub fn backup_database_file() -> Result<(), String> {
-    let database_url = config::get_config().database;
-    // println!("database_url: {database_url}");
-    let mut timestamp = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false);
-    timestamp = timestamp.replace(':', "-");
-    let mut filename = String::from(&database_url);
-    filename.push('.');
-    filename.push_str(&timestamp);
-    filename.push_str(".backup");
-    // println!("filename: {filename}");
-    std::fs::copy(database_url, filename)
-        .map(|_| ())
-        .map_err(|e| e.to_string())
+    backup::backup_now().map(|_| ()).map_err(|e| e.to_string())
 }
 
 fn check_valid_id_range(id: i32) -> Result<(), String> {
@@ -165,28 +602,28 @@ fn check_valid_id_range(id: i32) -> Result<(), String> {
 }
 
 pub fn edit_item_text(id: i32, text: String) -> Result<usize, String> {
-    println!("edit_item_text: {}", id);
     check_valid_id_range(id)?;
-    let mut item = get_item(id)?;
-    if item.kind == ITEM_KIND_GOAL {
-        item.title = Some(text.clone());
-    }
-    if item.kind == ITEM_KIND_NOTE {
-        item.note = Some(text.clone());
-    }
-    update_item(&item)
+    SqliteStorage
+        .edit_item_text(id, text)
+        .map_err(|e| e.to_string())
 }
 
 pub fn toggle_item_state(id: i32) -> Result<usize, String> {
-    println!("toggle_item_state: id: {id}");
     check_valid_id_range(id)?;
-    let mut item = get_item(id)?;
-    if item.status == Some(STATUS_DONE) {
-        item.status = Some(STATUS_UNDONE)
-    } else {
-        item.status = Some(STATUS_DONE);
-    }
-    update_item(&item)
+    SqliteStorage
+        .toggle_item_state(id)
+        .map_err(|e| e.to_string())
+}
+
+pub fn set_item_status(
+    id: i32,
+    status: ItemStatus,
+    reason: Option<String>,
+) -> Result<usize, String> {
+    check_valid_id_range(id)?;
+    SqliteStorage
+        .set_item_status(id, status, reason)
+        .map_err(|e| e.to_string())
 }
 
 pub fn update_item_objective_period(
@@ -195,29 +632,24 @@ pub fn update_item_objective_period(
     season: Option<i32>,
     month: Option<i32>,
 ) -> Result<usize, String> {
-    println!("update_item_objective_period: id: {id}, {year:?}, {season:?}, {month:?}");
     check_valid_id_range(id)?;
-    let mut item = get_item(id)?;
-    item.year = year;
-    item.season = season;
-    item.month = month;
-    update_item(&item)
+    SqliteStorage
+        .update_item_objective_period(id, year, season, month)
+        .map_err(|e| e.to_string())
 }
 
 pub fn update_item_week_ordering_key(id: i32, key: String) -> Result<usize, String> {
-    println!("update_item_week_ordering_key: id: {id}");
     check_valid_id_range(id)?;
-    let mut item = get_item(id)?;
-    item.order_in_week = Some(key);
-    update_item(&item)
+    SqliteStorage
+        .update_item_week_ordering_key(id, key)
+        .map_err(|e| e.to_string())
 }
 
 pub fn update_item_year_ordering_key(id: i32, key: String) -> Result<usize, String> {
-    println!("update_item_year_ordering_key: id: {id}");
     check_valid_id_range(id)?;
-    let mut item = get_item(id)?;
-    item.order_in_resolution = Some(key);
-    update_item(&item)
+    SqliteStorage
+        .update_item_year_ordering_key(id, key)
+        .map_err(|e| e.to_string())
 }
 
 diesel::table! {
@@ -249,7 +681,8 @@ pub fn is_correct_db(filepath: &str) -> bool {
     if let Ok(mut conn) = SqliteConnection::establish(filepath) {
         // todo: get table content...
         let schema: Result<Vec<SqliteSchema>, diesel::result::Error> = sqlite_schema::table
-            .filter(sqlite_schema::tbl_name.eq("items"))
+            .filter(sqlite_schema::r#type.eq("table"))
+            .filter(sqlite_schema::name.eq("items"))
             .select(SqliteSchema::as_select())
             .load(&mut conn);
         // println!("{:#?}", schema);
@@ -263,13 +696,183 @@ pub fn is_correct_db(filepath: &str) -> bool {
     }
 }
 
-pub fn create_db(_filepath: &str) -> AppResult<()> {
-    // it seems there is no need to!
-    // the migration, take care of creating initial database!
-    todo!();
+/// create a new empty database at `filepath` with all the migrations applied.
+/// the file should not exist already.
+pub fn create_db(filepath: &str) -> AppResult<()> {
+    let path = Path::new(filepath);
+    if path.exists() {
+        return Err(AppError::DatabaseFileNotEmptyError);
+    }
 
     // ensure target directory exists
-    // if let Some(parent) = Path::new(&filepath).parent() {
-    //     fs::create_dir_all(parent).map_err(|_| AppError::DatabaseFileCopyError)?;
-    // }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|_| AppError::DatabaseFileCreateError)?;
+    }
+
+    // opening the connection creates the file
+    let result = open_connection(filepath)
+        .map_err(|_| AppError::DatabaseFileCreateError)
+        .and_then(|mut conn| {
+            conn.run_pending_migrations(MIGRATIONS)
+                .map(|_| ())
+                .map_err(|e| AppError::DatabaseMigrationError(e.to_string()))
+        });
+
+    let result = result.and_then(|_| {
+        if is_correct_db(filepath) {
+            Ok(())
+        } else {
+            Err(AppError::DatabaseFileInvalidError)
+        }
+    });
+
+    if result.is_err() {
+        // don't leave a half-made database behind
+        let _ = fs::remove_file(filepath);
+    }
+    result
+}
+
+/// a new database in a temporary directory, the configured database while it
+/// lives. for the tests.
+#[cfg(test)]
+pub(crate) struct TestDatabase {
+    pub path: String,
+    pub dir: std::path::PathBuf,
+    _config: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl TestDatabase {
+    pub fn new() -> TestDatabase {
+        let dir = std::env::temp_dir().join(format!("thisweek-test-{}", cuid2::create_id()));
+        let path = dir.join("thisweek.db").to_string_lossy().into_owned();
+        let guard = config::set_test_config(config::Config {
+            database: path.clone(),
+            backup_schedule: "off".into(),
+            ..config::Config::default()
+        });
+        close_connection();
+        create_db(&path).unwrap();
+        TestDatabase {
+            path,
+            dir,
+            _config: guard,
+        }
+    }
+
+    /// a path in the directory of the database
+    pub fn file(&self, name: &str) -> String {
+        self.dir.join(name).to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        close_connection();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// a goal of the day, with the week ordering key. for the tests.
+#[cfg(test)]
+pub(crate) fn new_test_goal(day: i32, text: &str, key: &str) -> NewItem {
+    NewItem::new(
+        crate::calendar::CALENDAR_GREGORIAN,
+        None,
+        None,
+        None,
+        day,
+        crate::models::ItemKind::Goal,
+        text.into(),
+        key.into(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i32 = 20000;
+
+    #[test]
+    fn test_create_db() {
+        let db = TestDatabase::new();
+        assert!(is_correct_db(&db.path));
+        assert!(matches!(
+            create_db(&db.path),
+            Err(AppError::DatabaseFileNotEmptyError)
+        ));
+        let text = db.file("text.db");
+        fs::write(&text, "not a database").unwrap();
+        assert!(!is_correct_db(&text));
+        assert!(!is_correct_db(&db.file("missing.db")));
+    }
+
+    #[test]
+    fn test_batch_rolls_back_on_error() {
+        let _db = TestDatabase::new();
+        let id = create_item(&new_test_goal(DAY, "kept", "m")).unwrap();
+        let mut missing = find_item(id).unwrap();
+        missing.id = id + 100;
+        let ops = vec![
+            BatchOp::Insert(new_test_goal(DAY, "rolled back", "t")),
+            BatchOp::Update(missing),
+        ];
+        assert!(apply_batch(&ops).is_err());
+        assert!(apply_batch_journaled("batch", &ops).is_err());
+        let items = read_items_between_days(DAY, DAY, true).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].title.as_deref(), Some("kept"));
+    }
+
+    #[test]
+    fn test_repaired_keys_are_not_undone() {
+        let _db = TestDatabase::new();
+        let first = create_item(&new_test_goal(DAY, "first", "m")).unwrap();
+        create_item(&new_test_goal(DAY, "second", "t")).unwrap();
+        let mut items = read_items_between_days(DAY, DAY, true).unwrap();
+        items[0].order_in_week = Some("w".into());
+        assert_eq!(update_items(&items).unwrap(), 2);
+        assert_eq!(
+            find_item(first).unwrap().order_in_week.as_deref(),
+            Some("w")
+        );
+
+        // the undo skips the repair, to the last action
+        assert_eq!(journal::undo().unwrap().as_deref(), Some("add item"));
+        assert_eq!(
+            find_item(first).unwrap().order_in_week.as_deref(),
+            Some("w")
+        );
+    }
+
+    #[test]
+    fn test_trash_restore_and_purge() {
+        let _db = TestDatabase::new();
+        let first = create_item(&new_test_goal(DAY, "first", "m")).unwrap();
+        let second = create_item(&new_test_goal(DAY, "second", "t")).unwrap();
+        assert_eq!(trash_item(first).unwrap(), 1);
+        assert_eq!(read_items_between_days(DAY, DAY, true).unwrap().len(), 1);
+        assert_eq!(list_trash().unwrap()[0].id, first);
+
+        // restored at the end of its week
+        restore_item(first).unwrap();
+        let items = read_items_between_days(DAY, DAY, true).unwrap();
+        assert_eq!(
+            items.iter().map(|item| item.id).collect::<Vec<i32>>(),
+            vec![second, first]
+        );
+
+        tags::tag_item(&SqliteStorage, second, "home").unwrap();
+        let second_uuid = find_item(second).unwrap().uuid.unwrap();
+        trash_item(second).unwrap();
+        assert_eq!(purge_trash(chrono::Duration::days(1)).unwrap(), 0);
+        assert_eq!(purge_trash(chrono::Duration::zero()).unwrap(), 1);
+        assert!(find_item(second).is_err());
+        assert!(list_trash().unwrap().is_empty());
+        // with its tags
+        assert!(tags::find_item_tags(&[second_uuid]).unwrap().is_empty());
+    }
 }
@@ -17,6 +17,23 @@ pub enum Error {
     #[error("provided days range is very long: {} days", self)]
     LongDaysRangeError(i32),
 
+    #[error("invalid {0} value: {1}")]
+    InvalidValueError(&'static str, i32),
+    #[error("invalid status change: {0}")]
+    InvalidStatusChangeError(String),
+    #[error("invalid event: {0}")]
+    InvalidEventError(String),
+    #[error("invalid recurrence: {0}")]
+    InvalidRecurrenceError(String),
+    #[error("invalid subitem: {0}")]
+    InvalidSubitemError(String),
+    #[error("invalid tag: {0}")]
+    InvalidTagError(String),
+    #[error("invalid objective link: {0}")]
+    InvalidObjectiveLinkError(String),
+    #[error("invalid template: {0}")]
+    InvalidTemplateError(String),
+
     #[error("invalid timestamp: sec: {sec}, nano: {nano}")]
     InvalidTimestampError { sec: i64, nano: u32 },
 
@@ -24,6 +41,16 @@ pub enum Error {
     DatabaseInsertError(String),
     #[error("database error: {0}")]
     DatabaseSelectError(String),
+    #[error("database error: {0}")]
+    DatabaseUpdateError(String),
+    #[error("database error: {0}")]
+    DatabaseDeleteError(String),
+    #[error("database transaction error: {0}")]
+    DatabaseTransactionError(#[from] diesel::result::Error),
+    #[error("database connection error: {0}")]
+    DatabaseConnectionError(String),
+    #[error("database migration error: {0}")]
+    DatabaseMigrationError(String),
     #[error("can not copy database file")]
     DatabaseFileCopyError,
     #[error("can not create database file")]
@@ -32,8 +59,22 @@ pub enum Error {
     DatabaseFileRemoveError,
     #[error("the data file is not valid")]
     DatabaseFileInvalidError,
+    #[error("the data file has problems and should be repaired")]
+    DatabaseFileNeedsRepairError,
     #[error("the data file is not available")]
     DatabaseFileDontExistsError,
     #[error("the file location should be empty")]
     DatabaseFileNotEmptyError,
+    #[error("backup error: {0}")]
+    DatabaseBackupError(String),
+    #[error("merge error: {0}")]
+    DatabaseMergeError(String),
+    #[error("sync error: {0}")]
+    SyncError(String),
+    #[error("folder storage error: {0}")]
+    FolderStorageError(String),
+    #[error("legacy database import error: {0}")]
+    LegacyImportError(String),
+    #[error("profile error: {0}")]
+    ProfileError(String),
 }
@@ -0,0 +1,182 @@
+/* Events */
+
+// events are the items of the `ItemKind::Event` kind. each one is on a day:
+//   - `day` is the unix day of the event, and `fixed_date` is set
+//   - an `all_day` event has no time
+//   - the others start at `datetime` and last `duration` minutes.
+//     `datetime` is the local date and time, like "2026-10-18T09:30"
+// the week shows them on their days, sorted by time, not by ordering keys.
+
+use crate::calendar::Calendar;
+use crate::config;
+use crate::models::{Item, ItemKind, ItemStatus, NewItem};
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::storage::Storage;
+use chrono::{Days, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+
+const DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M";
+const TIME_FORMAT: &str = "%H:%M";
+
+/// when an event happens
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EventSchedule {
+    pub day: i32,
+    /// the local start time, like "09:30". `None` for an all day event
+    pub start_time: Option<String>,
+    /// in minutes
+    pub duration: Option<i32>,
+}
+
+fn event_error<E: ToString>(e: E) -> AppError {
+    AppError::InvalidEventError(e.to_string())
+}
+
+fn date_of_unix_day(day: i32) -> AppResult<NaiveDate> {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap_or_default();
+    let date = if day >= 0 {
+        epoch.checked_add_days(Days::new(day as u64))
+    } else {
+        epoch.checked_sub_days(Days::new(day.unsigned_abs() as u64))
+    };
+    date.ok_or(event_error(format!("invalid day: {day}")))
+}
+
+impl EventSchedule {
+    pub fn all_day(day: i32) -> Self {
+        EventSchedule {
+            day,
+            start_time: None,
+            duration: None,
+        }
+    }
+
+    pub fn at(day: i32, start_time: &str, duration: Option<i32>) -> Self {
+        EventSchedule {
+            day,
+            start_time: Some(start_time.to_string()),
+            duration,
+        }
+    }
+
+    /// the schedule of an event item
+    pub fn of(item: &Item) -> Option<EventSchedule> {
+        if item.kind != ItemKind::Event {
+            return None;
+        }
+        let start_time = if item.all_day {
+            None
+        } else {
+            item.datetime
+                .as_deref()
+                .and_then(|text| NaiveDateTime::parse_from_str(text, DATETIME_FORMAT).ok())
+                .map(|datetime| datetime.format(TIME_FORMAT).to_string())
+        };
+        Some(EventSchedule {
+            day: item.day,
+            start_time,
+            duration: item.duration,
+        })
+    }
+
+    pub fn is_all_day(&self) -> bool {
+        self.start_time.is_none()
+    }
+
+    // the `datetime` column value
+    fn datetime(&self) -> AppResult<Option<String>> {
+        if let Some(duration) = self.duration {
+            if duration < 0 {
+                return Err(event_error("the duration can not be negative"));
+            }
+        }
+        let Some(start_time) = &self.start_time else {
+            return Ok(None);
+        };
+        let time = NaiveTime::parse_from_str(start_time.trim(), TIME_FORMAT)
+            .map_err(|_| event_error(format!("invalid start time: {start_time}")))?;
+        let datetime = date_of_unix_day(self.day)?.and_time(time);
+        Ok(Some(datetime.format(DATETIME_FORMAT).to_string()))
+    }
+
+    fn apply_to(&self, item: &mut Item) -> AppResult<()> {
+        item.datetime = self.datetime()?;
+        item.day = self.day;
+        item.all_day = self.is_all_day();
+        item.duration = if self.is_all_day() {
+            None
+        } else {
+            self.duration
+        };
+        item.fixed_date = true;
+        Ok(())
+    }
+}
+
+/// a new event, in the main calendar
+pub fn new_event(title: String, schedule: &EventSchedule) -> AppResult<NewItem> {
+    let title = title.trim().to_string();
+    if title.is_empty() {
+        return Err(event_error("the event has no title"));
+    }
+    let main_cal: Calendar = config::get_config().main_calendar_type.into();
+    let mut new_item = NewItem::new(
+        main_cal.into(),
+        None,
+        None,
+        None,
+        schedule.day,
+        ItemKind::Event,
+        title,
+        String::new(),
+    );
+    // events are sorted by their time
+    new_item.order_in_week = None;
+    new_item.datetime = schedule.datetime()?;
+    new_item.all_day = schedule.is_all_day();
+    new_item.duration = if schedule.is_all_day() {
+        None
+    } else {
+        schedule.duration
+    };
+    new_item.fixed_date = true;
+    new_item.status = Some(ItemStatus::Undone);
+    Ok(new_item)
+}
+
+/// change the time of the event, as one undoable action
+pub fn reschedule_event(
+    storage: &dyn Storage,
+    id: i32,
+    schedule: &EventSchedule,
+) -> AppResult<usize> {
+    let mut item = storage.get_item(id)?;
+    if item.kind != ItemKind::Event {
+        return Err(event_error(format!("item {id} is not an event")));
+    }
+    schedule.apply_to(&mut item)?;
+    storage.update_item_labeled("reschedule event", &item)
+}
+
+/// the events of the days range (inclusive), sorted by their time
+pub fn read_events_between_days(
+    storage: &dyn Storage,
+    start_day: i32,
+    end_day: i32,
+) -> AppResult<Vec<Item>> {
+    let mut events: Vec<Item> = storage
+        .read_items_between_days(start_day, end_day)?
+        .into_iter()
+        .filter(|item| item.kind == ItemKind::Event)
+        .collect();
+    sort_events(&mut events);
+    Ok(events)
+}
+
+/// by day, the all day events first, then by start time
+pub fn sort_events(events: &mut [Item]) {
+    events.sort_by(|a, b| {
+        (a.day, !a.all_day, &a.datetime, a.id).cmp(&(b.day, !b.all_day, &b.datetime, b.id))
+    });
+}
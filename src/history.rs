@@ -0,0 +1,219 @@
+/* Item History */
+
+// every update of an item records the changed fields (old and new values)
+// in the `item_history` table. the ordering keys and bookkeeping columns
+// are not recorded.
+
+use crate::db_sqlite;
+use crate::models::{Item, ItemKind};
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::schema::item_history;
+use diesel::prelude::*;
+use serde::Serialize;
+
+#[derive(Queryable, Selectable, Debug, Serialize, Clone)]
+#[diesel(table_name = crate::schema::item_history)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ItemHistory {
+    pub id: i32,
+    pub item_id: i32,
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed_at: i64,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::item_history)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct NewItemHistory {
+    item_id: i32,
+    field: String,
+    old_value: Option<String>,
+    new_value: Option<String>,
+    changed_at: i64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ItemTimeline {
+    pub item_id: i32,
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
+    pub completed_at: Option<i64>,
+    pub deleted_at: Option<i64>,
+    // oldest change first
+    pub changes: Vec<ItemHistory>,
+}
+
+trait HistoryValue {
+    fn history_value(&self) -> Option<String>;
+}
+
+impl HistoryValue for i32 {
+    fn history_value(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+impl HistoryValue for ItemKind {
+    fn history_value(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+impl HistoryValue for bool {
+    fn history_value(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+impl<T: ToString> HistoryValue for Option<T> {
+    fn history_value(&self) -> Option<String> {
+        self.as_ref().map(|v| v.to_string())
+    }
+}
+
+macro_rules! changed_fields {
+    ($before:expr, $after:expr, $($field:ident),+ $(,)?) => {{
+        let mut changes: Vec<(&'static str, Option<String>, Option<String>)> = Vec::new();
+        $(
+            if $before.$field != $after.$field {
+                changes.push((
+                    stringify!($field),
+                    $before.$field.history_value(),
+                    $after.$field.history_value(),
+                ));
+            }
+        )+
+        changes
+    }};
+}
+
+/// the recorded fields that are different between the two versions of an item
+pub fn item_changes(
+    before: &Item,
+    after: &Item,
+) -> Vec<(&'static str, Option<String>, Option<String>)> {
+    changed_fields!(
+        before,
+        after,
+        calendar,
+        year,
+        season,
+        month,
+        day,
+        kind,
+        fixed_date,
+        all_day,
+        title,
+        note,
+        datetime,
+        duration,
+        status,
+        status_reason,
+        parent_uuid,
+        objective_uuid,
+        carry_count,
+        carried_from,
+        carried_to,
+        recurrence_uuid,
+        occurrence_key,
+        deleted_at,
+    )
+}
+
+/// record the changes of an item update.
+/// this should run in the same transaction as the update itself.
+pub(crate) fn record_changes_on(
+    conn: &mut SqliteConnection,
+    before: &Item,
+    after: &Item,
+    changed_at: i64,
+) -> AppResult<()> {
+    let entries: Vec<NewItemHistory> = item_changes(before, after)
+        .into_iter()
+        .map(|(field, old_value, new_value)| NewItemHistory {
+            item_id: after.id,
+            field: field.to_string(),
+            old_value,
+            new_value,
+            changed_at,
+        })
+        .collect();
+    if entries.is_empty() {
+        return Ok(());
+    }
+    diesel::insert_into(item_history::table)
+        .values(&entries)
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| AppError::DatabaseInsertError(e.to_string()))
+}
+
+pub(crate) fn remove_item_history_on(conn: &mut SqliteConnection, item_id: i32) -> AppResult<()> {
+    diesel::delete(item_history::table.filter(item_history::item_id.eq(item_id)))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| AppError::DatabaseDeleteError(e.to_string()))
+}
+
+/// when the item is created, completed and changed
+pub fn get_item_timeline(item_id: i32) -> AppResult<ItemTimeline> {
+    let item = db_sqlite::find_item(item_id)?;
+    let changes = db_sqlite::with_connection(|conn| {
+        item_history::table
+            .filter(item_history::item_id.eq(item_id))
+            .order((item_history::changed_at.asc(), item_history::id.asc()))
+            .select(ItemHistory::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+    })?;
+    Ok(ItemTimeline {
+        item_id,
+        created_at: item.created_at,
+        updated_at: item.updated_at,
+        completed_at: item.completed_at,
+        deleted_at: item.deleted_at,
+        changes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_sqlite::{new_test_goal, TestDatabase};
+    use crate::storage::{SqliteStorage, Storage};
+
+    #[test]
+    fn test_item_timeline() {
+        let _db = TestDatabase::new();
+        let id = db_sqlite::create_item(&new_test_goal(20000, "draft", "m")).unwrap();
+        SqliteStorage.edit_item_text(id, "final".into()).unwrap();
+        SqliteStorage.toggle_item_state(id).unwrap();
+        // only the ordering key, not recorded
+        SqliteStorage
+            .update_item_week_ordering_key(id, "t".into())
+            .unwrap();
+
+        let timeline = get_item_timeline(id).unwrap();
+        assert!(timeline.created_at.is_some());
+        assert!(timeline.completed_at.is_some());
+        let fields: Vec<&str> = timeline.changes.iter().map(|c| c.field.as_str()).collect();
+        assert_eq!(fields, vec!["title", "status"]);
+        assert_eq!(timeline.changes[0].old_value.as_deref(), Some("draft"));
+        assert_eq!(timeline.changes[0].new_value.as_deref(), Some("final"));
+
+        // the history goes with the item
+        db_sqlite::trash_item(id).unwrap();
+        db_sqlite::purge_trash(chrono::Duration::zero()).unwrap();
+        let count: i64 = db_sqlite::with_connection(|conn| {
+            item_history::table
+                .count()
+                .get_result(conn)
+                .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+        })
+        .unwrap();
+        assert_eq!(count, 0);
+    }
+}
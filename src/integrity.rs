@@ -0,0 +1,476 @@
+/* Database Integrity */
+
+// a deep check of a database file, beyond `db_sqlite::is_correct_db()`:
+//   - SQLite's own integrity check
+//   - the applied migrations compared to the ones this version knows
+//   - rows that would break the app: unknown calendar, kind or status values,
+//     missing or duplicated uuids, and broken ordering keys
+// all the rows are checked, the trashed ones too, except the ordering keys
+// that only matter in the lists.
+// the row problems can be fixed with `repair_database()`.
+
+use crate::calendar::{CALENDAR_ARABIC, CALENDAR_CHINESE, CALENDAR_GREGORIAN, CALENDAR_PERSIAN};
+use crate::db_sqlite;
+use crate::models::{ItemStatus, ITEM_KIND_EVENT, ITEM_KIND_GOAL, ITEM_KIND_NOTE, STATUS_UNDONE};
+use crate::ordering;
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use diesel::migration::MigrationSource;
+use diesel::prelude::*;
+use diesel::sql_types::{Bool, Integer, Nullable, Text};
+use diesel::sqlite::Sqlite;
+use diesel_migrations::MigrationHarness;
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct IntegrityReport {
+    pub path: String,
+    // messages of `PRAGMA integrity_check`. empty when ok.
+    pub integrity_errors: Vec<String>,
+    // known by this version, but not applied to the file
+    pub pending_migrations: Vec<String>,
+    // applied to the file, but not known by this version (made by a newer version?)
+    pub unknown_migrations: Vec<String>,
+    // the ids of the items with problems
+    pub invalid_calendar_items: Vec<i32>,
+    pub invalid_kind_items: Vec<i32>,
+    pub invalid_status_items: Vec<i32>,
+    pub missing_uuid_items: Vec<i32>,
+    pub duplicate_uuid_items: Vec<i32>,
+    pub broken_ordering_items: Vec<i32>,
+    // true if this report is the result of a repair
+    pub repaired: bool,
+}
+
+impl IntegrityReport {
+    /// no problem at all (pending migrations are not a problem)
+    pub fn is_healthy(&self) -> bool {
+        self.integrity_errors.is_empty()
+            && self.unknown_migrations.is_empty()
+            && !self.has_row_problems()
+    }
+
+    /// the app can open this file without crashing
+    pub fn is_safe_to_open(&self) -> bool {
+        self.integrity_errors.is_empty()
+            && self.invalid_calendar_items.is_empty()
+            && self.invalid_kind_items.is_empty()
+            && self.invalid_status_items.is_empty()
+    }
+
+    pub fn has_row_problems(&self) -> bool {
+        !(self.invalid_calendar_items.is_empty()
+            && self.invalid_kind_items.is_empty()
+            && self.invalid_status_items.is_empty()
+            && self.missing_uuid_items.is_empty()
+            && self.duplicate_uuid_items.is_empty()
+            && self.broken_ordering_items.is_empty())
+    }
+}
+
+#[derive(QueryableByName, Debug)]
+struct IntegrityCheckRow {
+    #[diesel(sql_type = Text)]
+    integrity_check: String,
+}
+
+#[derive(QueryableByName, Debug)]
+struct VersionRow {
+    #[diesel(sql_type = Text)]
+    version: String,
+}
+
+#[derive(QueryableByName, Debug)]
+struct NameRow {
+    #[diesel(sql_type = Text)]
+    name: String,
+}
+
+// only the columns of the first migration, so older files can be checked too
+#[derive(QueryableByName, Debug)]
+struct ItemRow {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+    #[diesel(sql_type = Integer)]
+    calendar: i32,
+    #[diesel(sql_type = Nullable<Integer>)]
+    year: Option<i32>,
+    #[diesel(sql_type = Integer)]
+    day: i32,
+    #[diesel(sql_type = Integer)]
+    kind: i32,
+    #[diesel(sql_type = Nullable<Integer>)]
+    status: Option<i32>,
+    #[diesel(sql_type = Nullable<Text>)]
+    order_in_week: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    order_in_resolution: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    uuid: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    parent_uuid: Option<String>,
+    #[diesel(sql_type = Bool)]
+    deleted: bool,
+}
+
+fn select_error(e: diesel::result::Error) -> AppError {
+    AppError::DatabaseSelectError(e.to_string())
+}
+
+fn update_error(e: diesel::result::Error) -> AppError {
+    AppError::DatabaseUpdateError(e.to_string())
+}
+
+fn is_valid_calendar(calendar: i32) -> bool {
+    matches!(
+        calendar,
+        CALENDAR_GREGORIAN | CALENDAR_PERSIAN | CALENDAR_CHINESE | CALENDAR_ARABIC
+    )
+}
+
+fn is_valid_kind(kind: i32) -> bool {
+    matches!(kind, ITEM_KIND_GOAL | ITEM_KIND_NOTE | ITEM_KIND_EVENT)
+}
+
+fn is_valid_status(status: Option<i32>) -> bool {
+    status.map_or(true, |status| ItemStatus::try_from(status).is_ok())
+}
+
+// the keys are made by midstring, which only uses [a-z]
+fn is_valid_ordering_key(key: &Option<String>) -> bool {
+    match key {
+        Some(key) => !key.is_empty() && key.bytes().all(|b| b.is_ascii_lowercase()),
+        None => false,
+    }
+}
+
+fn check_integrity(conn: &mut SqliteConnection, report: &mut IntegrityReport) -> AppResult<()> {
+    let rows: Vec<IntegrityCheckRow> = diesel::sql_query("PRAGMA integrity_check;")
+        .load(conn)
+        .map_err(select_error)?;
+    report.integrity_errors = rows
+        .into_iter()
+        .map(|row| row.integrity_check)
+        .filter(|message| message != "ok")
+        .collect();
+    Ok(())
+}
+
+fn table_names(conn: &mut SqliteConnection) -> AppResult<Vec<String>> {
+    let rows: Vec<NameRow> =
+        diesel::sql_query("SELECT name FROM sqlite_schema WHERE type = 'table';")
+            .load(conn)
+            .map_err(select_error)?;
+    Ok(rows.into_iter().map(|row| row.name).collect())
+}
+
+fn check_migrations(conn: &mut SqliteConnection, report: &mut IntegrityReport) -> AppResult<()> {
+    let known: Vec<String> = MigrationSource::<Sqlite>::migrations(&db_sqlite::MIGRATIONS)
+        .map_err(|e| AppError::DatabaseMigrationError(e.to_string()))?
+        .iter()
+        .map(|m| m.name().version().to_string())
+        .collect();
+    let applied: Vec<String> = if table_names(conn)?.contains(&"__diesel_schema_migrations".into())
+    {
+        let rows: Vec<VersionRow> =
+            diesel::sql_query("SELECT version FROM __diesel_schema_migrations;")
+                .load(conn)
+                .map_err(select_error)?;
+        rows.into_iter().map(|row| row.version).collect()
+    } else {
+        vec![]
+    };
+    report.pending_migrations = known
+        .iter()
+        .filter(|v| !applied.contains(v))
+        .cloned()
+        .collect();
+    report.unknown_migrations = applied
+        .iter()
+        .filter(|v| !known.contains(v))
+        .cloned()
+        .collect();
+    Ok(())
+}
+
+fn read_item_rows(conn: &mut SqliteConnection) -> AppResult<Vec<ItemRow>> {
+    let columns: Vec<NameRow> = diesel::sql_query("SELECT name FROM pragma_table_info('items');")
+        .load(conn)
+        .map_err(select_error)?;
+    let has_column = |name: &str| columns.iter().any(|c| c.name == name);
+    let parent = if has_column("parent_uuid") {
+        "parent_uuid"
+    } else {
+        "NULL AS parent_uuid"
+    };
+    let deleted = if has_column("deleted_at") {
+        "deleted_at IS NOT NULL AS deleted"
+    } else {
+        "0 AS deleted"
+    };
+    let query = format!(
+        "SELECT id, calendar, year, day, kind, status, order_in_week, order_in_resolution, \
+         uuid, {parent}, {deleted} FROM items ORDER BY id;"
+    );
+    diesel::sql_query(query).load(conn).map_err(select_error)
+}
+
+// a list of ordering keys, and the parent of its subitems
+type ListKey<'a> = ((bool, i32, i32), Option<&'a str>);
+
+// the ordering keys of each list: weekly items by week, objectives by calendar
+// year, and the subitems of each parent in their own list
+fn ordering_lists(rows: &[ItemRow]) -> Vec<(bool, Vec<&ItemRow>)> {
+    let mut lists: HashMap<ListKey, Vec<&ItemRow>> = HashMap::new();
+    for row in rows.iter().filter(|row| !row.deleted) {
+        // the events of a week are sorted by their time, they have no keys
+        if row.kind == ITEM_KIND_EVENT && row.year.is_none() {
+            continue;
+        }
+        let list = ordering::ordering_list_of(row.calendar, row.year, row.day);
+        lists
+            .entry((list, row.parent_uuid.as_deref()))
+            .or_default()
+            .push(row);
+    }
+    lists
+        .into_iter()
+        .map(|(((is_objective, _, _), _), list)| (is_objective, list))
+        .collect()
+}
+
+fn ordering_key(row: &ItemRow, is_objective: bool) -> &Option<String> {
+    if is_objective {
+        &row.order_in_resolution
+    } else {
+        &row.order_in_week
+    }
+}
+
+// the items of the list with invalid or duplicated keys
+fn broken_ordering(list: &[&ItemRow], is_objective: bool) -> Vec<i32> {
+    let mut keys: HashMap<&str, i32> = HashMap::new();
+    let mut broken = Vec::new();
+    for row in list {
+        let key = ordering_key(row, is_objective);
+        let valid = is_valid_ordering_key(key)
+            && keys
+                .insert(key.as_deref().unwrap_or_default(), row.id)
+                .is_none();
+        if !valid {
+            broken.push(row.id);
+        }
+    }
+    broken
+}
+
+fn check_items(conn: &mut SqliteConnection, report: &mut IntegrityReport) -> AppResult<()> {
+    let rows = read_item_rows(conn)?;
+
+    let mut uuids: HashMap<&str, i32> = HashMap::new();
+    for row in rows.iter() {
+        if !is_valid_calendar(row.calendar) {
+            report.invalid_calendar_items.push(row.id);
+        }
+        if !is_valid_kind(row.kind) {
+            report.invalid_kind_items.push(row.id);
+        }
+        if !is_valid_status(row.status) {
+            report.invalid_status_items.push(row.id);
+        }
+        match row.uuid.as_deref() {
+            None | Some("") => report.missing_uuid_items.push(row.id),
+            Some(uuid) => {
+                if uuids.insert(uuid, row.id).is_some() {
+                    report.duplicate_uuid_items.push(row.id);
+                }
+            }
+        }
+    }
+
+    for (is_objective, list) in ordering_lists(&rows) {
+        report
+            .broken_ordering_items
+            .extend(broken_ordering(&list, is_objective));
+    }
+    report.broken_ordering_items.sort();
+    Ok(())
+}
+
+// give new keys to all the items of the lists with broken keys.
+// the valid keys keep their order, the broken ones go to the end.
+fn rebuild_ordering(conn: &mut SqliteConnection) -> AppResult<()> {
+    let rows = read_item_rows(conn)?;
+    for (is_objective, mut list) in ordering_lists(&rows) {
+        let broken = broken_ordering(&list, is_objective);
+        if broken.is_empty() {
+            continue;
+        }
+        list.sort_by_key(|row| {
+            let key = ordering_key(row, is_objective);
+            (broken.contains(&row.id), key.clone(), row.id)
+        });
+        let column = if is_objective {
+            "order_in_resolution"
+        } else {
+            "order_in_week"
+        };
+        let keys = ordering::new_ordering_keys(list.len());
+        for (row, key) in list.into_iter().zip(keys) {
+            diesel::sql_query(format!("UPDATE items SET {column} = ? WHERE id = ?;"))
+                .bind::<Text, _>(key)
+                .bind::<Integer, _>(row.id)
+                .execute(conn)
+                .map_err(update_error)?;
+        }
+    }
+    Ok(())
+}
+
+fn open(path: &str) -> AppResult<SqliteConnection> {
+    if !db_sqlite::is_correct_db(path) {
+        return Err(AppError::DatabaseFileInvalidError);
+    }
+    SqliteConnection::establish(path).map_err(|e| AppError::DatabaseConnectionError(e.to_string()))
+}
+
+fn check_on(conn: &mut SqliteConnection, path: &str) -> AppResult<IntegrityReport> {
+    let mut report = IntegrityReport {
+        path: path.to_string(),
+        ..Default::default()
+    };
+    check_integrity(conn, &mut report)?;
+    check_migrations(conn, &mut report)?;
+    check_items(conn, &mut report)?;
+    Ok(report)
+}
+
+/// check the database file. nothing is changed.
+pub fn check_database(path: &str) -> AppResult<IntegrityReport> {
+    let mut conn = open(path)?;
+    check_on(&mut conn, path)
+}
+
+/// fix what can be fixed in the database file:
+///   - apply the pending migrations (not if the file is from a newer version)
+///   - unknown calendars become Gregorian, unknown kinds become notes,
+///     unknown statuses become undone
+///   - missing and duplicated uuids are replaced with new ones
+///   - the lists with broken ordering keys get new keys
+///
+/// returns the report of the problems found before the repair.
+pub fn repair_database(path: &str) -> AppResult<IntegrityReport> {
+    let mut conn = open(path)?;
+    let mut report = check_on(&mut conn, path)?;
+
+    if !report.integrity_errors.is_empty() {
+        // rebuilding the indexes fixes the most common corruptions
+        diesel::sql_query("REINDEX;")
+            .execute(&mut conn)
+            .map_err(update_error)?;
+    }
+
+    conn.immediate_transaction(|conn| {
+        for id in report.invalid_calendar_items.iter() {
+            diesel::sql_query("UPDATE items SET calendar = ? WHERE id = ?;")
+                .bind::<Integer, _>(CALENDAR_GREGORIAN)
+                .bind::<Integer, _>(id)
+                .execute(conn)
+                .map_err(update_error)?;
+        }
+        for id in report.invalid_kind_items.iter() {
+            diesel::sql_query(
+                "UPDATE items SET kind = ?, note = COALESCE(note, title) WHERE id = ?;",
+            )
+            .bind::<Integer, _>(ITEM_KIND_NOTE)
+            .bind::<Integer, _>(id)
+            .execute(conn)
+            .map_err(update_error)?;
+        }
+        for id in report.invalid_status_items.iter() {
+            diesel::sql_query("UPDATE items SET status = ? WHERE id = ?;")
+                .bind::<Integer, _>(STATUS_UNDONE)
+                .bind::<Integer, _>(id)
+                .execute(conn)
+                .map_err(update_error)?;
+        }
+        for id in report
+            .missing_uuid_items
+            .iter()
+            .chain(report.duplicate_uuid_items.iter())
+        {
+            diesel::sql_query("UPDATE items SET uuid = ? WHERE id = ?;")
+                .bind::<Text, _>(cuid2::create_id())
+                .bind::<Integer, _>(id)
+                .execute(conn)
+                .map_err(update_error)?;
+        }
+        if !report.broken_ordering_items.is_empty() {
+            rebuild_ordering(conn)?;
+        }
+        Ok::<(), AppError>(())
+    })?;
+
+    // the row fixes are kept even if a migration fails
+    if report.unknown_migrations.is_empty() && !report.pending_migrations.is_empty() {
+        conn.run_pending_migrations(db_sqlite::MIGRATIONS)
+            .map_err(|e| AppError::DatabaseMigrationError(e.to_string()))?;
+    }
+
+    report.repaired = true;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_sqlite::{new_test_goal, TestDatabase};
+
+    fn execute(query: &str) {
+        db_sqlite::with_connection(|conn| {
+            diesel::sql_query(query).execute(conn).map_err(update_error)
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_check_and_repair() {
+        let db = TestDatabase::new();
+        let first = db_sqlite::create_item(&new_test_goal(20000, "first", "m")).unwrap();
+        let second = db_sqlite::create_item(&new_test_goal(20000, "second", "t")).unwrap();
+        let report = check_database(&db.path).unwrap();
+        assert!(report.is_healthy());
+        assert!(report.pending_migrations.is_empty());
+
+        execute(&format!(
+            "UPDATE items SET kind = 9, order_in_week = 'M!' WHERE id = {second};"
+        ));
+        // a trashed item is checked too
+        execute(&format!(
+            "UPDATE items SET status = 9, deleted_at = 1 WHERE id = {first};"
+        ));
+        execute(&format!(
+            "UPDATE items SET uuid = (SELECT uuid FROM items WHERE id = {first}) WHERE id = {second};"
+        ));
+        let report = check_database(&db.path).unwrap();
+        assert!(!report.is_safe_to_open());
+        assert_eq!(report.invalid_kind_items, vec![second]);
+        assert_eq!(report.invalid_status_items, vec![first]);
+        assert_eq!(report.duplicate_uuid_items, vec![second]);
+        assert_eq!(report.broken_ordering_items, vec![second]);
+        // the bad rows don't fail the queries, the unknown kind is read as a note
+        let items = db_sqlite::read_items_between_days(20000, 20000, true).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].kind, crate::models::ItemKind::Note);
+        assert!(db_sqlite::list_trash().is_ok());
+
+        let report = repair_database(&db.path).unwrap();
+        assert!(report.repaired && report.has_row_problems());
+        assert!(check_database(&db.path).unwrap().is_healthy());
+        // the repaired note keeps its text
+        let note = db_sqlite::find_item(second).unwrap();
+        assert_eq!(note.note.as_deref(), Some("second"));
+        assert_eq!(db_sqlite::list_trash().unwrap()[0].id, first);
+    }
+}
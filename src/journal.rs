@@ -0,0 +1,349 @@
+/* Undo/Redo journal */
+
+// every journaled mutation keeps a snapshot of the touched items before and
+// after the change. undo writes back the "before" snapshots and redo the
+// "after" ones. snapshots are stored as toml text of the `Item`.
+// an action can change other records too (see `Record`), they are kept the
+// same way by their keys. undo reverts them before the items, redo after.
+
+use crate::db_sqlite;
+use crate::models::Item;
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::recurrence;
+use crate::schema::journal;
+use crate::tags::{self, ItemTag};
+use crate::templates;
+use diesel::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+// only this many last actions are kept in the journal
+pub(crate) const JOURNAL_MAX_ACTIONS: i32 = 100;
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = crate::schema::journal)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct JournalEntry {
+    pub id: i32,
+    pub action: i32,
+    pub label: String,
+    pub item_id: i32,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub undone: bool,
+    pub record: String,
+    pub record_key: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::journal)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct NewJournalEntry {
+    action: i32,
+    label: String,
+    item_id: i32,
+    before: Option<String>,
+    after: Option<String>,
+    undone: bool,
+    record: String,
+    record_key: Option<String>,
+}
+
+/// one item change of an action: (item id, before, after)
+pub type ItemChange = (i32, Option<Item>, Option<Item>);
+
+const ITEM_RECORD: &str = "item";
+
+/// the other records an action can change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Record {
+    Tag,
+    // the key of a link is "<item uuid> <tag uuid>", see `item_tag_key()`
+    ItemTag,
+    Recurrence,
+    Template,
+}
+
+/// one change of another record of an action: (record, key, before, after).
+/// the snapshots are toml text.
+pub(crate) type RecordChange = (Record, String, Option<String>, Option<String>);
+
+impl Record {
+    fn name(self) -> &'static str {
+        match self {
+            Record::Tag => "tag",
+            Record::ItemTag => "item_tag",
+            Record::Recurrence => "recurrence",
+            Record::Template => "template",
+        }
+    }
+
+    fn from_name(name: &str) -> AppResult<Record> {
+        match name {
+            "tag" => Ok(Record::Tag),
+            "item_tag" => Ok(Record::ItemTag),
+            "recurrence" => Ok(Record::Recurrence),
+            "template" => Ok(Record::Template),
+            _ => Err(AppError::DatabaseSelectError(format!(
+                "unknown journal record: {name}"
+            ))),
+        }
+    }
+}
+
+pub(crate) fn item_tag_key(link: &ItemTag) -> String {
+    format!("{} {}", link.item_uuid, link.tag_uuid)
+}
+
+fn item_tag_of_key(key: &str) -> AppResult<ItemTag> {
+    let (item_uuid, tag_uuid) =
+        key.split_once(' ')
+            .ok_or(AppError::DatabaseSelectError(format!(
+                "invalid link: {key}"
+            )))?;
+    Ok(ItemTag {
+        item_uuid: item_uuid.into(),
+        tag_uuid: tag_uuid.into(),
+    })
+}
+
+fn to_snapshot<T: Serialize>(value: &Option<T>) -> AppResult<Option<String>> {
+    value
+        .as_ref()
+        .map(|value| {
+            toml::to_string(value).map_err(|e| AppError::DatabaseInsertError(e.to_string()))
+        })
+        .transpose()
+}
+
+fn from_snapshot<T: DeserializeOwned>(snapshot: &Option<String>) -> AppResult<Option<T>> {
+    snapshot
+        .as_ref()
+        .map(|text| {
+            toml::from_str::<T>(text).map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+        })
+        .transpose()
+}
+
+/// the snapshot of the record as it is now, `None` if there is none
+pub(crate) fn read_record_on(
+    conn: &mut SqliteConnection,
+    record: Record,
+    key: &str,
+) -> AppResult<Option<String>> {
+    match record {
+        Record::Tag => to_snapshot(&tags::find_tag_on(conn, key)?),
+        Record::ItemTag => {
+            let link = item_tag_of_key(key)?;
+            let exists = tags::item_tag_exists_on(conn, &link)?;
+            to_snapshot(&Some(link).filter(|_| exists))
+        }
+        Record::Recurrence => to_snapshot(&recurrence::find_recurrence_on(conn, key)?),
+        Record::Template => to_snapshot(&templates::find_template_on(conn, key)?),
+    }
+}
+
+// make the stored record like the snapshot. `None` removes it.
+fn write_record_on(
+    conn: &mut SqliteConnection,
+    record: Record,
+    key: &str,
+    snapshot: &Option<String>,
+) -> AppResult<()> {
+    match record {
+        Record::Tag => match from_snapshot(snapshot)? {
+            Some(tag) => tags::write_tag_on(conn, &tag),
+            None => tags::erase_tag_on(conn, key),
+        },
+        Record::ItemTag => match from_snapshot(snapshot)? {
+            Some(link) => tags::write_item_tag_on(conn, &link),
+            None => tags::erase_item_tag_on(conn, &item_tag_of_key(key)?),
+        },
+        Record::Recurrence => match from_snapshot(snapshot)? {
+            Some(rule) => recurrence::write_recurrence_on(conn, &rule),
+            None => recurrence::erase_recurrence_on(conn, key),
+        },
+        Record::Template => match from_snapshot(snapshot)? {
+            Some(template) => templates::write_template_on(conn, &template),
+            None => templates::erase_template_on(conn, key),
+        },
+    }
+}
+
+/// record the changes as a new action.
+/// this should run in the same transaction as the changes themselves.
+pub(crate) fn record_on(
+    conn: &mut SqliteConnection,
+    label: &str,
+    changes: &[ItemChange],
+    records: &[RecordChange],
+) -> AppResult<()> {
+    if changes.is_empty() && records.is_empty() {
+        return Ok(());
+    }
+
+    // a new change makes the undone actions unreachable
+    diesel::delete(journal::table.filter(journal::undone.eq(true)))
+        .execute(conn)
+        .map_err(|e| AppError::DatabaseDeleteError(e.to_string()))?;
+
+    let last_action: Option<i32> = journal::table
+        .select(diesel::dsl::max(journal::action))
+        .first(conn)
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))?;
+    let action = last_action.unwrap_or(0) + 1;
+
+    let mut entries = Vec::with_capacity(changes.len() + records.len());
+    for (item_id, before, after) in changes {
+        entries.push(NewJournalEntry {
+            action,
+            label: label.to_string(),
+            item_id: *item_id,
+            before: to_snapshot(before)?,
+            after: to_snapshot(after)?,
+            undone: false,
+            record: ITEM_RECORD.into(),
+            record_key: None,
+        });
+    }
+    for (record, key, before, after) in records {
+        entries.push(NewJournalEntry {
+            action,
+            label: label.to_string(),
+            item_id: 0,
+            before: before.clone(),
+            after: after.clone(),
+            undone: false,
+            record: record.name().into(),
+            record_key: Some(key.clone()),
+        });
+    }
+    diesel::insert_into(journal::table)
+        .values(&entries)
+        .execute(conn)
+        .map_err(|e| AppError::DatabaseInsertError(e.to_string()))?;
+
+    // forget the too old actions
+    diesel::delete(journal::table.filter(journal::action.le(action - JOURNAL_MAX_ACTIONS)))
+        .execute(conn)
+        .map_err(|e| AppError::DatabaseDeleteError(e.to_string()))?;
+    Ok(())
+}
+
+// the action that undo (or redo) would apply next
+fn next_action_on(conn: &mut SqliteConnection, for_undo: bool) -> AppResult<Option<i32>> {
+    let query = journal::table.filter(journal::undone.eq(!for_undo));
+    let action = if for_undo {
+        query.select(diesel::dsl::max(journal::action)).first(conn)
+    } else {
+        query.select(diesel::dsl::min(journal::action)).first(conn)
+    };
+    action.map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+}
+
+fn read_action_on(conn: &mut SqliteConnection, action: i32) -> AppResult<Vec<JournalEntry>> {
+    journal::table
+        .filter(journal::action.eq(action))
+        .order(journal::id.asc())
+        .select(JournalEntry::as_select())
+        .load(conn)
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+}
+
+fn apply_on(conn: &mut SqliteConnection, for_undo: bool) -> AppResult<Option<String>> {
+    conn.immediate_transaction(|conn| {
+        let Some(action) = next_action_on(conn, for_undo)? else {
+            return Ok(None);
+        };
+        let mut entries = read_action_on(conn, action)?;
+        if for_undo {
+            // revert in the reverse order of the changes
+            entries.reverse();
+        }
+        for entry in entries.iter() {
+            let snapshot = if for_undo {
+                &entry.before
+            } else {
+                &entry.after
+            };
+            if entry.record == ITEM_RECORD {
+                db_sqlite::write_item_snapshot_on(conn, entry.item_id, from_snapshot(snapshot)?)?;
+            } else {
+                let record = Record::from_name(&entry.record)?;
+                let key = entry.record_key.as_deref().unwrap_or_default();
+                write_record_on(conn, record, key, snapshot)?;
+            }
+        }
+        diesel::update(journal::table.filter(journal::action.eq(action)))
+            .set(journal::undone.eq(for_undo))
+            .execute(conn)
+            .map_err(|e| AppError::DatabaseUpdateError(e.to_string()))?;
+        Ok(entries.first().map(|e| e.label.clone()))
+    })
+}
+
+/// revert the last action. returns its label, or `None` if there is nothing to undo.
+pub fn undo() -> AppResult<Option<String>> {
+    db_sqlite::with_connection(|conn| apply_on(conn, true))
+}
+
+/// re-apply the last undone action. returns its label, or `None` if there is nothing to redo.
+pub fn redo() -> AppResult<Option<String>> {
+    db_sqlite::with_connection(|conn| apply_on(conn, false))
+}
+
+fn next_label(for_undo: bool) -> AppResult<Option<String>> {
+    db_sqlite::with_connection(|conn| {
+        let Some(action) = next_action_on(conn, for_undo)? else {
+            return Ok(None);
+        };
+        Ok(read_action_on(conn, action)?
+            .first()
+            .map(|e| e.label.clone()))
+    })
+}
+
+/// label of the action that `undo()` would revert
+pub fn undo_label() -> AppResult<Option<String>> {
+    next_label(true)
+}
+
+/// label of the action that `redo()` would re-apply
+pub fn redo_label() -> AppResult<Option<String>> {
+    next_label(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_sqlite::{new_test_goal, TestDatabase};
+    use crate::storage::{SqliteStorage, Storage};
+
+    fn title_of(id: i32) -> Option<String> {
+        db_sqlite::find_item(id).ok().and_then(|item| item.title)
+    }
+
+    #[test]
+    fn test_undo_and_redo_after_reconnect() {
+        let _db = TestDatabase::new();
+        let id = db_sqlite::create_item(&new_test_goal(20000, "draft", "m")).unwrap();
+        SqliteStorage.edit_item_text(id, "final".into()).unwrap();
+        assert_eq!(undo_label().unwrap().as_deref(), Some("edit item text"));
+
+        // the journal is in the file, like after a restart of the app
+        db_sqlite::close_connection();
+        assert_eq!(undo().unwrap().as_deref(), Some("edit item text"));
+        assert_eq!(title_of(id).as_deref(), Some("draft"));
+        assert_eq!(undo().unwrap().as_deref(), Some("add item"));
+        assert_eq!(title_of(id), None);
+        assert_eq!(undo().unwrap(), None);
+
+        db_sqlite::close_connection();
+        assert_eq!(redo_label().unwrap().as_deref(), Some("add item"));
+        redo().unwrap();
+        redo().unwrap();
+        assert_eq!(title_of(id).as_deref(), Some("final"));
+        assert_eq!(redo().unwrap(), None);
+    }
+}
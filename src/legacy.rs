@@ -0,0 +1,330 @@
+/* Legacy */
+
+// import the data of the old redb database files (see `db_redb.rs.backup`).
+// the old file has one table, `tbl_weeks`: for each week, a list of
+// (variant, cuid2 id, text, done, params) elements.
+// the week key is the number of 7 day weeks since the unix epoch, counted
+// from the configured start weekday, the same way `Week` finds its days.
+// the old ids become the uuids of the items, so importing a file twice
+// doesn't make copies.
+
+use crate::calendar::Calendar;
+use crate::config;
+use crate::db_sqlite::{self, BatchOp};
+use crate::models::{ItemKind, ItemStatus, NewItem};
+use crate::ordering;
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::time;
+use crate::weekdays::{WeekDaysUnixOffset, SEVEN_DAY_WEEK_SIZE};
+use diesel::prelude::*;
+use redb::{Database, ReadableTable, TableDefinition};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+type TypeOfElementsInDB<'a> = Vec<(i8, &'a str, &'a str, bool, Vec<&'a str>)>;
+const TABLE_I64_WEEKS: TableDefinition<i64, TypeOfElementsInDB> = TableDefinition::new("tbl_weeks");
+const DB_ELEMTN_VARIANT_GOAL: i8 = 1;
+const DB_ELEMTN_VARIANT_NOTE: i8 = 2;
+
+// the first bytes of every redb file
+const REDB_MAGIC_NUMBER: [u8; 9] = [b'r', b'e', b'd', b'b', 0x1A, 0x0A, 0xA9, 0x0D, 0x0A];
+
+// variant, id, text, done
+type LegacyElement = (i8, String, String, bool);
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub enum SkipReason {
+    InvalidId,
+    EmptyText,
+    UnknownVariant(i8),
+    /// the id is already used by an item of the database
+    AlreadyImported,
+    /// the id is used by an earlier element of the file
+    DuplicateId,
+    /// the week is out of the supported days range
+    InvalidWeek,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SkippedEntry {
+    pub week: i64,
+    // position of the element in its week
+    pub index: usize,
+    pub id: String,
+    pub text: String,
+    pub reason: SkipReason,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct LegacyImportReport {
+    pub path: String,
+    pub weeks: usize,
+    pub goals: usize,
+    pub notes: usize,
+    pub skipped: Vec<SkippedEntry>,
+}
+
+impl LegacyImportReport {
+    pub fn imported(&self) -> usize {
+        self.goals + self.notes
+    }
+}
+
+fn legacy_error<E: ToString>(e: E) -> AppError {
+    AppError::LegacyImportError(e.to_string())
+}
+
+// the start and middle unix days of the old week
+fn week_days(week: i64, day_offset: i32) -> Option<(i32, i32)> {
+    let start = week
+        .checked_mul(SEVEN_DAY_WEEK_SIZE as i64)?
+        .checked_add(day_offset as i64)?;
+    let start = i32::try_from(start).ok()?;
+    let middle = start.checked_add(SEVEN_DAY_WEEK_SIZE / 2)?;
+    Some((start, middle))
+}
+
+fn is_redb_file(path: &str) -> bool {
+    let mut magic = [0u8; REDB_MAGIC_NUMBER.len()];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && magic == REDB_MAGIC_NUMBER
+}
+
+// all the weeks of the old file, with their elements as owned values.
+// redb writes to the files it opens (recovery, format upgrade), so a copy is read.
+fn read_weeks(path: &str) -> AppResult<BTreeMap<i64, Vec<LegacyElement>>> {
+    let temp = std::env::temp_dir().join(format!("thisweek-legacy-{}.redb", cuid2::create_id()));
+    fs::copy(path, &temp).map_err(legacy_error)?;
+    let weeks = read_weeks_of_copy(&temp);
+    let _ = fs::remove_file(&temp);
+    weeks
+}
+
+fn read_weeks_of_copy(path: &Path) -> AppResult<BTreeMap<i64, Vec<LegacyElement>>> {
+    let db = Database::open(path).map_err(legacy_error)?;
+    let txn = db.begin_read().map_err(legacy_error)?;
+    let table = txn.open_table(TABLE_I64_WEEKS).map_err(legacy_error)?;
+    let mut weeks = BTreeMap::new();
+    for entry in table.iter().map_err(legacy_error)? {
+        let (week, elements) = entry.map_err(legacy_error)?;
+        let elements: Vec<LegacyElement> = elements
+            .value()
+            .into_iter()
+            .map(|(variant, id, text, done, _params)| {
+                (variant, id.to_string(), text.to_string(), done)
+            })
+            .collect();
+        weeks.insert(week.value(), elements);
+    }
+    Ok(weeks)
+}
+
+/// import the goals and notes of an old redb database file into the current
+/// database, as one undoable action. in each week, the imported items are
+/// placed after the existing ones, in their old order.
+pub fn import_redb_database(path: &str) -> AppResult<LegacyImportReport> {
+    if !Path::new(path).is_file() {
+        return Err(AppError::DatabaseFileDontExistsError);
+    }
+    if !is_redb_file(path) {
+        return Err(AppError::DatabaseFileInvalidError);
+    }
+    let weeks = read_weeks(path)?;
+
+    let main_calendar: Calendar = config::get_config().main_calendar_type.into();
+    let calendar: i32 = main_calendar.into();
+    let day_offset: WeekDaysUnixOffset = config::get_config().main_calendar_start_weekday.into();
+    let day_offset = day_offset as i32;
+    let now = time::get_current_timestamp();
+
+    let mut known_ids: HashSet<String> = db_sqlite::with_connection(|conn| {
+        crate::schema::items::table
+            .select(crate::schema::items::uuid)
+            .load::<Option<String>>(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+    })?
+    .into_iter()
+    .flatten()
+    .collect();
+    let mut file_ids: HashSet<String> = HashSet::new();
+
+    let mut report = LegacyImportReport {
+        path: path.to_string(),
+        ..Default::default()
+    };
+    let mut ops: Vec<BatchOp> = Vec::new();
+    for (week, elements) in weeks {
+        let mut new_items: Vec<NewItem> = Vec::new();
+        for (index, (variant, id, text, done)) in elements.into_iter().enumerate() {
+            let reason = if !cuid2::is_cuid2(id.clone()) {
+                Some(SkipReason::InvalidId)
+            } else if text.is_empty() {
+                Some(SkipReason::EmptyText)
+            } else if variant != DB_ELEMTN_VARIANT_GOAL && variant != DB_ELEMTN_VARIANT_NOTE {
+                Some(SkipReason::UnknownVariant(variant))
+            } else if file_ids.contains(&id) {
+                Some(SkipReason::DuplicateId)
+            } else if known_ids.contains(&id) {
+                Some(SkipReason::AlreadyImported)
+            } else if week_days(week, day_offset).is_none() {
+                Some(SkipReason::InvalidWeek)
+            } else {
+                None
+            };
+            file_ids.insert(id.clone());
+            if let Some(reason) = reason {
+                report.skipped.push(SkippedEntry {
+                    week,
+                    index,
+                    id,
+                    text,
+                    reason,
+                });
+                continue;
+            }
+            let Some((_, middle_day)) = week_days(week, day_offset) else {
+                continue;
+            };
+            let kind = if variant == DB_ELEMTN_VARIANT_GOAL {
+                report.goals += 1;
+                ItemKind::Goal
+            } else {
+                report.notes += 1;
+                ItemKind::Note
+            };
+            // the key is set below, with the other items of the week
+            let mut new_item = NewItem::new(
+                calendar,
+                None,
+                None,
+                None,
+                middle_day,
+                kind,
+                text,
+                String::new(),
+            );
+            if kind == ItemKind::Goal {
+                new_item.status = Some(if done {
+                    ItemStatus::Done
+                } else {
+                    ItemStatus::Undone
+                });
+            }
+            // the old elements have no times. with `updated_at` the item is
+            // a copy, and a done goal gets no completion time.
+            new_item.updated_at = Some(now);
+            known_ids.insert(id.clone());
+            new_item.uuid = Some(id);
+            new_items.push(new_item);
+        }
+        if new_items.is_empty() {
+            continue;
+        }
+        report.weeks += 1;
+
+        let (start_day, _) = week_days(week, day_offset).unwrap_or_default();
+        let last_key = db_sqlite::read_items_between_days(
+            start_day,
+            start_day + SEVEN_DAY_WEEK_SIZE - 1,
+            false,
+        )?
+        .into_iter()
+        .filter_map(|item| item.order_in_week)
+        .max()
+        .unwrap_or_default();
+        let keys = ordering::ordering_keys_after(&last_key, new_items.len());
+        for (mut new_item, key) in new_items.into_iter().zip(keys) {
+            new_item.order_in_week = Some(key);
+            ops.push(BatchOp::Insert(new_item));
+        }
+    }
+
+    if !ops.is_empty() {
+        db_sqlite::apply_batch_journaled("import legacy database", &ops)?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_sqlite::TestDatabase;
+
+    const WEEK: i64 = 2857;
+
+    fn write_redb_file(path: &str, elements: TypeOfElementsInDB) {
+        let db = Database::create(path).unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(TABLE_I64_WEEKS).unwrap();
+            table.insert(WEEK, elements).unwrap();
+        }
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    fn test_import_redb_database() {
+        let db = TestDatabase::new();
+        let path = db.file("old.redb");
+        let (goal, note) = (cuid2::create_id(), cuid2::create_id());
+        write_redb_file(
+            &path,
+            vec![
+                (
+                    DB_ELEMTN_VARIANT_GOAL,
+                    goal.as_str(),
+                    "old goal",
+                    true,
+                    vec![],
+                ),
+                (
+                    DB_ELEMTN_VARIANT_NOTE,
+                    note.as_str(),
+                    "old note",
+                    false,
+                    vec![],
+                ),
+                (DB_ELEMTN_VARIANT_NOTE, "bad id", "text", false, vec![]),
+                (7, cuid2::create_id().as_str(), "text", false, vec![]),
+            ],
+        );
+        assert!(import_redb_database(&db.path).is_err());
+
+        let report = import_redb_database(&path).unwrap();
+        assert_eq!((report.weeks, report.goals, report.notes), (1, 1, 1));
+        let reasons: Vec<SkipReason> = report.skipped.into_iter().map(|s| s.reason).collect();
+        assert_eq!(
+            reasons,
+            vec![SkipReason::InvalidId, SkipReason::UnknownVariant(7)]
+        );
+
+        let day_offset: WeekDaysUnixOffset =
+            config::get_config().main_calendar_start_weekday.into();
+        let (start_day, _) = week_days(WEEK, day_offset as i32).unwrap();
+        let items = db_sqlite::read_items_between_days(
+            start_day,
+            start_day + SEVEN_DAY_WEEK_SIZE - 1,
+            true,
+        )
+        .unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].uuid.as_ref(), Some(&goal));
+        assert_eq!(items[0].status, Some(ItemStatus::Done));
+        assert_eq!(items[0].completed_at, None);
+        assert_eq!(items[1].note.as_deref(), Some("old note"));
+
+        // not copied twice
+        let report = import_redb_database(&path).unwrap();
+        assert_eq!(report.imported(), 0);
+        assert!(report
+            .skipped
+            .iter()
+            .any(|s| s.reason == SkipReason::AlreadyImported));
+    }
+}
@@ -1,15 +1,32 @@
+pub mod backup;
 pub mod calendar;
+pub mod carry_over;
 pub mod config;
 pub mod db_sqlite;
 pub mod error;
+pub mod event;
+pub mod history;
+pub mod integrity;
+pub mod journal;
 pub mod language;
+pub mod legacy;
+pub mod merge;
 pub mod models;
 pub mod month_names;
 pub mod notify;
+pub mod objective_links;
 pub mod ordering;
 pub mod prelude;
+pub mod profile;
+pub mod recurrence;
 pub mod schema;
+pub mod search;
 pub mod season_names;
+pub mod storage;
+pub mod subitems;
+pub mod sync;
+pub mod tags;
+pub mod templates;
 pub mod time;
 pub mod today;
 pub mod week;
@@ -0,0 +1,556 @@
+/* Merge */
+
+// combine the items of another database file into the current one.
+// items are matched by their uuid:
+//   - the ones missing here are imported (unless they are in the other trash,
+//     or are occurrences of a recurrence rule made here already)
+//   - the ones edited in both files are conflicts, resolved by a policy
+// the lists that got new or moved items get new ordering keys, so the
+// merged lists stay ordered.
+// the tags (matched by their key), the tags of the merged items, the
+// recurrence rules and the templates are merged too: the missing ones are
+// added, and of the ones in both files the latest version is kept.
+// the whole merge is one undoable action.
+
+use crate::db_sqlite::{self, BatchOp};
+use crate::history;
+use crate::integrity;
+use crate::models::{Item, NewItem};
+use crate::ordering;
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::recurrence::{self, Recurrence};
+use crate::tags::{self, ItemTag, Tag};
+use crate::templates::{self, Template};
+use diesel::prelude::*;
+use diesel_migrations::MigrationHarness;
+use rusqlite::DatabaseName;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum MergeChoice {
+    Local,
+    Other,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct MergeConflict {
+    pub uuid: String,
+    pub local: Item,
+    pub other: Item,
+    // the names of the different fields
+    pub fields: Vec<String>,
+    pub choice: MergeChoice,
+}
+
+pub enum MergePolicy<'a> {
+    /// the version with the latest `updated_at` wins, the local one on a tie
+    LastWriteWins,
+    /// the callback chooses. the conflict it gets has the last-write-wins choice.
+    Interactive(&'a mut dyn FnMut(&MergeConflict) -> MergeChoice),
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct MergeReport {
+    pub path: String,
+    pub imported: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    // items of the other trash that are not here
+    pub skipped_deleted: usize,
+    // occurrences that are made here already, under another uuid
+    pub skipped_occurrences: usize,
+    pub conflicts: Vec<MergeConflict>,
+    pub reordered_lists: usize,
+    // the tags, recurrence rules and templates added or updated
+    pub tags: usize,
+    pub recurrences: usize,
+    pub templates: usize,
+}
+
+// what is merged from the other file
+struct OtherDatabase {
+    items: Vec<Item>,
+    tags: Vec<Tag>,
+    item_tags: Vec<ItemTag>,
+    recurrences: Vec<Recurrence>,
+    templates: Vec<Template>,
+}
+
+// a list of ordering keys, and the parent of its subitems
+type ListKey = ((bool, i32, i32), Option<String>);
+
+impl MergePolicy<'_> {
+    fn resolve(&mut self, conflict: &MergeConflict) -> MergeChoice {
+        match self {
+            MergePolicy::LastWriteWins => conflict.choice,
+            MergePolicy::Interactive(callback) => callback(conflict),
+        }
+    }
+}
+
+fn merge_error<E: ToString>(e: E) -> AppError {
+    AppError::DatabaseMergeError(e.to_string())
+}
+
+fn temp_database_path() -> PathBuf {
+    std::env::temp_dir().join(format!("thisweek-merge-{}.db", cuid2::create_id()))
+}
+
+fn remove_temp_database(path: &Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = fs::remove_file(format!("{}{suffix}", path.to_string_lossy()));
+    }
+}
+
+// read all the other file. it's copied with the backup API
+// (which also reads its WAL) and migrated, so older files can be merged.
+fn read_other_database(path: &str, temp: &Path) -> AppResult<OtherDatabase> {
+    let source = rusqlite::Connection::open(path).map_err(merge_error)?;
+    source
+        .backup(DatabaseName::Main, temp, None)
+        .map_err(merge_error)?;
+    drop(source);
+
+    let temp = temp.to_string_lossy();
+    if !integrity::check_database(&temp)?.is_safe_to_open() {
+        return Err(AppError::DatabaseFileNeedsRepairError);
+    }
+    let mut conn = SqliteConnection::establish(&temp).map_err(merge_error)?;
+    conn.run_pending_migrations(db_sqlite::MIGRATIONS)
+        .map_err(|e| AppError::DatabaseMigrationError(e.to_string()))?;
+    let items = crate::schema::items::table
+        .select(Item::as_select())
+        .load(&mut conn)
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))?;
+    Ok(OtherDatabase {
+        items,
+        tags: tags::find_tags_on(&mut conn)?,
+        item_tags: tags::find_all_item_tags_on(&mut conn)?,
+        recurrences: recurrence::find_recurrences_on(&mut conn)?,
+        templates: templates::find_templates_on(&mut conn)?,
+    })
+}
+
+fn read_local_items() -> AppResult<Vec<Item>> {
+    db_sqlite::with_connection(|conn| {
+        crate::schema::items::table
+            .select(Item::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+    })
+}
+
+fn ordering_list(item: &Item) -> ListKey {
+    (
+        ordering::ordering_list_of(item.calendar, item.year, item.day),
+        item.parent_uuid.clone(),
+    )
+}
+
+// the local item with the content of the other one
+fn take_other(local: &Item, other: &Item) -> Item {
+    let mut item = other.clone();
+    item.id = local.id;
+    item.sync = local.sync;
+    if ordering_list(local) == ordering_list(other) {
+        item.order_in_week = local.order_in_week.clone();
+        item.order_in_resolution = local.order_in_resolution.clone();
+    }
+    item
+}
+
+// the rules to write: the missing ones, and the latest version of the ones in
+// both files. the occurrences removed for good in either file stay removed.
+fn merged_recurrences(local: &[Recurrence], other: &[Recurrence]) -> Vec<Recurrence> {
+    let mut rules = Vec::new();
+    for rule in other.iter() {
+        let Some(local) = local.iter().find(|local| local.uuid == rule.uuid) else {
+            rules.push(rule.clone());
+            continue;
+        };
+        let mut merged = if rule.updated_at.unwrap_or(0) > local.updated_at.unwrap_or(0) {
+            rule.clone()
+        } else {
+            local.clone()
+        };
+        for key in local
+            .skipped
+            .split_whitespace()
+            .chain(rule.skipped.split_whitespace())
+        {
+            merged.skip(key);
+        }
+        if merged != *local {
+            rules.push(merged);
+        }
+    }
+    rules
+}
+
+// the templates to write: the missing ones, and the newer ones
+fn merged_templates(local: &[Template], other: &[Template]) -> Vec<Template> {
+    other
+        .iter()
+        .filter(|template| {
+            local
+                .iter()
+                .find(|local| local.uuid == template.uuid)
+                .map_or(true, |local| {
+                    template.updated_at.unwrap_or(0) > local.updated_at.unwrap_or(0)
+                })
+        })
+        .cloned()
+        .collect()
+}
+
+/// merge the other database file into the current database.
+pub fn merge_database(path: &str, mut policy: MergePolicy) -> AppResult<MergeReport> {
+    let current = crate::config::get_config().database;
+    if fs::canonicalize(path).ok() == fs::canonicalize(&current).ok() {
+        return Err(merge_error("can not merge the database with itself"));
+    }
+    if !db_sqlite::is_correct_db(path) {
+        return Err(AppError::DatabaseFileInvalidError);
+    }
+
+    let temp = temp_database_path();
+    let other = read_other_database(path, &temp);
+    remove_temp_database(&temp);
+    let other = other?;
+    let local_items = read_local_items()?;
+    let local_rules = recurrence::find_recurrences()?;
+
+    let mut report = MergeReport {
+        path: path.to_string(),
+        ..Default::default()
+    };
+
+    let by_uuid: HashMap<&str, &Item> = local_items
+        .iter()
+        .filter_map(|item| item.uuid.as_deref().map(|uuid| (uuid, item)))
+        .collect();
+    let occurrences: HashSet<(&str, &str)> = local_items
+        .iter()
+        .filter_map(|item| {
+            Some((
+                item.recurrence_uuid.as_deref()?,
+                item.occurrence_key.as_deref()?,
+            ))
+        })
+        .collect();
+    // the final version of the changed and new items.
+    // new items get temporary negative ids until they are inserted.
+    let mut changed: HashMap<i32, Item> = HashMap::new();
+    let mut new_items: Vec<Item> = Vec::new();
+    let mut affected_lists: HashSet<ListKey> = HashSet::new();
+
+    for other in other.items.iter() {
+        let local = other.uuid.as_deref().and_then(|uuid| by_uuid.get(uuid));
+        let Some(local) = local else {
+            if other.deleted_at.is_some() {
+                report.skipped_deleted += 1;
+                continue;
+            }
+            let occurrence = other
+                .recurrence_uuid
+                .as_deref()
+                .zip(other.occurrence_key.as_deref());
+            // made here already, or removed here for good
+            let removed = occurrence.is_some_and(|(rule_uuid, key)| {
+                local_rules
+                    .iter()
+                    .any(|rule| rule.uuid == rule_uuid && rule.is_skipped(key))
+            });
+            if removed || occurrence.is_some_and(|occurrence| occurrences.contains(&occurrence)) {
+                report.skipped_occurrences += 1;
+                continue;
+            }
+            let mut item = other.clone();
+            item.id = -(new_items.len() as i32) - 1;
+            affected_lists.insert(ordering_list(&item));
+            new_items.push(item);
+            continue;
+        };
+        let fields: Vec<String> = history::item_changes(local, other)
+            .into_iter()
+            .map(|(field, _, _)| field.to_string())
+            .collect();
+        if fields.is_empty() {
+            report.unchanged += 1;
+            continue;
+        }
+        let mut conflict = MergeConflict {
+            uuid: other.uuid.clone().unwrap_or_default(),
+            local: (*local).clone(),
+            other: other.clone(),
+            fields,
+            choice: if other.updated_at.unwrap_or(0) > local.updated_at.unwrap_or(0) {
+                MergeChoice::Other
+            } else {
+                MergeChoice::Local
+            },
+        };
+        conflict.choice = policy.resolve(&conflict);
+        if conflict.choice == MergeChoice::Other {
+            let item = take_other(local, other);
+            if ordering_list(local) != ordering_list(&item) {
+                affected_lists.insert(ordering_list(&item));
+            }
+            changed.insert(item.id, item);
+        }
+        report.conflicts.push(conflict);
+    }
+
+    // new keys for the whole lists that got new or moved items.
+    // the existing items keep their order, the new ones are placed by their own keys.
+    for list in affected_lists.iter() {
+        let mut members: Vec<Item> = local_items
+            .iter()
+            .map(|item| changed.get(&item.id).unwrap_or(item))
+            .chain(new_items.iter())
+            .filter(|item| item.deleted_at.is_none() && ordering_list(item) == *list)
+            .cloned()
+            .collect();
+        let is_objective = list.0 .0;
+        members.sort_by_key(|item| {
+            let key = if is_objective {
+                item.order_in_resolution.clone()
+            } else {
+                item.order_in_week.clone()
+            };
+            (key, item.id < 0, item.id.abs())
+        });
+        let keys = ordering::new_ordering_keys(members.len());
+        for (mut item, key) in members.into_iter().zip(keys) {
+            if is_objective {
+                item.order_in_resolution = Some(key);
+            } else {
+                item.order_in_week = Some(key);
+            }
+            if item.id < 0 {
+                let pos = (-item.id - 1) as usize;
+                new_items[pos] = item;
+            } else {
+                changed.insert(item.id, item);
+            }
+        }
+        report.reordered_lists += 1;
+    }
+
+    let mut ops: Vec<BatchOp> = Vec::new();
+    for item in local_items.iter() {
+        if let Some(new_version) = changed.remove(&item.id) {
+            if new_version != *item {
+                ops.push(BatchOp::Update(new_version));
+            }
+        }
+    }
+    ops.extend(
+        new_items
+            .iter()
+            .map(|item| BatchOp::Insert(NewItem::copy_of(item))),
+    );
+
+    // the tags of the other file by the local ones with the same key
+    let local_tags = tags::find_tags()?;
+    let mut new_tags = 0;
+    let mut tag_uuids: HashMap<&str, &str> = HashMap::new();
+    for tag in other.tags.iter() {
+        let key = tags::tag_key(&tag.name);
+        match local_tags
+            .iter()
+            .find(|local| tags::tag_key(&local.name) == key)
+        {
+            Some(local) => tag_uuids.insert(&tag.uuid, &local.uuid),
+            None => {
+                ops.push(BatchOp::SaveTag(tag.clone()));
+                new_tags += 1;
+                tag_uuids.insert(&tag.uuid, &tag.uuid)
+            }
+        };
+    }
+    // the links of the items that are here after the merge
+    let local_links: HashSet<(String, String)> = tags::find_all_item_tags()?
+        .into_iter()
+        .map(|link| (link.item_uuid, link.tag_uuid))
+        .collect();
+    let merged_uuids: HashSet<&str> = local_items
+        .iter()
+        .chain(new_items.iter())
+        .filter_map(|item| item.uuid.as_deref())
+        .collect();
+    for link in other.item_tags.iter() {
+        let Some(tag_uuid) = tag_uuids.get(link.tag_uuid.as_str()) else {
+            continue;
+        };
+        let link = ItemTag {
+            item_uuid: link.item_uuid.clone(),
+            tag_uuid: tag_uuid.to_string(),
+        };
+        if merged_uuids.contains(link.item_uuid.as_str())
+            && !local_links.contains(&(link.item_uuid.clone(), link.tag_uuid.clone()))
+        {
+            ops.push(BatchOp::Tag(link));
+        }
+    }
+
+    let rules = merged_recurrences(&local_rules, &other.recurrences);
+    let templates = merged_templates(&templates::find_templates()?, &other.templates);
+    report.tags = new_tags;
+    report.recurrences = rules.len();
+    report.templates = templates.len();
+    ops.extend(rules.into_iter().map(BatchOp::SaveRecurrence));
+    ops.extend(templates.into_iter().map(BatchOp::SaveTemplate));
+
+    if !ops.is_empty() {
+        let result = db_sqlite::apply_batch_journaled("merge database", &ops)?;
+        report.imported = result.inserted_ids.len();
+    }
+    report.updated = report
+        .conflicts
+        .iter()
+        .filter(|conflict| conflict.choice == MergeChoice::Other)
+        .count();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::db_sqlite::{new_test_goal, TestDatabase};
+    use crate::journal;
+    use crate::models::ItemKind;
+    use crate::storage::{SqliteStorage, Storage};
+    use crate::templates::TemplateItem;
+
+    // run `f` on the other database file
+    fn on_database<T>(path: &str, f: impl FnOnce() -> T) -> T {
+        let current = config::get_config();
+        config::set_config(config::Config {
+            database: path.into(),
+            ..current.clone()
+        });
+        let result = f();
+        config::set_config(current);
+        result
+    }
+
+    fn titles() -> Vec<String> {
+        db_sqlite::read_items_between_days(20000, 20000, true)
+            .unwrap()
+            .into_iter()
+            .filter_map(|item| item.title)
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_database() {
+        let db = TestDatabase::new();
+        let shared = db_sqlite::create_item(&new_test_goal(20000, "shared", "m")).unwrap();
+        db_sqlite::close_connection();
+        let other = db.file("other.db");
+        fs::copy(&db.path, &other).unwrap();
+
+        on_database(&other, || {
+            SqliteStorage
+                .edit_item_text(shared, "edited".into())
+                .unwrap();
+            db_sqlite::create_item(&new_test_goal(20000, "new", "t")).unwrap();
+            let trashed = db_sqlite::create_item(&new_test_goal(20000, "trashed", "w")).unwrap();
+            db_sqlite::trash_item(trashed).unwrap();
+        });
+        assert!(merge_database(&db.path, MergePolicy::LastWriteWins).is_err());
+
+        let mut asked = 0;
+        let mut choose = |conflict: &MergeConflict| {
+            asked += 1;
+            assert_eq!(conflict.fields, vec!["title"]);
+            MergeChoice::Other
+        };
+        let report = merge_database(&other, MergePolicy::Interactive(&mut choose)).unwrap();
+        assert_eq!(asked, 1);
+        assert_eq!(report.imported, 1);
+        assert_eq!(report.updated, 1);
+        assert_eq!(report.skipped_deleted, 1);
+        assert_eq!(titles(), vec!["edited", "new"]);
+
+        assert_eq!(journal::undo().unwrap().as_deref(), Some("merge database"));
+        assert_eq!(titles(), vec!["shared"]);
+    }
+
+    #[test]
+    fn test_merge_tags_rules_templates_and_subitems() {
+        let db = TestDatabase::new();
+        let shared = db_sqlite::create_item(&new_test_goal(20000, "shared", "m")).unwrap();
+        let shared_uuid = db_sqlite::find_item(shared).unwrap().uuid;
+        db_sqlite::close_connection();
+        let other = db.file("other.db");
+        fs::copy(&db.path, &other).unwrap();
+        let health = tags::add_tag(&SqliteStorage, "Health").unwrap();
+
+        on_database(&other, || {
+            tags::tag_item(&SqliteStorage, shared, "health").unwrap();
+            let mut step = new_test_goal(20000, "step", "m");
+            step.parent_uuid = shared_uuid.clone();
+            let step = db_sqlite::create_item(&step).unwrap();
+            tags::tag_item(&SqliteStorage, step, "work").unwrap();
+            let rule = Recurrence::weekly(ItemKind::Goal, "gym".into(), 20000, 1, &[]);
+            recurrence::add_recurrence(&SqliteStorage, &rule).unwrap();
+            let items = vec![TemplateItem {
+                kind: ItemKind::Goal,
+                text: "plan".into(),
+                subitems: vec![],
+            }];
+            templates::save_template(&SqliteStorage, "week", items).unwrap();
+        });
+
+        let report = merge_database(&other, MergePolicy::LastWriteWins).unwrap();
+        assert_eq!(report.imported, 1);
+        assert_eq!(report.reordered_lists, 1);
+        assert_eq!(
+            (report.tags, report.recurrences, report.templates),
+            (1, 1, 1)
+        );
+        // the subitem is ordered in its own list
+        assert_eq!(
+            db_sqlite::find_item(shared)
+                .unwrap()
+                .order_in_week
+                .as_deref(),
+            Some("m")
+        );
+        // the same tag by its key
+        let links = tags::find_item_tags(&[shared_uuid.unwrap()]).unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].tag_uuid, health.uuid);
+        assert_eq!(tags::find_tags().unwrap().len(), 2);
+        assert_eq!(tags::find_all_item_tags().unwrap().len(), 2);
+        assert_eq!(recurrence::find_recurrences().unwrap().len(), 1);
+        assert_eq!(templates::find_templates().unwrap()[0].name, "week");
+
+        // nothing new the second time
+        let report = merge_database(&other, MergePolicy::LastWriteWins).unwrap();
+        assert_eq!(report.imported, 0);
+        assert_eq!(
+            (report.tags, report.recurrences, report.templates),
+            (0, 0, 0)
+        );
+
+        // the whole merge is undone, and redone
+        assert_eq!(journal::undo().unwrap().as_deref(), Some("merge database"));
+        assert_eq!(tags::find_tags().unwrap().len(), 1);
+        assert_eq!(tags::find_all_item_tags().unwrap().len(), 0);
+        assert!(recurrence::find_recurrences().unwrap().is_empty());
+        assert!(templates::find_templates().unwrap().is_empty());
+        assert_eq!(journal::redo().unwrap().as_deref(), Some("merge database"));
+        assert_eq!(tags::find_tags().unwrap().len(), 2);
+        assert_eq!(tags::find_all_item_tags().unwrap().len(), 2);
+        assert_eq!(recurrence::find_recurrences().unwrap().len(), 1);
+        assert_eq!(templates::find_templates().unwrap().len(), 1);
+    }
+}
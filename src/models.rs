@@ -1,21 +1,220 @@
 use crate::calendar::Calendar;
 use crate::calendar::CalendarLanguagePair;
+use crate::event::EventSchedule;
+use crate::objective_links::ParentObjective;
+use crate::prelude::Error as AppError;
+use crate::recurrence;
+use crate::subitems::Rollup;
+use crate::tags::Tag;
+use crate::week_info;
 use cuid2;
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
 use diesel::prelude::*;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Integer;
+use diesel::sqlite::{Sqlite, SqliteValue};
 use serde::Deserialize;
 use serde::Serialize;
+use std::fmt;
 
+// the values stored in the database.
+// the typed enums below should be used instead, these are for raw sql.
 pub const ITEM_KIND_GOAL: i32 = 1;
 pub const ITEM_KIND_NOTE: i32 = 2;
 pub const ITEM_KIND_EVENT: i32 = 3;
 pub const STATUS_UNDONE: i32 = 0;
 pub const STATUS_DONE: i32 = 1;
+pub const STATUS_IN_PROGRESS: i32 = 2;
+pub const STATUS_CANCELLED: i32 = 3;
+pub const STATUS_DEFERRED: i32 = 4;
+pub const STATUS_BLOCKED: i32 = 5;
 
 pub const LIST_TYPE_WEEKS: i32 = 1;
 pub const LIST_TYPE_OBJECTIVES: i32 = 2;
 
+pub const RECURRENCE_WEEKLY: i32 = 1;
+pub const RECURRENCE_MONTHLY: i32 = 2;
+pub const RECURRENCE_YEARLY: i32 = 3;
+
+// conversions of an enum from/to its stored integer: fallible from i32,
+// serde and the database as the integer, and displayed as the integer.
+// with `(unknown = Variant)` the unknown values of the database are read as
+// that variant, so one bad row doesn't fail a whole query. the integrity
+// check reads the raw values and reports them.
+macro_rules! integer_enum {
+    (@common $name:ident, $($variant:ident = $value:expr),+) => {
+        impl TryFrom<i32> for $name {
+            type Error = AppError;
+            fn try_from(value: i32) -> Result<Self, Self::Error> {
+                match value {
+                    $(v if v == $value => Ok($name::$variant),)+
+                    _ => Err(AppError::InvalidValueError(stringify!($name), value)),
+                }
+            }
+        }
+
+        impl From<$name> for i32 {
+            fn from(value: $name) -> Self {
+                match value {
+                    $($name::$variant => $value,)+
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", i32::from(*self))
+            }
+        }
+
+        impl ToSql<Integer, Sqlite> for $name {
+            fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+                out.set_value(i32::from(*self));
+                Ok(IsNull::No)
+            }
+        }
+    };
+    ($name:ident, $($variant:ident = $value:expr),+ $(,)?) => {
+        integer_enum!(@common $name, $($variant = $value),+);
+
+        impl FromSql<Integer, Sqlite> for $name {
+            fn from_sql(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+                let value = <i32 as FromSql<Integer, Sqlite>>::from_sql(value)?;
+                Ok($name::try_from(value)?)
+            }
+        }
+    };
+    ($name:ident (unknown = $unknown:ident), $($variant:ident = $value:expr),+ $(,)?) => {
+        integer_enum!(@common $name, $($variant = $value),+);
+
+        impl FromSql<Integer, Sqlite> for $name {
+            fn from_sql(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+                let value = <i32 as FromSql<Integer, Sqlite>>::from_sql(value)?;
+                Ok($name::try_from(value).unwrap_or($name::$unknown))
+            }
+        }
+    };
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Default,
+    Serialize,
+    Deserialize,
+    AsExpression,
+    FromSqlRow,
+)]
+#[serde(try_from = "i32", into = "i32")]
+#[diesel(sql_type = Integer)]
+pub enum ItemKind {
+    #[default]
+    Goal,
+    Note,
+    Event,
+}
+
+// the unknown values are read like `repair_database()` fixes them
+integer_enum!(
+    ItemKind(unknown = Note),
+    Goal = ITEM_KIND_GOAL,
+    Note = ITEM_KIND_NOTE,
+    Event = ITEM_KIND_EVENT,
+);
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Default,
+    Serialize,
+    Deserialize,
+    AsExpression,
+    FromSqlRow,
+)]
+#[serde(try_from = "i32", into = "i32")]
+#[diesel(sql_type = Integer)]
+pub enum ItemStatus {
+    #[default]
+    Undone,
+    Done,
+    InProgress,
+    /// dropped on purpose, not counted as a failed item
+    Cancelled,
+    /// moved to a later week or year
+    Deferred,
+    /// waiting for something, the reason is in `Item.status_reason`
+    Blocked,
+}
+
+integer_enum!(
+    ItemStatus(unknown = Undone),
+    Undone = STATUS_UNDONE,
+    Done = STATUS_DONE,
+    InProgress = STATUS_IN_PROGRESS,
+    Cancelled = STATUS_CANCELLED,
+    Deferred = STATUS_DEFERRED,
+    Blocked = STATUS_BLOCKED,
+);
+
+impl ItemStatus {
+    /// done and cancelled items are closed, the others still need work
+    pub fn is_closed(&self) -> bool {
+        matches!(self, ItemStatus::Done | ItemStatus::Cancelled)
+    }
+
+    /// the allowed status changes. closed items can only be opened again,
+    /// the open ones can go to any other status.
+    pub fn can_change_to(&self, next: ItemStatus) -> bool {
+        if *self == next {
+            return false;
+        }
+        if self.is_closed() {
+            matches!(next, ItemStatus::Undone | ItemStatus::InProgress)
+        } else {
+            true
+        }
+    }
+}
+
 #[derive(
-    Queryable, Selectable, Identifiable, AsChangeset, Debug, Serialize, Deserialize, Clone,
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, AsExpression, FromSqlRow,
+)]
+#[serde(try_from = "i32", into = "i32")]
+#[diesel(sql_type = Integer)]
+pub enum RecurrenceFrequency {
+    Weekly,
+    /// by the months of the rule's calendar
+    Monthly,
+    Yearly,
+}
+
+integer_enum!(
+    RecurrenceFrequency,
+    Weekly = RECURRENCE_WEEKLY,
+    Monthly = RECURRENCE_MONTHLY,
+    Yearly = RECURRENCE_YEARLY,
+);
+
+#[derive(
+    Queryable,
+    Selectable,
+    Identifiable,
+    AsChangeset,
+    Insertable,
+    Debug,
+    Serialize,
+    Deserialize,
+    Clone,
+    PartialEq,
 )]
 #[diesel(table_name = crate::schema::items)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
@@ -27,29 +226,64 @@ pub struct Item {
     pub season: Option<i32>,
     pub month: Option<i32>,
     pub day: i32,
-    pub kind: i32,
+    pub kind: ItemKind,
     pub fixed_date: bool,
     pub all_day: bool,
     pub title: Option<String>,
     pub note: Option<String>,
     pub datetime: Option<String>,
     pub duration: Option<i32>,
-    pub status: Option<i32>,
+    pub status: Option<ItemStatus>,
     pub order_in_week: Option<String>,
     pub order_in_resolution: Option<String>,
     pub sync: Option<i32>,
     pub uuid: Option<String>,
+    pub deleted_at: Option<i64>,
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
+    pub completed_at: Option<i64>,
+    pub status_reason: Option<String>,
+    pub parent_uuid: Option<String>,
+    pub objective_uuid: Option<String>,
+    // the synced changes of older versions don't have it
+    #[serde(default)]
+    pub carry_count: i32,
+    pub carried_from: Option<String>,
+    /// the uuid of the copy, for a goal copied to another week
+    pub carried_to: Option<String>,
+    /// the rule of an occurrence, and its key in the rule
+    pub recurrence_uuid: Option<String>,
+    pub occurrence_key: Option<String>,
 }
 
 #[derive(Debug, Serialize, Clone, Default)]
 pub struct ItemView {
     pub id: i32,
     pub calendar: i32,
-    pub kind: i32,
+    pub kind: ItemKind,
     pub text: String,
+    // done or not, for the simple check box
     pub status: bool,
+    pub state: ItemStatus,
+    pub status_reason: Option<String>,
+    // the time of the events
+    pub event: Option<EventSchedule>,
     pub fixed_day_tag: Option<String>,
     pub objective_tag: Option<ObjectiveTag>,
+    // an occurrence of a recurrence rule
+    pub recurring: bool,
+    // the subitems, nested
+    pub children: Vec<ItemView>,
+    // the completion of the subitems, `None` without subitems
+    pub rollup: Option<Rollup>,
+    // the tags, sorted by their names
+    pub tags: Vec<Tag>,
+    // the objective of a linked weekly goal
+    pub parent_objective: Option<ParentObjective>,
+    // the linked weekly goals of an objective, `None` without any
+    pub progress: Option<Rollup>,
+    // how many times the goal is carried over to the next week
+    pub carry_count: i32,
     pub uuid: Option<String>,
 }
 
@@ -58,11 +292,42 @@ pub const OBJECTIVE_TYPE_MONTHLY: i32 = 1;
 pub const OBJECTIVE_TYPE_SEASONAL: i32 = 2;
 pub const OBJECTIVE_TYPE_YEARLY: i32 = 3;
 
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Default,
+    Serialize,
+    Deserialize,
+    AsExpression,
+    FromSqlRow,
+)]
+#[serde(try_from = "i32", into = "i32")]
+#[diesel(sql_type = Integer)]
+pub enum ObjectiveType {
+    #[default]
+    None,
+    Monthly,
+    Seasonal,
+    Yearly,
+}
+
+integer_enum!(
+    ObjectiveType,
+    None = OBJECTIVE_TYPE_NONE,
+    Monthly = OBJECTIVE_TYPE_MONTHLY,
+    Seasonal = OBJECTIVE_TYPE_SEASONAL,
+    Yearly = OBJECTIVE_TYPE_YEARLY,
+);
+
 #[derive(Debug, Serialize, Clone, Default)]
 pub struct ObjectiveTag {
     pub calendar: i32,
     pub text: String,
-    pub r#type: i32,
+    pub r#type: ObjectiveType,
     pub calendar_name: String,
     pub language: String,
     pub year_string: String,
@@ -74,14 +339,15 @@ pub struct ObjectiveTag {
 impl From<&Item> for ItemView {
     fn from(item: &Item) -> Self {
         let text: String = match item.kind {
-            ITEM_KIND_GOAL => item.title.clone().unwrap_or_default(),
-            ITEM_KIND_NOTE => item.note.clone().unwrap_or_default(),
-            _ => "".into(),
+            ItemKind::Goal => item.title.clone().unwrap_or_default(),
+            ItemKind::Note => item.note.clone().unwrap_or_default(),
+            // events have a title, and a note as their details
+            ItemKind::Event => item.title.clone().or(item.note.clone()).unwrap_or_default(),
         };
-        let status = { !matches!(item.status.unwrap_or_default(), 0) };
+        let status = item.status == Some(ItemStatus::Done);
         let fixed_day_tag = {
             if item.day != 0 && item.fixed_date {
-                Some("todo: fixed date!".to_string())
+                Some(week_info::day_tag(item.day))
             } else {
                 None
             }
@@ -98,8 +364,18 @@ impl From<&Item> for ItemView {
             kind: item.kind,
             text,
             status,
+            state: item.status.unwrap_or_default(),
+            status_reason: item.status_reason.clone(),
+            event: EventSchedule::of(item),
             fixed_day_tag,
             objective_tag,
+            recurring: recurrence::occurrence_of(item).is_some(),
+            children: vec![],
+            rollup: None,
+            tags: vec![],
+            parent_objective: None,
+            progress: None,
+            carry_count: item.carry_count,
             uuid: item.uuid.clone(),
         }
     }
@@ -125,7 +401,7 @@ impl AsItemsList for Vec<Item> {
             }
             (previous, next)
         } else {
-            let position = iter.position(|i| (i.id == id));
+            let position = iter.position(|i| i.id == id);
             if let Some(pos) = position {
                 if pos > 0 {
                     previous = Some(self[pos - 1].id);
@@ -141,7 +417,7 @@ impl AsItemsList for Vec<Item> {
     }
 }
 
-#[derive(Insertable)]
+#[derive(Insertable, Debug, Clone)]
 #[diesel(table_name = crate::schema::items)]
 #[diesel(treat_none_as_null = true)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
@@ -151,18 +427,35 @@ pub struct NewItem {
     pub season: Option<i32>,
     pub month: Option<i32>,
     pub day: i32,
-    pub kind: i32,
+    pub kind: ItemKind,
     pub fixed_date: bool,
     pub all_day: bool,
     pub title: Option<String>,
     pub note: Option<String>,
     pub datetime: Option<String>,
     pub duration: Option<i32>,
-    pub status: Option<i32>,
+    pub status: Option<ItemStatus>,
     pub order_in_week: Option<String>,
     pub order_in_resolution: Option<String>,
     pub sync: Option<i32>,
     pub uuid: Option<String>,
+    // in the trash, for the imported copies of the trashed items
+    pub deleted_at: Option<i64>,
+    // filled by the database functions when not provided. a copy of an
+    // existing item (with `updated_at`) keeps its timestamps as they are.
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
+    pub completed_at: Option<i64>,
+    pub status_reason: Option<String>,
+    pub parent_uuid: Option<String>,
+    pub objective_uuid: Option<String>,
+    pub carry_count: i32,
+    pub carried_from: Option<String>,
+    /// the uuid of the copy, for a goal copied to another week
+    pub carried_to: Option<String>,
+    /// the rule of an occurrence, and its key in the rule
+    pub recurrence_uuid: Option<String>,
+    pub occurrence_key: Option<String>,
 }
 
 impl NewItem {
@@ -173,7 +466,7 @@ impl NewItem {
         season: Option<i32>,
         month: Option<i32>,
         day: i32,
-        kind: i32,
+        kind: ItemKind,
         text: String,
         ordering_key: String,
     ) -> Self {
@@ -187,19 +480,19 @@ impl NewItem {
             kind,
             fixed_date: false,
             all_day: false,
-            title: if kind == ITEM_KIND_GOAL {
+            title: if kind != ItemKind::Note {
                 Some(text.clone())
             } else {
                 None
             },
-            note: if kind == ITEM_KIND_NOTE {
+            note: if kind == ItemKind::Note {
                 Some(text.clone())
             } else {
                 None
             },
             datetime: None,
             duration: None,
-            status: Some(STATUS_UNDONE),
+            status: Some(ItemStatus::Undone),
             order_in_week: if is_objective {
                 None
             } else {
@@ -212,6 +505,18 @@ impl NewItem {
             },
             sync: None,
             uuid: Some(cuid2::create_id()),
+            deleted_at: None,
+            created_at: None,
+            updated_at: None,
+            completed_at: None,
+            status_reason: None,
+            parent_uuid: None,
+            objective_uuid: None,
+            carry_count: 0,
+            carried_from: None,
+            carried_to: None,
+            recurrence_uuid: None,
+            occurrence_key: None,
         }
     }
 
@@ -234,6 +539,28 @@ impl NewItem {
             order_in_resolution: item.order_in_resolution.clone(),
             sync: None,
             uuid: Some(cuid2::create_id()),
+            deleted_at: None,
+            created_at: None,
+            updated_at: None,
+            completed_at: item.completed_at,
+            status_reason: item.status_reason.clone(),
+            parent_uuid: item.parent_uuid.clone(),
+            objective_uuid: item.objective_uuid.clone(),
+            carry_count: item.carry_count,
+            carried_from: item.carried_from.clone(),
+            carried_to: item.carried_to.clone(),
+            recurrence_uuid: item.recurrence_uuid.clone(),
+            occurrence_key: item.occurrence_key.clone(),
         }
     }
+
+    /// the copy of the item, with its uuid, trash state and timestamps
+    pub fn copy_of(item: &Item) -> NewItem {
+        let mut new_item = NewItem::from(item);
+        new_item.uuid = item.uuid.clone().or(new_item.uuid);
+        new_item.deleted_at = item.deleted_at;
+        new_item.created_at = item.created_at;
+        new_item.updated_at = item.updated_at;
+        new_item
+    }
 }
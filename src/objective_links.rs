@@ -0,0 +1,186 @@
+/* Objective links */
+
+// a weekly goal can be for an objective (a yearly, seasonal or monthly item):
+// it keeps the uuid of the objective in `objective_uuid`. the week shows the
+// objective of each linked goal, and the year shows the progress of each
+// objective as the done and total of its linked goals, in all the weeks.
+// the cancelled goals are not counted, and neither are the goals copied to
+// another week (see `carry_over`), their copies are. a goal of a trashed objective is
+// shown without it.
+
+use crate::db_sqlite;
+use crate::models::{Item, ItemKind, ItemStatus, ItemView, ObjectiveTag};
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::schema::items;
+use crate::storage::Storage;
+use crate::subitems::Rollup;
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// the objective of a weekly goal
+#[derive(Debug, Serialize, Clone)]
+pub struct ParentObjective {
+    pub id: i32,
+    pub uuid: String,
+    pub text: String,
+    pub objective_tag: Option<ObjectiveTag>,
+}
+
+fn link_error<E: ToString>(e: E) -> AppError {
+    AppError::InvalidObjectiveLinkError(e.to_string())
+}
+
+/// link the weekly goal to the objective, or unlink it without one.
+/// it can be undone.
+pub fn link_goal_to_objective(
+    storage: &dyn Storage,
+    goal_id: i32,
+    objective_id: Option<i32>,
+) -> AppResult<usize> {
+    let mut goal = storage.get_item(goal_id)?;
+    if goal.kind != ItemKind::Goal || goal.year.is_some() {
+        return Err(link_error(format!("item {goal_id} is not a weekly goal")));
+    }
+    goal.objective_uuid = match objective_id {
+        Some(objective_id) => {
+            let objective = storage.get_item(objective_id)?;
+            if objective.year.is_none() || objective.deleted_at.is_some() {
+                return Err(link_error(format!(
+                    "item {objective_id} is not an objective"
+                )));
+            }
+            let Some(uuid) = objective.uuid else {
+                return Err(link_error(format!("item {objective_id} has no uuid")));
+            };
+            Some(uuid)
+        }
+        None => None,
+    };
+    storage.update_item_labeled("link goal to objective", &goal)
+}
+
+/// the progress of the objectives by their uuids, from their linked goals
+pub fn progress_of_objectives<'a, I>(
+    storage: &dyn Storage,
+    objectives: I,
+) -> AppResult<HashMap<String, Rollup>>
+where
+    I: IntoIterator<Item = &'a Item>,
+{
+    let uuids: Vec<String> = objectives
+        .into_iter()
+        .filter_map(|objective| objective.uuid.clone())
+        .collect();
+    let mut progress: HashMap<String, Rollup> = HashMap::new();
+    if uuids.is_empty() {
+        return Ok(progress);
+    }
+    for goal in storage.read_linked_goals(&uuids)? {
+        let Some(uuid) = goal.objective_uuid else {
+            continue;
+        };
+        if goal.status == Some(ItemStatus::Cancelled) || goal.carried_to.is_some() {
+            continue;
+        }
+        let rollup = progress.entry(uuid).or_default();
+        rollup.total += 1;
+        if goal.status == Some(ItemStatus::Done) {
+            rollup.done += 1;
+        }
+    }
+    for rollup in progress.values_mut() {
+        rollup.complete = rollup.total > 0 && rollup.done == rollup.total;
+    }
+    Ok(progress)
+}
+
+/// the objectives of the linked goals, by their uuids
+pub fn parent_objectives<'a, I>(
+    storage: &dyn Storage,
+    goals: I,
+) -> AppResult<HashMap<String, ParentObjective>>
+where
+    I: IntoIterator<Item = &'a Item>,
+{
+    let mut uuids: Vec<String> = goals
+        .into_iter()
+        .filter_map(|goal| goal.objective_uuid.clone())
+        .collect();
+    uuids.sort();
+    uuids.dedup();
+    if uuids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    Ok(storage
+        .read_items_by_uuids(&uuids)?
+        .into_iter()
+        .filter(|objective| objective.year.is_some())
+        .filter_map(|objective| {
+            let view = ItemView::from(&objective);
+            let uuid = objective.uuid?;
+            let parent = ParentObjective {
+                id: objective.id,
+                uuid: uuid.clone(),
+                text: view.text,
+                objective_tag: view.objective_tag,
+            };
+            Some((uuid, parent))
+        })
+        .collect())
+}
+
+/// put the objectives on the views of the linked goals, and their subitems
+pub fn fill_parent_objectives(
+    views: &mut [ItemView],
+    goals: &[&Item],
+    objectives: &HashMap<String, ParentObjective>,
+) {
+    for view in views.iter_mut() {
+        view.parent_objective = goals
+            .iter()
+            .find(|goal| goal.id == view.id)
+            .and_then(|goal| goal.objective_uuid.as_ref())
+            .and_then(|uuid| objectives.get(uuid))
+            .cloned();
+        fill_parent_objectives(&mut view.children, goals, objectives);
+    }
+}
+
+/// put the progress on the views of the objectives, and their subitems
+pub fn fill_progress(views: &mut [ItemView], progress: &HashMap<String, Rollup>) {
+    for view in views.iter_mut() {
+        view.progress = view
+            .uuid
+            .as_ref()
+            .and_then(|uuid| progress.get(uuid))
+            .cloned();
+        fill_progress(&mut view.children, progress);
+    }
+}
+
+/* Sqlite */
+
+pub(crate) fn find_items_by_uuids(uuids: &[String]) -> AppResult<Vec<Item>> {
+    db_sqlite::with_connection(|conn| {
+        items::table
+            .filter(items::deleted_at.is_null())
+            .filter(items::uuid.eq_any(uuids))
+            .select(Item::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+    })
+}
+
+pub(crate) fn find_linked_goals(objective_uuids: &[String]) -> AppResult<Vec<Item>> {
+    db_sqlite::with_connection(|conn| {
+        items::table
+            .filter(items::deleted_at.is_null())
+            .filter(items::year.is_null())
+            .filter(items::objective_uuid.eq_any(objective_uuids))
+            .select(Item::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+    })
+}
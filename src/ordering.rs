@@ -1,3 +1,6 @@
+use crate::prelude::Result as AppResult;
+use crate::week::get_week_start_middle_end_unix_day;
+
 pub type Result<T> = std::result::Result<T, String>;
 
 pub trait Ordering {
@@ -6,7 +9,8 @@ pub trait Ordering {
     fn set_ordering_key_of_posision(&mut self, i: usize, key: Option<String>) -> Result<()>;
     // fn get_posision_of_id(&self, id: i32) -> Result<usize>;
     fn get_ordering_key_of_id(&self, id: i32) -> Option<Option<String>>;
-    fn new_ordering_finished(&self);
+    /// save the new keys of the list
+    fn new_ordering_finished(&self) -> AppResult<()>;
 
     fn get_key_pos_of_id(&self, id: i32) -> Option<(String, usize)> {
         let ordering_key = self.get_ordering_key_of_id(id)??;
@@ -28,8 +32,7 @@ pub trait Ordering {
         fix
     }
 
-    fn new_ordering(&mut self) {
-        println!("reordering all items...");
+    fn new_ordering(&mut self) -> AppResult<()> {
         let mut top = String::from("");
         let bot = String::from("");
         let mut i: usize = 0;
@@ -44,19 +47,20 @@ pub trait Ordering {
             i += 1;
             top = new_key;
         }
-        self.new_ordering_finished();
+        self.new_ordering_finished()
     }
 
-    fn check_and_fix_ordering(&mut self) {
+    fn check_and_fix_ordering(&mut self) -> AppResult<()> {
         let fix = self.needs_reordering();
         if fix {
             // println!("fixing some invalid ordering keys...");
             // println!("items before ordering: {:?}", self);
-            self.new_ordering();
+            self.new_ordering()?;
             // println!("items after ordering: {:?}", self);
         } else {
             // println!("ordering seems ok");
         }
+        Ok(())
     }
 
     fn get_new_ordering_key(&self, after_id: Option<i32>) -> String {
@@ -224,3 +228,29 @@ pub trait Ordering {
     //     }
     // }
 }
+
+/// the list an item is ordered in, as (is objective, calendar, year or week start day).
+/// weekly items are ordered per week, objectives per calendar year.
+pub fn ordering_list_of(calendar: i32, year: Option<i32>, day: i32) -> (bool, i32, i32) {
+    match year {
+        Some(year) => (true, calendar, year),
+        None => (false, 0, get_week_start_middle_end_unix_day(day).0),
+    }
+}
+
+/// `count` increasing keys, the same ones `new_ordering()` gives a list
+pub fn new_ordering_keys(count: usize) -> Vec<String> {
+    ordering_keys_after("", count)
+}
+
+/// `count` increasing keys, all sorting after `last`
+pub fn ordering_keys_after(last: &str, count: usize) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::with_capacity(count);
+    let mut top = String::from(last);
+    for _ in 0..count {
+        let key = midstring::mid_string(&top, "");
+        keys.push(key.clone());
+        top = key;
+    }
+    keys
+}
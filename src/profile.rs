@@ -0,0 +1,247 @@
+/* Profiles */
+
+// named sets of settings, like "work" and "personal", each with its own
+// database file and calendar settings.
+// the settings of the active profile are the usual fields of `Config`, so
+// the rest of the app doesn't need to know about profiles. `Config.profiles`
+// keeps the settings of all the profiles, and on a switch the settings of
+// the active profile are saved there and the ones of the new profile are
+// copied to the config.
+// after a switch, `update()` the `Week`, `Year` and `Today` in use.
+
+use crate::config::{self, Config};
+use crate::db_sqlite;
+use crate::integrity;
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub database: String,
+    pub main_calendar_type: String,
+    pub main_calendar_language: String,
+    pub main_calendar_start_weekday: String,
+    pub secondary_calendar_type: Option<String>,
+    pub secondary_calendar_language: Option<String>,
+}
+
+impl Profile {
+    // the settings of the active profile
+    fn of(config: &Config) -> Profile {
+        Profile {
+            name: config.profile.clone(),
+            database: config.database.clone(),
+            main_calendar_type: config.main_calendar_type.clone(),
+            main_calendar_language: config.main_calendar_language.clone(),
+            main_calendar_start_weekday: config.main_calendar_start_weekday.clone(),
+            secondary_calendar_type: config.secondary_calendar_type.clone(),
+            secondary_calendar_language: config.secondary_calendar_language.clone(),
+        }
+    }
+
+    fn apply_to(&self, config: &mut Config) {
+        config.profile = self.name.clone();
+        config.database = self.database.clone();
+        config.main_calendar_type = self.main_calendar_type.clone();
+        config.main_calendar_language = self.main_calendar_language.clone();
+        config.main_calendar_start_weekday = self.main_calendar_start_weekday.clone();
+        config.secondary_calendar_type = self.secondary_calendar_type.clone();
+        config.secondary_calendar_language = self.secondary_calendar_language.clone();
+    }
+}
+
+fn profile_error<E: ToString>(e: E) -> AppError {
+    AppError::ProfileError(e.to_string())
+}
+
+// all the profiles, with the current settings of the active one
+fn profiles_of(config: &Config) -> Vec<Profile> {
+    let mut profiles = config.profiles.clone();
+    let active = Profile::of(config);
+    match profiles.iter_mut().find(|p| p.name == config.profile) {
+        Some(profile) => *profile = active,
+        None => profiles.insert(0, active),
+    }
+    profiles
+}
+
+fn check_new_name(profiles: &[Profile], name: &str) -> AppResult<String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(profile_error("the profile name is empty"));
+    }
+    if profiles.iter().any(|p| p.name == name) {
+        return Err(profile_error(format!(
+            "the profile '{name}' already exists"
+        )));
+    }
+    Ok(name.to_string())
+}
+
+// a database file next to the current one, named after the profile
+fn default_database_of(config: &Config, name: &str) -> String {
+    let file_name: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    let directory = Path::new(&config.database)
+        .parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or_default();
+    directory
+        .join(format!("thisweek-{file_name}.db"))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn save(config: Config) -> AppResult<()> {
+    config::set_config(config.clone());
+    config::save_config(config)
+}
+
+/// all the profiles, the active one too
+pub fn list_profiles() -> Vec<Profile> {
+    profiles_of(&config::get_config())
+}
+
+/// the name of the active profile
+pub fn active_profile() -> String {
+    config::get_config().profile
+}
+
+/// add a profile with the calendar settings of the active profile.
+/// without a database path, a new file is made next to the current database.
+/// an existing database file is used if it is valid.
+pub fn create_profile(name: &str, database: Option<String>) -> AppResult<Profile> {
+    let mut config = config::get_config();
+    let mut profiles = profiles_of(&config);
+    let name = check_new_name(&profiles, name)?;
+    let database = database.unwrap_or_else(|| default_database_of(&config, &name));
+    if profiles.iter().any(|p| p.database == database) {
+        return Err(profile_error(format!(
+            "the database is used by another profile: {database}"
+        )));
+    }
+    if Path::new(&database).exists() {
+        if !db_sqlite::is_correct_db(&database) {
+            return Err(AppError::DatabaseFileInvalidError);
+        }
+    } else {
+        if let Some(parent) = Path::new(&database).parent() {
+            fs::create_dir_all(parent).map_err(|_| AppError::DatabaseFileCreateError)?;
+        }
+        db_sqlite::create_db(&database)?;
+    }
+    let profile = Profile {
+        name,
+        database,
+        ..Profile::of(&config)
+    };
+    profiles.push(profile.clone());
+    config.profiles = profiles;
+    save(config)?;
+    Ok(profile)
+}
+
+/// make the profile active: its database is opened and its calendar
+/// settings are used from now on.
+pub fn switch_profile(name: &str) -> AppResult<()> {
+    let mut config = config::get_config();
+    if config.profile == name {
+        return Ok(());
+    }
+    let profiles = profiles_of(&config);
+    let profile = profiles
+        .iter()
+        .find(|p| p.name == name)
+        .ok_or(profile_error(format!("no profile named '{name}'")))?;
+    if !Path::new(&profile.database).exists() {
+        return Err(AppError::DatabaseFileDontExistsError);
+    }
+    if !db_sqlite::is_correct_db(&profile.database) {
+        return Err(AppError::DatabaseFileInvalidError);
+    }
+    if !integrity::check_database(&profile.database)?.is_safe_to_open() {
+        return Err(AppError::DatabaseFileNeedsRepairError);
+    }
+    profile.apply_to(&mut config);
+    config.profiles = profiles;
+    db_sqlite::close_connection();
+    save(config)
+}
+
+pub fn rename_profile(name: &str, new_name: &str) -> AppResult<()> {
+    let mut config = config::get_config();
+    let mut profiles = profiles_of(&config);
+    let new_name = check_new_name(&profiles, new_name)?;
+    let profile = profiles
+        .iter_mut()
+        .find(|p| p.name == name)
+        .ok_or(profile_error(format!("no profile named '{name}'")))?;
+    profile.name = new_name.clone();
+    if config.profile == name {
+        config.profile = new_name;
+    }
+    config.profiles = profiles;
+    save(config)
+}
+
+/// remove the profile from the config. its database file is kept.
+/// the active profile can not be deleted, switch to another one first.
+pub fn delete_profile(name: &str) -> AppResult<()> {
+    let mut config = config::get_config();
+    if config.profile == name {
+        return Err(profile_error("the active profile can not be deleted"));
+    }
+    let mut profiles = profiles_of(&config);
+    let count = profiles.len();
+    profiles.retain(|p| p.name != name);
+    if profiles.len() == count {
+        return Err(profile_error(format!("no profile named '{name}'")));
+    }
+    config.profiles = profiles;
+    save(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_sqlite::{new_test_goal, TestDatabase};
+
+    #[test]
+    fn test_profiles() {
+        let db = TestDatabase::new();
+        db_sqlite::create_item(&new_test_goal(20000, "personal", "m")).unwrap();
+        let work = create_profile("work", None).unwrap();
+        assert!(db_sqlite::is_correct_db(&work.database));
+        assert!(create_profile("work", None).is_err());
+        assert!(create_profile(" ", None).is_err());
+        assert_eq!(list_profiles().len(), 2);
+        assert_eq!(active_profile(), "default");
+
+        switch_profile("work").unwrap();
+        assert_eq!(config::get_config().database, work.database);
+        assert!(db_sqlite::read_items_between_days(20000, 20000, true)
+            .unwrap()
+            .is_empty());
+        switch_profile("default").unwrap();
+        assert_eq!(config::get_config().database, db.path);
+        assert_eq!(
+            db_sqlite::read_items_between_days(20000, 20000, true)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        rename_profile("work", "job").unwrap();
+        assert!(delete_profile("default").is_err());
+        delete_profile("job").unwrap();
+        assert_eq!(list_profiles().len(), 1);
+        // the database file is kept
+        assert!(Path::new(&work.database).exists());
+    }
+}
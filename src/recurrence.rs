@@ -0,0 +1,807 @@
+/* Recurrence */
+
+// rules that repeat a goal or a note: every n weeks (on some weekdays, or
+// for the whole week), or every n months or years of a calendar on a day of
+// the month. the monthly and yearly rules without a day make objectives.
+// the occurrences are made as normal items when their week or year is opened,
+// by an explicit call (see `materialize_between_days()` and
+// `materialize_in_calendar_year()`), so each one is done, moved or edited on
+// its own. an occurrence keeps its rule and its key in the rule
+// (`recurrence_uuid` and `occurrence_key`), so it is made only once. a
+// trashed occurrence is not made again, and the key of one removed for good
+// is kept in the `skipped` keys of its rule.
+// the uuid of an occurrence is `<rule uuid>:<occurrence key>`, the same on
+// all the devices, so the ones made on two devices are one item when synced
+// or merged.
+// "edit the following occurrences" ends the rule before the occurrence and
+// starts a changed copy of the rule from there. the following occurrences go
+// to the new rule, with their own state (like being done). the past stays as
+// it was.
+// the changes of the rules can not be undone, and the rules are not synced,
+// only their occurrences are.
+
+use crate::calendar::{Calendar, CALENDAR_ARABIC, CALENDAR_GREGORIAN};
+use crate::config;
+use crate::db_sqlite::{self, BatchOp};
+use crate::models::{Item, ItemKind, NewItem, RecurrenceFrequency};
+use crate::ordering;
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::schema::{items, recurrences};
+use crate::storage::Storage;
+use crate::time;
+use crate::today;
+use crate::week::get_week_start_middle_end_unix_day;
+use crate::weekdays::{WeekDaysUnixOffset, SEVEN_DAY_WEEK_SIZE};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+const OCCURRENCE_SEPARATOR: char = ':';
+
+#[derive(
+    Queryable, Selectable, Insertable, AsChangeset, Debug, Serialize, Deserialize, Clone, PartialEq,
+)]
+#[diesel(table_name = crate::schema::recurrences)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+#[diesel(treat_none_as_null = true)]
+pub struct Recurrence {
+    pub uuid: String,
+    pub kind: ItemKind,
+    pub text: String,
+    /// the calendar of the months and years
+    pub calendar: i32,
+    pub frequency: RecurrenceFrequency,
+    /// every `interval` weeks, months or years
+    pub interval: i32,
+    /// the weekdays of a weekly rule, as bits of `WeekDaysUnixOffset`.
+    /// `None` for the whole week
+    pub weekdays: Option<i32>,
+    /// the month of a yearly rule
+    pub month: Option<i32>,
+    /// the day of the month. without it a monthly or yearly rule makes objectives
+    pub month_day: Option<i32>,
+    pub start_day: i32,
+    /// the last day, `None` for no end
+    pub end_day: Option<i32>,
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
+    /// the keys of the occurrences removed for good, separated by spaces.
+    /// they are not made again.
+    #[serde(default)]
+    pub skipped: String,
+}
+
+// one occurrence of a rule
+#[derive(Debug, Clone, PartialEq)]
+enum Occurrence {
+    Day(i32),
+    // the whole week, by its start day
+    Week(i32),
+    // the objective of the year, or of a month of the year
+    Objective(i32, Option<i32>),
+}
+
+impl Occurrence {
+    fn key(&self) -> String {
+        match self {
+            Occurrence::Day(day) => format!("d{day}"),
+            Occurrence::Week(start_day) => format!("w{start_day}"),
+            Occurrence::Objective(year, None) => format!("y{year}"),
+            Occurrence::Objective(year, Some(month)) => format!("y{year}m{month}"),
+        }
+    }
+
+    fn parse(key: &str) -> Option<Occurrence> {
+        if let Some(day) = key.strip_prefix('d') {
+            return day.parse().ok().map(Occurrence::Day);
+        }
+        if let Some(start_day) = key.strip_prefix('w') {
+            return start_day.parse().ok().map(Occurrence::Week);
+        }
+        let period = key.strip_prefix('y')?;
+        match period.split_once('m') {
+            Some((year, month)) => Some(Occurrence::Objective(
+                year.parse().ok()?,
+                Some(month.parse().ok()?),
+            )),
+            None => Some(Occurrence::Objective(period.parse().ok()?, None)),
+        }
+    }
+}
+
+fn recurrence_error<E: ToString>(e: E) -> AppError {
+    AppError::InvalidRecurrenceError(e.to_string())
+}
+
+// the rule uuid and the occurrence of the item
+fn occurrence_parts(item: &Item) -> Option<(&str, Occurrence)> {
+    let rule = item.recurrence_uuid.as_deref()?;
+    Some((rule, Occurrence::parse(item.occurrence_key.as_deref()?)?))
+}
+
+/// the uuid of the rule, if the item is one of its occurrences
+pub fn occurrence_of(item: &Item) -> Option<String> {
+    occurrence_parts(item).map(|(rule, _)| rule.to_string())
+}
+
+// the year and month of the day in the calendar
+fn month_of(calendar: &Calendar, day: i32) -> (i32, i32) {
+    let date = calendar.get_date(day);
+    (date.year, date.month as i32)
+}
+
+// the first unix day of the month of the calendar, or of the year without a
+// month. months have at least 28 days, so steps of 27 days don't jump over one.
+fn first_day_of(calendar: &Calendar, year: i32, month: Option<i32>) -> i32 {
+    let target = (year, month.unwrap_or(1));
+    let today = today::get_unix_day();
+    let now = month_of(calendar, today);
+    let mut day = today + ((target.0 - now.0) * 12 + target.1 - now.1) * 29;
+    while month_of(calendar, day) < target {
+        day += 27;
+    }
+    while month_of(calendar, day) > target {
+        day -= 27;
+    }
+    while month_of(calendar, day - 1) == target {
+        day -= 1;
+    }
+    day
+}
+
+// the day is the day of the month, or the last day of a shorter month
+fn is_month_day(calendar: &Calendar, day: i32, day_of_month: i32, month_day: i32) -> bool {
+    day_of_month == month_day || (day_of_month < month_day && calendar.get_date(day + 1).day == 1)
+}
+
+impl Recurrence {
+    fn new(
+        kind: ItemKind,
+        text: String,
+        calendar: i32,
+        frequency: RecurrenceFrequency,
+        start_day: i32,
+        interval: i32,
+    ) -> Recurrence {
+        Recurrence {
+            uuid: cuid2::create_id(),
+            kind,
+            text,
+            calendar,
+            frequency,
+            interval,
+            weekdays: None,
+            month: None,
+            month_day: None,
+            start_day,
+            end_day: None,
+            created_at: None,
+            updated_at: None,
+            skipped: String::new(),
+        }
+    }
+
+    /// every `interval` weeks from the week of `start_day`, on the weekdays,
+    /// or for the whole week without them
+    pub fn weekly(
+        kind: ItemKind,
+        text: String,
+        start_day: i32,
+        interval: i32,
+        weekdays: &[WeekDaysUnixOffset],
+    ) -> Recurrence {
+        let main_cal: Calendar = config::get_config().main_calendar_type.into();
+        let mut rule = Recurrence::new(
+            kind,
+            text,
+            main_cal.into(),
+            RecurrenceFrequency::Weekly,
+            start_day,
+            interval,
+        );
+        rule.weekdays = (!weekdays.is_empty())
+            .then(|| weekdays.iter().fold(0, |bits, day| bits | 1 << *day as i32));
+        rule
+    }
+
+    /// every `interval` months of the calendar on the day of the month (the
+    /// last day of the shorter months), or as a monthly objective without it
+    pub fn monthly(
+        kind: ItemKind,
+        text: String,
+        calendar: &Calendar,
+        start_day: i32,
+        interval: i32,
+        month_day: Option<i32>,
+    ) -> Recurrence {
+        let mut rule = Recurrence::new(
+            kind,
+            text,
+            calendar.clone().into(),
+            RecurrenceFrequency::Monthly,
+            start_day,
+            interval,
+        );
+        rule.month_day = month_day;
+        rule
+    }
+
+    /// every `interval` years of the calendar on the day of the month, or as
+    /// an objective of the year (or of the month) without a day
+    pub fn yearly(
+        kind: ItemKind,
+        text: String,
+        calendar: &Calendar,
+        start_day: i32,
+        interval: i32,
+        month: Option<i32>,
+        month_day: Option<i32>,
+    ) -> Recurrence {
+        let mut rule = Recurrence::new(
+            kind,
+            text,
+            calendar.clone().into(),
+            RecurrenceFrequency::Yearly,
+            start_day,
+            interval,
+        );
+        rule.month = month;
+        rule.month_day = month_day;
+        rule
+    }
+
+    /// the occurrences are objectives, shown in the years
+    pub fn is_objective(&self) -> bool {
+        self.frequency != RecurrenceFrequency::Weekly && self.month_day.is_none()
+    }
+
+    fn check(&self) -> AppResult<()> {
+        if self.text.trim().is_empty() {
+            return Err(recurrence_error("the text is empty"));
+        }
+        if self.kind == ItemKind::Event {
+            return Err(recurrence_error("only goals and notes can repeat"));
+        }
+        if !(CALENDAR_GREGORIAN..=CALENDAR_ARABIC).contains(&self.calendar) {
+            return Err(recurrence_error(format!(
+                "invalid calendar: {}",
+                self.calendar
+            )));
+        }
+        if self.interval < 1 {
+            return Err(recurrence_error("the interval should be at least 1"));
+        }
+        if self.month_day.is_some_and(|day| !(1..=31).contains(&day)) {
+            return Err(recurrence_error("invalid day of the month"));
+        }
+        if self.month.is_some_and(|month| !(1..=12).contains(&month)) {
+            return Err(recurrence_error("invalid month"));
+        }
+        if self
+            .weekdays
+            .is_some_and(|bits| bits <= 0 || bits >= 1 << 7)
+        {
+            return Err(recurrence_error("invalid weekdays"));
+        }
+        let valid = match self.frequency {
+            RecurrenceFrequency::Weekly => self.month.is_none() && self.month_day.is_none(),
+            RecurrenceFrequency::Monthly => self.month.is_none() && self.weekdays.is_none(),
+            RecurrenceFrequency::Yearly => {
+                self.weekdays.is_none() && (self.month_day.is_none() || self.month.is_some())
+            }
+        };
+        if !valid {
+            return Err(recurrence_error(format!(
+                "the fields don't match a {:?} rule",
+                self.frequency
+            )));
+        }
+        Ok(())
+    }
+
+    fn occurrence_uuid(&self, occurrence: &Occurrence) -> String {
+        format!("{}{OCCURRENCE_SEPARATOR}{}", self.uuid, occurrence.key())
+    }
+
+    pub(crate) fn is_skipped(&self, key: &str) -> bool {
+        self.skipped
+            .split_whitespace()
+            .any(|skipped| skipped == key)
+    }
+
+    // keep the key of an occurrence removed for good, so it's not made again
+    pub(crate) fn skip(&mut self, key: &str) {
+        if !self.is_skipped(key) {
+            if !self.skipped.is_empty() {
+                self.skipped.push(' ');
+            }
+            self.skipped.push_str(key);
+        }
+    }
+
+    // the first day of the occurrence. the yearly objectives start with their year.
+    fn first_day_of(&self, occurrence: &Occurrence) -> i32 {
+        match *occurrence {
+            Occurrence::Day(day) | Occurrence::Week(day) => day,
+            Occurrence::Objective(year, month) => {
+                let month = month.filter(|_| self.frequency == RecurrenceFrequency::Monthly);
+                first_day_of(&self.calendar.into(), year, month)
+            }
+        }
+    }
+
+    // the occurrences in the days range (inclusive)
+    fn occurrences_between_days(&self, start_day: i32, end_day: i32) -> Vec<Occurrence> {
+        if self.is_objective() {
+            return vec![];
+        }
+        let calendar: Calendar = self.calendar.into();
+        let first_week = get_week_start_middle_end_unix_day(self.start_day).0;
+        let first_month = month_of(&calendar, self.start_day);
+        let in_rule =
+            |day: i32| day >= self.start_day && self.end_day.map_or(true, |end| day <= end);
+        let mut occurrences = Vec::new();
+        for day in start_day..=end_day {
+            if self.frequency == RecurrenceFrequency::Weekly {
+                let week = get_week_start_middle_end_unix_day(day).0;
+                let weeks = (week - first_week) / SEVEN_DAY_WEEK_SIZE;
+                if weeks < 0 || weeks % self.interval != 0 {
+                    continue;
+                }
+                match self.weekdays {
+                    Some(bits) if in_rule(day) && bits & (1 << day.rem_euclid(7)) != 0 => {
+                        occurrences.push(Occurrence::Day(day))
+                    }
+                    Some(_) => {}
+                    None => {
+                        let occurrence = Occurrence::Week(week);
+                        let ended = self.end_day.is_some_and(|end| week > end);
+                        if !ended && !occurrences.contains(&occurrence) {
+                            occurrences.push(occurrence);
+                        }
+                    }
+                }
+                continue;
+            }
+            let Some(month_day) = self.month_day else {
+                continue;
+            };
+            if !in_rule(day) {
+                continue;
+            }
+            let date = calendar.get_date(day);
+            if !is_month_day(&calendar, day, date.day as i32, month_day) {
+                continue;
+            }
+            let (year, month) = (date.year, date.month as i32);
+            let periods = match self.frequency {
+                RecurrenceFrequency::Yearly if self.month != Some(month) => continue,
+                RecurrenceFrequency::Yearly => year - first_month.0,
+                _ => (year - first_month.0) * 12 + month - first_month.1,
+            };
+            if periods >= 0 && periods % self.interval == 0 {
+                occurrences.push(Occurrence::Day(day));
+            }
+        }
+        occurrences
+    }
+
+    // the objective occurrences in the year of the rule's calendar
+    fn occurrences_in_year(&self, year: i32) -> Vec<Occurrence> {
+        if !self.is_objective() {
+            return vec![];
+        }
+        let calendar: Calendar = self.calendar.into();
+        let first = month_of(&calendar, self.start_day);
+        let last = self.end_day.map(|day| month_of(&calendar, day));
+        if self.frequency == RecurrenceFrequency::Monthly {
+            return (1..=12)
+                .filter(|month| {
+                    let periods = (year - first.0) * 12 + month - first.1;
+                    let ended = last.is_some_and(|last| (year, *month) > last);
+                    periods >= 0 && periods % self.interval == 0 && !ended
+                })
+                .map(|month| Occurrence::Objective(year, Some(month)))
+                .collect();
+        }
+        let periods = year - first.0;
+        let ended = last.is_some_and(|last| year > last.0);
+        if periods >= 0 && periods % self.interval == 0 && !ended {
+            vec![Occurrence::Objective(year, self.month)]
+        } else {
+            vec![]
+        }
+    }
+
+    // the item of the occurrence, without an ordering key
+    fn new_item(&self, occurrence: &Occurrence) -> NewItem {
+        let (year, month, day, fixed_date) = match *occurrence {
+            Occurrence::Day(day) => (None, None, day, true),
+            Occurrence::Week(start_day) => (
+                None,
+                None,
+                get_week_start_middle_end_unix_day(start_day).1,
+                false,
+            ),
+            Occurrence::Objective(year, month) => (Some(year), month, 0, false),
+        };
+        let mut new_item = NewItem::new(
+            self.calendar,
+            year,
+            None,
+            month,
+            day,
+            self.kind,
+            self.text.clone(),
+            String::new(),
+        );
+        new_item.fixed_date = fixed_date;
+        new_item.order_in_week = None;
+        new_item.order_in_resolution = None;
+        new_item.uuid = Some(self.occurrence_uuid(occurrence));
+        new_item.recurrence_uuid = Some(self.uuid.clone());
+        new_item.occurrence_key = Some(occurrence.key());
+        new_item
+    }
+
+    // the occurrence is one of the rule
+    fn has_occurrence(&self, occurrence: &Occurrence) -> bool {
+        let occurrences = match *occurrence {
+            Occurrence::Day(day) => self.occurrences_between_days(day, day),
+            Occurrence::Week(start_day) => {
+                self.occurrences_between_days(start_day, start_day + SEVEN_DAY_WEEK_SIZE - 1)
+            }
+            Occurrence::Objective(year, _) => self.occurrences_in_year(year),
+        };
+        occurrences.contains(occurrence)
+    }
+
+    // the occurrence goes to the rule, with its text and kind
+    fn apply_to(&self, item: &mut Item) {
+        let text = self.text.trim().to_string();
+        item.kind = self.kind;
+        (item.title, item.note) = if self.kind == ItemKind::Note {
+            (None, Some(text))
+        } else {
+            (Some(text), None)
+        };
+        item.recurrence_uuid = Some(self.uuid.clone());
+    }
+}
+
+// the occurrences of the rules that are not made yet
+fn missing_occurrences<F>(
+    storage: &dyn Storage,
+    rules: &[Recurrence],
+    occurrences_of: F,
+) -> AppResult<Vec<NewItem>>
+where
+    F: Fn(&Recurrence) -> Vec<Occurrence>,
+{
+    let mut new_items = Vec::new();
+    for rule in rules {
+        let occurrences = occurrences_of(rule);
+        if occurrences.is_empty() {
+            continue;
+        }
+        let known: HashSet<String> = storage
+            .read_occurrences(&rule.uuid)?
+            .into_iter()
+            .filter_map(|item| item.occurrence_key)
+            .collect();
+        new_items.extend(
+            occurrences
+                .iter()
+                .filter(|occurrence| {
+                    let key = occurrence.key();
+                    !known.contains(&key) && !rule.is_skipped(&key)
+                })
+                .map(|occurrence| rule.new_item(occurrence)),
+        );
+    }
+    Ok(new_items)
+}
+
+fn insert_items(storage: &dyn Storage, new_items: Vec<NewItem>) -> AppResult<usize> {
+    if new_items.is_empty() {
+        return Ok(0);
+    }
+    let ops: Vec<BatchOp> = new_items.into_iter().map(BatchOp::Insert).collect();
+    storage
+        .apply_batch(None, &ops)
+        .map(|result| result.inserted_ids.len())
+}
+
+fn update_items(storage: &dyn Storage, ops: Vec<BatchOp>) -> AppResult<usize> {
+    if ops.is_empty() {
+        return Ok(0);
+    }
+    storage.apply_batch(None, &ops).map(|result| result.updated)
+}
+
+/// make the missing occurrences of the days range (inclusive), after the
+/// items of the range. returns the number of new items.
+pub fn materialize_between_days(
+    storage: &dyn Storage,
+    start_day: i32,
+    end_day: i32,
+) -> AppResult<usize> {
+    let rules = storage.read_recurrences()?;
+    let mut new_items = missing_occurrences(storage, &rules, |rule| {
+        rule.occurrences_between_days(start_day, end_day)
+    })?;
+    if new_items.is_empty() {
+        return Ok(0);
+    }
+    let last_key = storage
+        .read_items_between_days(start_day, end_day)?
+        .into_iter()
+        .filter_map(|item| item.order_in_week)
+        .max()
+        .unwrap_or_default();
+    let keys = ordering::ordering_keys_after(&last_key, new_items.len());
+    for (new_item, key) in new_items.iter_mut().zip(keys) {
+        new_item.order_in_week = Some(key);
+    }
+    insert_items(storage, new_items)
+}
+
+/// make the missing objective occurrences of the calendar year, after the
+/// objectives of the year. returns the number of new items.
+pub fn materialize_in_calendar_year(
+    storage: &dyn Storage,
+    calendar: i32,
+    year: i32,
+) -> AppResult<usize> {
+    let rules: Vec<Recurrence> = storage
+        .read_recurrences()?
+        .into_iter()
+        .filter(|rule| rule.calendar == calendar)
+        .collect();
+    let mut new_items =
+        missing_occurrences(storage, &rules, |rule| rule.occurrences_in_year(year))?;
+    if new_items.is_empty() {
+        return Ok(0);
+    }
+    let last_key = storage
+        .read_items_in_calendar_year(calendar, year)?
+        .into_iter()
+        .filter_map(|item| item.order_in_resolution)
+        .max()
+        .unwrap_or_default();
+    let keys = ordering::ordering_keys_after(&last_key, new_items.len());
+    for (new_item, key) in new_items.iter_mut().zip(keys) {
+        new_item.order_in_resolution = Some(key);
+    }
+    insert_items(storage, new_items)
+}
+
+/// add the rule. its occurrences are made when their week or year is opened.
+pub fn add_recurrence(storage: &dyn Storage, recurrence: &Recurrence) -> AppResult<()> {
+    recurrence.check()?;
+    let now = time::get_current_timestamp();
+    let mut recurrence = recurrence.clone();
+    recurrence.text = recurrence.text.trim().to_string();
+    recurrence.created_at = Some(now);
+    recurrence.updated_at = Some(now);
+    storage.save_recurrence(&recurrence)
+}
+
+// the rule of the occurrence item, and the occurrence
+fn rule_of(storage: &dyn Storage, id: i32) -> AppResult<(Recurrence, Occurrence)> {
+    let item = storage.get_item(id)?;
+    let (uuid, occurrence) = occurrence_parts(&item)
+        .ok_or(recurrence_error(format!("item {id} is not an occurrence")))?;
+    let rule = storage
+        .read_recurrences()?
+        .into_iter()
+        .find(|rule| rule.uuid == uuid)
+        .ok_or(recurrence_error(format!(
+            "the rule of item {id} is removed"
+        )))?;
+    Ok((rule, occurrence))
+}
+
+// end the rule before the day. returns its occurrences from that day on,
+// the trashed ones too.
+fn end_rule_before(storage: &dyn Storage, rule: &Recurrence, day: i32) -> AppResult<Vec<Item>> {
+    let mut ended = rule.clone();
+    ended.end_day = Some(rule.end_day.map_or(day - 1, |end| end.min(day - 1)));
+    ended.updated_at = Some(time::get_current_timestamp());
+    storage.save_recurrence(&ended)?;
+    Ok(storage
+        .read_occurrences(&rule.uuid)?
+        .into_iter()
+        .filter(|item| {
+            occurrence_parts(item)
+                .is_some_and(|(_, occurrence)| rule.first_day_of(&occurrence) >= day)
+        })
+        .collect())
+}
+
+/// stop the rule from this occurrence on. this occurrence and the following
+/// ones are moved to the trash. returns the number of trashed items.
+pub fn stop_recurrence(storage: &dyn Storage, id: i32) -> AppResult<usize> {
+    let (rule, occurrence) = rule_of(storage, id)?;
+    let now = time::get_current_timestamp();
+    let ops: Vec<BatchOp> = end_rule_before(storage, &rule, rule.first_day_of(&occurrence))?
+        .into_iter()
+        .filter(|item| item.deleted_at.is_none())
+        .map(|mut item| {
+            item.deleted_at = Some(now);
+            BatchOp::Update(item)
+        })
+        .collect();
+    update_items(storage, ops)
+}
+
+/// change this occurrence and the following ones: the rule ends before this
+/// occurrence, and a copy of it changed by `edit` starts from it. the
+/// following occurrences go to the new rule with its changes, and keep their
+/// own state (like being done). the ones that are not in the new rule are
+/// moved to the trash. returns the new rule.
+pub fn edit_following_occurrences<F>(
+    storage: &dyn Storage,
+    id: i32,
+    edit: F,
+) -> AppResult<Recurrence>
+where
+    F: FnOnce(&mut Recurrence),
+{
+    let (rule, occurrence) = rule_of(storage, id)?;
+    let day = rule.first_day_of(&occurrence);
+    let mut following = rule.clone();
+    following.uuid = cuid2::create_id();
+    following.start_day = day;
+    edit(&mut following);
+    following.check()?;
+    let now = time::get_current_timestamp();
+    let ops: Vec<BatchOp> = end_rule_before(storage, &rule, day)?
+        .into_iter()
+        .filter_map(|mut item| {
+            let (_, occurrence) = occurrence_parts(&item)?;
+            if following.has_occurrence(&occurrence) {
+                following.apply_to(&mut item);
+            } else if item.deleted_at.is_none() {
+                item.deleted_at = Some(now);
+            } else {
+                return None;
+            }
+            Some(BatchOp::Update(item))
+        })
+        .collect();
+    add_recurrence(storage, &following)?;
+    update_items(storage, ops)?;
+    Ok(following)
+}
+
+/* Sqlite */
+
+pub(crate) fn find_recurrences_on(conn: &mut SqliteConnection) -> AppResult<Vec<Recurrence>> {
+    recurrences::table
+        .order(recurrences::created_at.asc())
+        .select(Recurrence::as_select())
+        .load(conn)
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+}
+
+pub(crate) fn find_recurrences() -> AppResult<Vec<Recurrence>> {
+    db_sqlite::with_connection(find_recurrences_on)
+}
+
+pub(crate) fn find_recurrence_on(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+) -> AppResult<Option<Recurrence>> {
+    recurrences::table
+        .filter(recurrences::uuid.eq(uuid))
+        .select(Recurrence::as_select())
+        .first(conn)
+        .optional()
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+}
+
+pub(crate) fn erase_recurrence_on(conn: &mut SqliteConnection, uuid: &str) -> AppResult<()> {
+    diesel::delete(recurrences::table.filter(recurrences::uuid.eq(uuid)))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| AppError::DatabaseDeleteError(e.to_string()))
+}
+
+pub(crate) fn write_recurrence_on(
+    conn: &mut SqliteConnection,
+    recurrence: &Recurrence,
+) -> AppResult<()> {
+    diesel::replace_into(recurrences::table)
+        .values(recurrence)
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| AppError::DatabaseInsertError(e.to_string()))
+}
+
+pub(crate) fn write_recurrence(recurrence: &Recurrence) -> AppResult<()> {
+    db_sqlite::with_connection(|conn| write_recurrence_on(conn, recurrence))
+}
+
+// the occurrences of the rule, the trashed ones too
+pub(crate) fn find_occurrences(uuid: &str) -> AppResult<Vec<Item>> {
+    db_sqlite::with_connection(|conn| {
+        items::table
+            .filter(items::recurrence_uuid.eq(uuid))
+            .select(Item::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+    })
+}
+
+// another item here is the same occurrence of the same rule
+pub(crate) fn occurrence_exists_on(conn: &mut SqliteConnection, item: &Item) -> AppResult<bool> {
+    let (Some(rule_uuid), Some(key)) = (&item.recurrence_uuid, &item.occurrence_key) else {
+        return Ok(false);
+    };
+    items::table
+        .filter(items::recurrence_uuid.eq(rule_uuid))
+        .filter(items::occurrence_key.eq(key))
+        .filter(items::uuid.is_not(&item.uuid))
+        .count()
+        .get_result::<i64>(conn)
+        .map(|count| count > 0)
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+}
+
+// the occurrence item is removed for good, its key is kept in its rule
+pub(crate) fn skip_occurrence_on(conn: &mut SqliteConnection, item: &Item) -> AppResult<()> {
+    let (Some(rule_uuid), Some(key)) = (&item.recurrence_uuid, &item.occurrence_key) else {
+        return Ok(());
+    };
+    let Some(mut rule) = find_recurrence_on(conn, rule_uuid)? else {
+        return Ok(());
+    };
+    rule.skip(key);
+    diesel::update(recurrences::table.filter(recurrences::uuid.eq(rule_uuid)))
+        .set(recurrences::skipped.eq(&rule.skipped))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| AppError::DatabaseUpdateError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_sqlite::TestDatabase;
+    use crate::storage::SqliteStorage;
+
+    #[test]
+    fn test_occurrences_in_sqlite() {
+        let _db = TestDatabase::new();
+        let start = get_week_start_middle_end_unix_day(20000).0;
+        let end = start + SEVEN_DAY_WEEK_SIZE - 1;
+        let rule = Recurrence::weekly(ItemKind::Goal, "gym".into(), start, 1, &[]);
+        add_recurrence(&SqliteStorage, &rule).unwrap();
+        assert_eq!(
+            materialize_between_days(&SqliteStorage, start, end).unwrap(),
+            1
+        );
+        assert_eq!(
+            materialize_between_days(&SqliteStorage, start, end).unwrap(),
+            0
+        );
+        let occurrence = find_occurrences(&rule.uuid).unwrap().remove(0);
+        assert_eq!(occurrence_of(&occurrence), Some(rule.uuid.clone()));
+        assert_eq!(
+            occurrence.uuid,
+            Some(rule.occurrence_uuid(&Occurrence::Week(start)))
+        );
+
+        // removed for good, it's kept in the rule and not made again
+        db_sqlite::trash_item(occurrence.id).unwrap();
+        db_sqlite::purge_trash(chrono::Duration::zero()).unwrap();
+        assert!(find_occurrences(&rule.uuid).unwrap().is_empty());
+        assert_eq!(find_recurrences().unwrap()[0].skipped, format!("w{start}"));
+        assert_eq!(
+            materialize_between_days(&SqliteStorage, start, end).unwrap(),
+            0
+        );
+    }
+}
@@ -20,5 +20,113 @@ diesel::table! {
         order_in_resolution -> Nullable<Text>,
         sync -> Nullable<Integer>,
         uuid -> Nullable<Text>,
+        deleted_at -> Nullable<BigInt>,
+        created_at -> Nullable<BigInt>,
+        updated_at -> Nullable<BigInt>,
+        completed_at -> Nullable<BigInt>,
+        status_reason -> Nullable<Text>,
+        parent_uuid -> Nullable<Text>,
+        objective_uuid -> Nullable<Text>,
+        carry_count -> Integer,
+        carried_from -> Nullable<Text>,
+        carried_to -> Nullable<Text>,
+        recurrence_uuid -> Nullable<Text>,
+        occurrence_key -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    journal (id) {
+        id -> Integer,
+        action -> Integer,
+        label -> Text,
+        item_id -> Integer,
+        before -> Nullable<Text>,
+        after -> Nullable<Text>,
+        undone -> Bool,
+        record -> Text,
+        record_key -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    item_history (id) {
+        id -> Integer,
+        item_id -> Integer,
+        field -> Text,
+        old_value -> Nullable<Text>,
+        new_value -> Nullable<Text>,
+        changed_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    sync_log (id) {
+        id -> Integer,
+        uuid -> Text,
+        field -> Text,
+        value -> Nullable<Text>,
+        changed_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    sync_clock (uuid, field) {
+        uuid -> Text,
+        field -> Text,
+        changed_at -> BigInt,
+        device -> Text,
+    }
+}
+
+diesel::table! {
+    sync_state (key) {
+        key -> Text,
+        value -> Text,
+    }
+}
+
+diesel::table! {
+    recurrences (uuid) {
+        uuid -> Text,
+        kind -> Integer,
+        text -> Text,
+        calendar -> Integer,
+        frequency -> Integer,
+        interval -> Integer,
+        weekdays -> Nullable<Integer>,
+        month -> Nullable<Integer>,
+        month_day -> Nullable<Integer>,
+        start_day -> Integer,
+        end_day -> Nullable<Integer>,
+        created_at -> Nullable<BigInt>,
+        updated_at -> Nullable<BigInt>,
+        skipped -> Text,
+    }
+}
+
+diesel::table! {
+    tags (uuid) {
+        uuid -> Text,
+        name -> Text,
+        created_at -> Nullable<BigInt>,
+        updated_at -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    item_tags (item_uuid, tag_uuid) {
+        item_uuid -> Text,
+        tag_uuid -> Text,
+    }
+}
+
+diesel::table! {
+    templates (uuid) {
+        uuid -> Text,
+        name -> Text,
+        items -> Text,
+        created_at -> Nullable<BigInt>,
+        updated_at -> Nullable<BigInt>,
     }
 }
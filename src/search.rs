@@ -0,0 +1,199 @@
+/* Search */
+
+// full-text search over all the items, using the `items_fts` index.
+// the index uses the trigram tokenizer, so a search term should have at least
+// 3 characters. shorter terms are searched with a plain `LIKE` instead.
+
+use crate::calendar::Calendar;
+use crate::config;
+use crate::db_sqlite;
+use crate::language::Language;
+use crate::models::{Item, ItemView, ObjectiveTag};
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::today;
+use crate::week::get_week_start_middle_end_unix_day;
+use crate::week_info::WeekInfo;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Double, Integer, Text};
+use serde::Serialize;
+
+const FTS_MIN_TERM_LENGTH: usize = 3;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SearchResult {
+    pub item: ItemView,
+    // the week of a weekly item, in the main calendar
+    pub week_info: Option<WeekInfo>,
+    // the period of an objective item
+    pub objective_tag: Option<ObjectiveTag>,
+    // lower is better
+    pub rank: f64,
+}
+
+#[derive(QueryableByName, Debug)]
+struct SearchHit {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+    #[diesel(sql_type = Double)]
+    rank: f64,
+}
+
+// make a fts5 query that matches all the terms as plain strings
+fn fts_query(terms: &[&str]) -> String {
+    terms
+        .iter()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// a `LIKE` pattern that matches the term as a plain string, with `\` as the
+// escape character
+fn like_pattern(term: &str) -> String {
+    let escaped = term
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+fn search_hits(query: &str, limit: usize) -> AppResult<Vec<SearchHit>> {
+    let terms: Vec<&str> = query.split_whitespace().collect();
+    if terms.is_empty() {
+        return Ok(vec![]);
+    }
+    let limit = limit as i64;
+    let all_long = terms
+        .iter()
+        .all(|term| term.chars().count() >= FTS_MIN_TERM_LENGTH);
+
+    db_sqlite::with_connection(|conn| {
+        if all_long {
+            diesel::sql_query(
+                "SELECT items.id AS id, bm25(items_fts) AS rank \
+                 FROM items_fts JOIN items ON items.id = items_fts.rowid \
+                 WHERE items_fts MATCH ? AND items.deleted_at IS NULL \
+                 ORDER BY rank LIMIT ?",
+            )
+            .bind::<Text, _>(fts_query(&terms))
+            .bind::<BigInt, _>(limit)
+            .load(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+        } else {
+            use crate::schema::items::dsl::*;
+            let mut select = items.filter(deleted_at.is_null()).into_boxed();
+            for term in terms {
+                let pattern = like_pattern(term);
+                select = select.filter(
+                    title
+                        .like(pattern.clone())
+                        .escape('\\')
+                        .or(note.like(pattern).escape('\\')),
+                );
+            }
+            select
+                .select(id)
+                .limit(limit)
+                .load::<i32>(conn)
+                .map(|ids| {
+                    ids.into_iter()
+                        .map(|id_| SearchHit { id: id_, rank: 0.0 })
+                        .collect()
+                })
+                .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+        }
+    })
+}
+
+fn read_items(ids: &[i32]) -> AppResult<Vec<Item>> {
+    use crate::schema::items::dsl::*;
+    db_sqlite::with_connection(|conn| {
+        items
+            .filter(id.eq_any(ids))
+            .select(Item::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+    })
+}
+
+/// search the text of all the items (weekly items and objectives).
+/// the results are sorted by relevance.
+pub fn search_items(query: &str, limit: usize) -> AppResult<Vec<SearchResult>> {
+    let hits = search_hits(query, limit)?;
+    let ids: Vec<i32> = hits.iter().map(|hit| hit.id).collect();
+    let items = read_items(&ids)?;
+
+    let today = today::get_unix_day();
+    let main_cal: Calendar = config::get_config().main_calendar_type.into();
+    let main_lang: Language = config::get_config().main_calendar_language.into();
+
+    let mut results = Vec::with_capacity(hits.len());
+    for hit in hits {
+        let Some(item) = items.iter().find(|item| item.id == hit.id) else {
+            continue;
+        };
+        let item_view = ItemView::from(item);
+        let objective_tag = item_view.objective_tag.clone();
+        let week_info = if item.year.is_none() {
+            let (start_day, _, end_day) = get_week_start_middle_end_unix_day(item.day);
+            WeekInfo::from_unix_start_end_days(
+                start_day,
+                end_day,
+                today,
+                main_cal.clone(),
+                main_lang.clone(),
+            )
+            .ok()
+        } else {
+            None
+        };
+        results.push(SearchResult {
+            item: item_view,
+            week_info,
+            objective_tag,
+            rank: hit.rank,
+        });
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_sqlite::{new_test_goal, TestDatabase};
+
+    fn search_texts(query: &str) -> Vec<String> {
+        search_items(query, 10)
+            .unwrap()
+            .into_iter()
+            .map(|result| result.item.text)
+            .collect()
+    }
+
+    #[test]
+    fn test_search_items() {
+        let _db = TestDatabase::new();
+        db_sqlite::create_item(&new_test_goal(20000, "weekly review", "m")).unwrap();
+        db_sqlite::create_item(&new_test_goal(20000, "go to the gym", "t")).unwrap();
+        let trashed = db_sqlite::create_item(&new_test_goal(20000, "old review", "w")).unwrap();
+        db_sqlite::trash_item(trashed).unwrap();
+
+        // with the index
+        assert_eq!(search_texts("review"), vec!["weekly review"]);
+        assert_eq!(search_texts("REVIEW week"), vec!["weekly review"]);
+        let results = search_items("gym", 10).unwrap();
+        assert!(results[0].week_info.is_some());
+        // short terms
+        assert_eq!(search_texts("gy"), vec!["go to the gym"]);
+        assert!(search_texts("zz").is_empty());
+        assert!(search_texts("  ").is_empty());
+
+        // the wildcards of `LIKE` are plain characters
+        db_sqlite::create_item(&new_test_goal(20000, "100% done", "y")).unwrap();
+        db_sqlite::create_item(&new_test_goal(20000, "a_b", "z")).unwrap();
+        assert_eq!(search_texts("%"), vec!["100% done"]);
+        assert_eq!(search_texts("_"), vec!["a_b"]);
+        assert!(search_texts("\\").is_empty());
+    }
+}
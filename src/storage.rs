@@ -0,0 +1,164 @@
+/* Storage */
+
+// the item operations `Week`, `Year` and the edit helpers need, behind a
+// trait, so the storage can be replaced. `SqliteStorage` is the real one
+// (the free functions of `db_sqlite`), `FolderStorage` keeps the items as
+// plain text files in a folder, `MemoryStorage` keeps everything in memory
+// and is meant for tests.
+
+mod folder;
+mod memory;
+mod sqlite;
+
+pub use folder::{export_sqlite_to_folder, import_folder_to_sqlite, FolderStorage};
+pub use memory::MemoryStorage;
+pub use sqlite::SqliteStorage;
+
+use crate::db_sqlite::{BatchOp, BatchResult};
+use crate::models::{Item, ItemKind, ItemStatus, NewItem};
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::recurrence::Recurrence;
+use crate::tags::{ItemTag, Tag};
+use crate::templates::Template;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+pub trait Storage: Send + Sync + Debug {
+    /// returns the id of the new item
+    fn create_item(&self, new_item: &NewItem) -> AppResult<i32>;
+    fn get_item(&self, id: i32) -> AppResult<Item>;
+    /// update the item, the change can be undone as an action with this label
+    fn update_item_labeled(&self, label: &str, item: &Item) -> AppResult<usize>;
+    /// move the item to the trash
+    fn remove_item(&self, id: i32) -> AppResult<usize>;
+    /// items of the days range (inclusive), sorted by their week ordering key
+    fn read_items_between_days(&self, start_day: i32, end_day: i32) -> AppResult<Vec<Item>>;
+    /// objectives of the calendar year, sorted by their objective ordering key
+    fn read_items_in_calendar_year(&self, calendar: i32, year: i32) -> AppResult<Vec<Item>>;
+    /// apply all the operations, or none of them.
+    /// with a label the whole batch can be undone as one action.
+    fn apply_batch(&self, label: Option<&str>, ops: &[BatchOp]) -> AppResult<BatchResult>;
+    /// returns the label of the undone action
+    fn undo(&self) -> AppResult<Option<String>>;
+    /// returns the label of the redone action
+    fn redo(&self) -> AppResult<Option<String>>;
+    /// all the recurrence rules
+    fn read_recurrences(&self) -> AppResult<Vec<Recurrence>>;
+    /// add the rule, or replace the one with its uuid. it can not be undone.
+    fn save_recurrence(&self, recurrence: &Recurrence) -> AppResult<()>;
+    /// the occurrences of the rule, the trashed ones too
+    fn read_occurrences(&self, recurrence_uuid: &str) -> AppResult<Vec<Item>>;
+    /// all the tags, in no order
+    fn read_tags(&self) -> AppResult<Vec<Tag>>;
+    /// add the tag, or replace the one with its uuid. it can not be undone.
+    fn save_tag(&self, tag: &Tag) -> AppResult<()>;
+    /// delete the tag and its links to the items
+    fn delete_tag(&self, uuid: &str) -> AppResult<()>;
+    /// the tags of the items, by the item uuids
+    fn read_item_tags(&self, item_uuids: &[String]) -> AppResult<Vec<ItemTag>>;
+    fn save_item_tag(&self, item_tag: &ItemTag) -> AppResult<()>;
+    fn delete_item_tag(&self, item_tag: &ItemTag) -> AppResult<()>;
+    /// the items with the tag, not the trashed ones
+    fn read_tagged_items(&self, tag_uuid: &str) -> AppResult<Vec<Item>>;
+    /// the items with the uuids, not the trashed ones
+    fn read_items_by_uuids(&self, uuids: &[String]) -> AppResult<Vec<Item>>;
+    /// the weekly items linked to the objectives, not the trashed ones
+    fn read_linked_goals(&self, objective_uuids: &[String]) -> AppResult<Vec<Item>>;
+    /// all the week templates, in no order
+    fn read_templates(&self) -> AppResult<Vec<Template>>;
+    /// add the template, or replace the one with its uuid. it can not be undone.
+    fn save_template(&self, template: &Template) -> AppResult<()>;
+    fn delete_template(&self, uuid: &str) -> AppResult<()>;
+
+    fn update_item(&self, item: &Item) -> AppResult<usize> {
+        self.update_item_labeled("update item", item)
+    }
+
+    /// save the items with their repaired ordering keys. it's not an undoable
+    /// action, undoing it would only bring the broken keys back.
+    fn update_items(&self, items: &[Item]) -> AppResult<usize> {
+        let ops: Vec<BatchOp> = items.iter().cloned().map(BatchOp::Update).collect();
+        self.apply_batch(None, &ops).map(|result| result.updated)
+    }
+
+    fn edit_item_text(&self, id: i32, text: String) -> AppResult<usize> {
+        let mut item = self.get_item(id)?;
+        match item.kind {
+            ItemKind::Goal | ItemKind::Event => item.title = Some(text),
+            ItemKind::Note => item.note = Some(text),
+        }
+        self.update_item_labeled("edit item text", &item)
+    }
+
+    fn toggle_item_state(&self, id: i32) -> AppResult<usize> {
+        let mut item = self.get_item(id)?;
+        if item.status == Some(ItemStatus::Done) {
+            item.status = Some(ItemStatus::Undone)
+        } else {
+            item.status = Some(ItemStatus::Done);
+        }
+        item.status_reason = None;
+        self.update_item_labeled("toggle item state", &item)
+    }
+
+    /// change the status, if `ItemStatus::can_change_to()` allows it.
+    /// a blocked item needs a reason, the other statuses have none.
+    fn set_item_status(
+        &self,
+        id: i32,
+        status: ItemStatus,
+        reason: Option<String>,
+    ) -> AppResult<usize> {
+        let mut item = self.get_item(id)?;
+        let current = item.status.unwrap_or_default();
+        // a blocked item can get a new reason
+        if current != status && !current.can_change_to(status) {
+            return Err(AppError::InvalidStatusChangeError(format!(
+                "{current:?} to {status:?}"
+            )));
+        }
+        let reason = reason
+            .map(|reason| reason.trim().to_string())
+            .filter(|reason| !reason.is_empty());
+        if status == ItemStatus::Blocked && reason.is_none() {
+            return Err(AppError::InvalidStatusChangeError(
+                "a blocked item needs a reason".into(),
+            ));
+        }
+        item.status = Some(status);
+        item.status_reason = reason.filter(|_| status == ItemStatus::Blocked);
+        self.update_item_labeled("change item status", &item)
+    }
+
+    fn update_item_objective_period(
+        &self,
+        id: i32,
+        year: Option<i32>,
+        season: Option<i32>,
+        month: Option<i32>,
+    ) -> AppResult<usize> {
+        let mut item = self.get_item(id)?;
+        item.year = year;
+        item.season = season;
+        item.month = month;
+        self.update_item_labeled("change objective period", &item)
+    }
+
+    fn update_item_week_ordering_key(&self, id: i32, key: String) -> AppResult<usize> {
+        let mut item = self.get_item(id)?;
+        item.order_in_week = Some(key);
+        self.update_item_labeled("reorder item", &item)
+    }
+
+    fn update_item_year_ordering_key(&self, id: i32, key: String) -> AppResult<usize> {
+        let mut item = self.get_item(id)?;
+        item.order_in_resolution = Some(key);
+        self.update_item_labeled("reorder item", &item)
+    }
+}
+
+/// the storage of the app: the configured sqlite database
+pub fn default_storage() -> Arc<dyn Storage> {
+    Arc::new(SqliteStorage)
+}
@@ -0,0 +1,836 @@
+use super::{MemoryStorage, Storage};
+use crate::calendar::Calendar;
+use crate::db_sqlite::{self, BatchOp, BatchResult};
+use crate::models::{Item, ItemKind, ItemStatus, NewItem};
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::recurrence::{self, Recurrence};
+use crate::tags::{self, ItemTag, Tag};
+use crate::templates::{self, Template, TemplatesFile};
+use crate::week::get_week_start_middle_end_unix_day;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+const WEEKS_FOLDER: &str = "weeks";
+const OBJECTIVES_FOLDER: &str = "objectives";
+const RECURRENCES_FILE: &str = "recurrences.toml";
+const TAGS_FILE: &str = "tags.toml";
+const TEMPLATES_FILE: &str = "templates.toml";
+const FILE_EXTENSION: &str = "toml";
+
+/// the items are kept as toml files in a folder, which is friendly to git
+/// and file sync services:
+///   - `weeks/<date of the week start>.toml` for the items of each week
+///   - `objectives/<calendar>-<year>.toml` for the yearly objectives,
+///     `objectives/<calendar>-<year>-season<season>.toml` and
+///     `objectives/<calendar>-<year>-month<month>.toml` for the objectives of
+///     each season and month
+///   - `recurrences.toml` for the recurrence rules
+///   - `tags.toml` for the tags and their links to the items
+///   - `templates.toml` for the week templates
+///
+/// the items are identified by their uuid, the ids only live in memory.
+/// after each change only the files whose content changed are written.
+/// the undo/redo history is kept in memory and lost when dropped.
+#[derive(Debug)]
+pub struct FolderStorage {
+    path: PathBuf,
+    memory: MemoryStorage,
+    // the content of the files, as last read or written
+    files: Mutex<BTreeMap<PathBuf, String>>,
+}
+
+// an item as written in the files. the id and the sync state are local.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct FileItem {
+    uuid: String,
+    // the gregorian date of the day, for the people reading the file
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    date: Option<String>,
+    calendar: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    year: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    season: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    month: Option<i32>,
+    day: i32,
+    kind: ItemKind,
+    #[serde(default)]
+    fixed_date: bool,
+    #[serde(default)]
+    all_day: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    datetime: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<ItemStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    order_in_week: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    order_in_resolution: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    updated_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    completed_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent_uuid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    objective_uuid: Option<String>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    carry_count: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    carried_from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    carried_to: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    recurrence_uuid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    occurrence_key: Option<String>,
+}
+
+fn is_zero(count: &i32) -> bool {
+    *count == 0
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct ItemsFile {
+    #[serde(default)]
+    items: Vec<FileItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct RecurrencesFile {
+    #[serde(default)]
+    recurrences: Vec<Recurrence>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct TagsFile {
+    #[serde(default)]
+    tags: Vec<Tag>,
+    #[serde(default)]
+    item_tags: Vec<ItemTag>,
+}
+
+// everything kept in the folder
+#[derive(Debug, Default)]
+struct FolderData {
+    items: Vec<Item>,
+    recurrences: Vec<Recurrence>,
+    tags: Vec<Tag>,
+    item_tags: Vec<ItemTag>,
+    templates: Vec<Template>,
+}
+
+impl FileItem {
+    fn from(item: &Item) -> FileItem {
+        let date = Calendar::default().get_date(item.day);
+        FileItem {
+            uuid: item.uuid.clone().unwrap_or_default(),
+            date: item
+                .year
+                .is_none()
+                .then(|| format!("{:04}-{:02}-{:02}", date.year, date.month, date.day)),
+            calendar: item.calendar,
+            year: item.year,
+            season: item.season,
+            month: item.month,
+            day: item.day,
+            kind: item.kind,
+            fixed_date: item.fixed_date,
+            all_day: item.all_day,
+            title: item.title.clone(),
+            note: item.note.clone(),
+            datetime: item.datetime.clone(),
+            duration: item.duration,
+            status: item.status,
+            order_in_week: item.order_in_week.clone(),
+            order_in_resolution: item.order_in_resolution.clone(),
+            deleted_at: item.deleted_at,
+            created_at: item.created_at,
+            updated_at: item.updated_at,
+            completed_at: item.completed_at,
+            status_reason: item.status_reason.clone(),
+            parent_uuid: item.parent_uuid.clone(),
+            objective_uuid: item.objective_uuid.clone(),
+            carry_count: item.carry_count,
+            carried_from: item.carried_from.clone(),
+            carried_to: item.carried_to.clone(),
+            recurrence_uuid: item.recurrence_uuid.clone(),
+            occurrence_key: item.occurrence_key.clone(),
+        }
+    }
+
+    fn into_item(self, id: i32) -> Item {
+        Item {
+            id,
+            calendar: self.calendar,
+            year: self.year,
+            season: self.season,
+            month: self.month,
+            day: self.day,
+            kind: self.kind,
+            fixed_date: self.fixed_date,
+            all_day: self.all_day,
+            title: self.title,
+            note: self.note,
+            datetime: self.datetime,
+            duration: self.duration,
+            status: self.status,
+            order_in_week: self.order_in_week,
+            order_in_resolution: self.order_in_resolution,
+            sync: None,
+            uuid: Some(self.uuid),
+            deleted_at: self.deleted_at,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            completed_at: self.completed_at,
+            status_reason: self.status_reason,
+            parent_uuid: self.parent_uuid,
+            objective_uuid: self.objective_uuid,
+            carry_count: self.carry_count,
+            carried_from: self.carried_from,
+            carried_to: self.carried_to,
+            recurrence_uuid: self.recurrence_uuid,
+            occurrence_key: self.occurrence_key,
+        }
+    }
+}
+
+fn folder_error<E: ToString>(e: E) -> AppError {
+    AppError::FolderStorageError(e.to_string())
+}
+
+fn calendar_file_name(calendar: i32) -> String {
+    match calendar {
+        crate::calendar::CALENDAR_GREGORIAN
+        | crate::calendar::CALENDAR_PERSIAN
+        | crate::calendar::CALENDAR_CHINESE
+        | crate::calendar::CALENDAR_ARABIC => String::from(Calendar::from(calendar)).to_lowercase(),
+        _ => format!("calendar{calendar}"),
+    }
+}
+
+// the file of the item, relative to the folder
+fn file_of(item: &Item) -> PathBuf {
+    match item.year {
+        Some(year) => {
+            // the objective period, like in `get_objective_tag()`
+            let period = match (item.season, item.month) {
+                (Some(season), _) => format!("-season{season}"),
+                (None, Some(month)) => format!("-month{month:02}"),
+                (None, None) => String::new(),
+            };
+            Path::new(OBJECTIVES_FOLDER).join(format!(
+                "{}-{year}{period}.{FILE_EXTENSION}",
+                calendar_file_name(item.calendar)
+            ))
+        }
+        None => {
+            let (start_day, _, _) = get_week_start_middle_end_unix_day(item.day);
+            let date = Calendar::default().get_date(start_day);
+            Path::new(WEEKS_FOLDER).join(format!(
+                "{:04}-{:02}-{:02}.{FILE_EXTENSION}",
+                date.year, date.month, date.day
+            ))
+        }
+    }
+}
+
+// the content of all the files, the items of each file in their list order
+fn render_files(data: FolderData) -> AppResult<BTreeMap<PathBuf, String>> {
+    let mut grouped: BTreeMap<PathBuf, Vec<Item>> = BTreeMap::new();
+    for item in data.items {
+        grouped.entry(file_of(&item)).or_default().push(item);
+    }
+    let mut files = BTreeMap::new();
+    for (file, mut items) in grouped {
+        items.sort_by(|a, b| {
+            let key_a = a.order_in_week.as_ref().or(a.order_in_resolution.as_ref());
+            let key_b = b.order_in_week.as_ref().or(b.order_in_resolution.as_ref());
+            (key_a, &a.uuid).cmp(&(key_b, &b.uuid))
+        });
+        let content = ItemsFile {
+            items: items.iter().map(FileItem::from).collect(),
+        };
+        files.insert(
+            file,
+            toml::to_string_pretty(&content).map_err(folder_error)?,
+        );
+    }
+    if !data.recurrences.is_empty() {
+        let content = RecurrencesFile {
+            recurrences: data.recurrences,
+        };
+        files.insert(
+            PathBuf::from(RECURRENCES_FILE),
+            toml::to_string_pretty(&content).map_err(folder_error)?,
+        );
+    }
+    if !data.tags.is_empty() {
+        let mut tags = data.tags;
+        tags.sort_by(|a, b| (a.created_at, &a.uuid).cmp(&(b.created_at, &b.uuid)));
+        let mut item_tags = data.item_tags;
+        item_tags.sort();
+        let content = TagsFile { tags, item_tags };
+        files.insert(
+            PathBuf::from(TAGS_FILE),
+            toml::to_string_pretty(&content).map_err(folder_error)?,
+        );
+    }
+    if !data.templates.is_empty() {
+        let mut templates = data.templates;
+        templates.sort_by(|a, b| (a.created_at, &a.uuid).cmp(&(b.created_at, &b.uuid)));
+        let content = TemplatesFile { templates };
+        files.insert(
+            PathBuf::from(TEMPLATES_FILE),
+            toml::to_string_pretty(&content).map_err(folder_error)?,
+        );
+    }
+    Ok(files)
+}
+
+// the data of the folder, and the content of its files
+type FolderContent = (FolderData, BTreeMap<PathBuf, String>);
+
+// read all the files of the folder.
+// an item in more than one file (like a conflicted copy of a file sync
+// service) is taken from the file where it's updated last.
+fn read_folder(path: &Path) -> AppResult<FolderContent> {
+    let mut files = BTreeMap::new();
+    let mut by_uuid: HashMap<String, FileItem> = HashMap::new();
+    let mut order: Vec<String> = Vec::new();
+    for folder in [WEEKS_FOLDER, OBJECTIVES_FOLDER] {
+        let Ok(entries) = fs::read_dir(path.join(folder)) else {
+            continue;
+        };
+        let mut names: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|file| file.extension().is_some_and(|ext| ext == FILE_EXTENSION))
+            .collect();
+        names.sort();
+        for file in names {
+            let content = fs::read_to_string(&file).map_err(folder_error)?;
+            let parsed: ItemsFile = toml::from_str(&content)
+                .map_err(|e| folder_error(format!("{}: {e}", file.to_string_lossy())))?;
+            for mut file_item in parsed.items {
+                if file_item.uuid.is_empty() {
+                    file_item.uuid = cuid2::create_id();
+                }
+                match by_uuid.get(&file_item.uuid) {
+                    Some(known) if known.updated_at >= file_item.updated_at => {}
+                    Some(_) => {
+                        by_uuid.insert(file_item.uuid.clone(), file_item);
+                    }
+                    None => {
+                        order.push(file_item.uuid.clone());
+                        by_uuid.insert(file_item.uuid.clone(), file_item);
+                    }
+                }
+            }
+            let relative = file.strip_prefix(path).unwrap_or(&file).to_path_buf();
+            files.insert(relative, content);
+        }
+    }
+    let items = order
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, uuid)| {
+            by_uuid
+                .remove(&uuid)
+                .map(|file_item| file_item.into_item(i as i32 + 1))
+        })
+        .collect();
+    let mut recurrences = Vec::new();
+    let file = path.join(RECURRENCES_FILE);
+    if file.is_file() {
+        let content = fs::read_to_string(&file).map_err(folder_error)?;
+        let parsed: RecurrencesFile = toml::from_str(&content)
+            .map_err(|e| folder_error(format!("{RECURRENCES_FILE}: {e}")))?;
+        recurrences = parsed.recurrences;
+        files.insert(PathBuf::from(RECURRENCES_FILE), content);
+    }
+    let mut tags_file = TagsFile::default();
+    let file = path.join(TAGS_FILE);
+    if file.is_file() {
+        let content = fs::read_to_string(&file).map_err(folder_error)?;
+        tags_file =
+            toml::from_str(&content).map_err(|e| folder_error(format!("{TAGS_FILE}: {e}")))?;
+        files.insert(PathBuf::from(TAGS_FILE), content);
+    }
+    let mut templates_file = TemplatesFile::default();
+    let file = path.join(TEMPLATES_FILE);
+    if file.is_file() {
+        let content = fs::read_to_string(&file).map_err(folder_error)?;
+        templates_file =
+            toml::from_str(&content).map_err(|e| folder_error(format!("{TEMPLATES_FILE}: {e}")))?;
+        files.insert(PathBuf::from(TEMPLATES_FILE), content);
+    }
+    let data = FolderData {
+        items,
+        recurrences,
+        tags: tags_file.tags,
+        item_tags: tags_file.item_tags,
+        templates: templates_file.templates,
+    };
+    Ok((data, files))
+}
+
+fn memory_of(data: FolderData) -> AppResult<MemoryStorage> {
+    let memory = MemoryStorage::with_items(data.items);
+    for recurrence in data.recurrences {
+        memory.save_recurrence(&recurrence)?;
+    }
+    for tag in data.tags {
+        memory.save_tag(&tag)?;
+    }
+    for item_tag in data.item_tags {
+        memory.save_item_tag(&item_tag)?;
+    }
+    for template in data.templates {
+        memory.save_template(&template)?;
+    }
+    Ok(memory)
+}
+
+impl FolderStorage {
+    /// open the folder, it's created if it doesn't exist
+    pub fn open<P: Into<PathBuf>>(path: P) -> AppResult<Self> {
+        let path: PathBuf = path.into();
+        fs::create_dir_all(&path).map_err(folder_error)?;
+        let (data, files) = read_folder(&path)?;
+        Ok(FolderStorage {
+            path,
+            memory: memory_of(data)?,
+            files: Mutex::new(files),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// read the folder again, to see the changes made to the files outside
+    /// the app. the undo/redo history is dropped.
+    pub fn reload(&mut self) -> AppResult<()> {
+        let (data, files) = read_folder(&self.path)?;
+        self.memory = memory_of(data)?;
+        self.files = Mutex::new(files);
+        Ok(())
+    }
+
+    /// all the items, the trashed ones too
+    pub fn all_items(&self) -> Vec<Item> {
+        self.memory.all_items()
+    }
+
+    fn files(&self) -> MutexGuard<'_, BTreeMap<PathBuf, String>> {
+        self.files.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // write the files that are changed and remove the ones without items.
+    // `files` is the locked content, so changes are saved one at a time.
+    fn save(&self, files: &mut BTreeMap<PathBuf, String>) -> AppResult<()> {
+        let rendered = render_files(FolderData {
+            items: self.memory.all_items(),
+            recurrences: self.memory.read_recurrences()?,
+            tags: self.memory.read_tags()?,
+            item_tags: self.memory.all_item_tags(),
+            templates: self.memory.read_templates()?,
+        })?;
+        for (file, content) in rendered.iter() {
+            if files.get(file) == Some(content) {
+                continue;
+            }
+            let full_path = self.path.join(file);
+            if let Some(parent) = full_path.parent() {
+                fs::create_dir_all(parent).map_err(folder_error)?;
+            }
+            // write the whole file at once, a sync service should never see half of it
+            let temp = full_path.with_extension("toml.tmp");
+            fs::write(&temp, content).map_err(folder_error)?;
+            fs::rename(&temp, &full_path).map_err(folder_error)?;
+        }
+        for file in files.keys() {
+            if !rendered.contains_key(file) {
+                fs::remove_file(self.path.join(file)).map_err(folder_error)?;
+            }
+        }
+        *files = rendered;
+        Ok(())
+    }
+
+    // run the change on the memory, then save the files
+    fn change<T, F>(&self, f: F) -> AppResult<T>
+    where
+        F: FnOnce(&MemoryStorage) -> AppResult<T>,
+    {
+        let mut files = self.files();
+        let result = f(&self.memory)?;
+        self.save(&mut files)?;
+        Ok(result)
+    }
+}
+
+// the items of the folder are identified by their uuid, so they need one
+fn with_uuid(new_item: &NewItem) -> NewItem {
+    let mut new_item = new_item.clone();
+    if new_item.uuid.is_none() {
+        new_item.uuid = Some(cuid2::create_id());
+    }
+    new_item
+}
+
+impl Storage for FolderStorage {
+    fn create_item(&self, new_item: &NewItem) -> AppResult<i32> {
+        self.change(|memory| memory.create_item(&with_uuid(new_item)))
+    }
+
+    fn get_item(&self, id: i32) -> AppResult<Item> {
+        self.memory.get_item(id)
+    }
+
+    fn update_item_labeled(&self, label: &str, item: &Item) -> AppResult<usize> {
+        self.change(|memory| memory.update_item_labeled(label, item))
+    }
+
+    fn remove_item(&self, id: i32) -> AppResult<usize> {
+        self.change(|memory| memory.remove_item(id))
+    }
+
+    fn read_items_between_days(&self, start_day: i32, end_day: i32) -> AppResult<Vec<Item>> {
+        self.memory.read_items_between_days(start_day, end_day)
+    }
+
+    fn read_items_in_calendar_year(&self, calendar: i32, year: i32) -> AppResult<Vec<Item>> {
+        self.memory.read_items_in_calendar_year(calendar, year)
+    }
+
+    fn apply_batch(&self, label: Option<&str>, ops: &[BatchOp]) -> AppResult<BatchResult> {
+        let ops: Vec<BatchOp> = ops
+            .iter()
+            .map(|op| match op {
+                BatchOp::Insert(new_item) => BatchOp::Insert(with_uuid(new_item)),
+                op => op.clone(),
+            })
+            .collect();
+        self.change(|memory| memory.apply_batch(label, &ops))
+    }
+
+    fn undo(&self) -> AppResult<Option<String>> {
+        self.change(|memory| memory.undo())
+    }
+
+    fn redo(&self) -> AppResult<Option<String>> {
+        self.change(|memory| memory.redo())
+    }
+
+    fn read_recurrences(&self) -> AppResult<Vec<Recurrence>> {
+        self.memory.read_recurrences()
+    }
+
+    fn save_recurrence(&self, recurrence: &Recurrence) -> AppResult<()> {
+        self.change(|memory| memory.save_recurrence(recurrence))
+    }
+
+    fn read_occurrences(&self, recurrence_uuid: &str) -> AppResult<Vec<Item>> {
+        self.memory.read_occurrences(recurrence_uuid)
+    }
+
+    fn read_tags(&self) -> AppResult<Vec<Tag>> {
+        self.memory.read_tags()
+    }
+
+    fn save_tag(&self, tag: &Tag) -> AppResult<()> {
+        self.change(|memory| memory.save_tag(tag))
+    }
+
+    fn delete_tag(&self, uuid: &str) -> AppResult<()> {
+        self.change(|memory| memory.delete_tag(uuid))
+    }
+
+    fn read_item_tags(&self, item_uuids: &[String]) -> AppResult<Vec<ItemTag>> {
+        self.memory.read_item_tags(item_uuids)
+    }
+
+    fn save_item_tag(&self, item_tag: &ItemTag) -> AppResult<()> {
+        self.change(|memory| memory.save_item_tag(item_tag))
+    }
+
+    fn delete_item_tag(&self, item_tag: &ItemTag) -> AppResult<()> {
+        self.change(|memory| memory.delete_item_tag(item_tag))
+    }
+
+    fn read_tagged_items(&self, tag_uuid: &str) -> AppResult<Vec<Item>> {
+        self.memory.read_tagged_items(tag_uuid)
+    }
+
+    fn read_items_by_uuids(&self, uuids: &[String]) -> AppResult<Vec<Item>> {
+        self.memory.read_items_by_uuids(uuids)
+    }
+
+    fn read_linked_goals(&self, objective_uuids: &[String]) -> AppResult<Vec<Item>> {
+        self.memory.read_linked_goals(objective_uuids)
+    }
+
+    fn read_templates(&self) -> AppResult<Vec<Template>> {
+        self.memory.read_templates()
+    }
+
+    fn save_template(&self, template: &Template) -> AppResult<()> {
+        self.change(|memory| memory.save_template(template))
+    }
+
+    fn delete_template(&self, uuid: &str) -> AppResult<()> {
+        self.change(|memory| memory.delete_template(uuid))
+    }
+}
+
+/* Converters */
+
+/// write all the items of the current database (the trashed ones too), the
+/// recurrence rules, the tags and the templates into the folder. the folder
+/// should not have any items yet. returns the number of written items.
+pub fn export_sqlite_to_folder<P: Into<PathBuf>>(path: P) -> AppResult<usize> {
+    let storage = FolderStorage::open(path)?;
+    if !storage.all_items().is_empty() {
+        return Err(AppError::DatabaseFileNotEmptyError);
+    }
+    let items: Vec<Item> = db_sqlite::with_connection(|conn| {
+        crate::schema::items::table
+            .select(Item::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+    })?;
+    let count = items.len();
+    let items: Vec<Item> = items
+        .into_iter()
+        .map(|mut item| {
+            // the folder has no sync state, the file sync service does that
+            item.sync = None;
+            item.uuid = item.uuid.or_else(|| Some(cuid2::create_id()));
+            item
+        })
+        .collect();
+    let storage = FolderStorage {
+        memory: memory_of(FolderData {
+            items,
+            recurrences: recurrence::find_recurrences()?,
+            tags: tags::find_tags()?,
+            item_tags: tags::find_all_item_tags()?,
+            templates: templates::find_templates()?,
+        })?,
+        ..storage
+    };
+    let mut files = storage.files();
+    storage.save(&mut files)?;
+    Ok(count)
+}
+
+/// copy the items, the recurrence rules, the tags and the templates of the
+/// folder into the current database, as one undoable action. the ones already
+/// in the database (by uuid) are updated with the version of the folder, the
+/// others are added. returns the number of added and updated items.
+pub fn import_folder_to_sqlite<P: Into<PathBuf>>(path: P) -> AppResult<usize> {
+    let path: PathBuf = path.into();
+    if !path.is_dir() {
+        return Err(AppError::DatabaseFileDontExistsError);
+    }
+    let storage = FolderStorage::open(path)?;
+    let local: HashMap<String, Item> = db_sqlite::with_connection(|conn| {
+        crate::schema::items::table
+            .select(Item::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+    })?
+    .into_iter()
+    .filter_map(|item| item.uuid.clone().map(|uuid| (uuid, item)))
+    .collect();
+    let mut ops: Vec<BatchOp> = Vec::new();
+    for item in storage.all_items() {
+        let local = item.uuid.as_ref().and_then(|uuid| local.get(uuid));
+        match local {
+            Some(local) => {
+                let mut item = item.clone();
+                item.id = local.id;
+                item.sync = local.sync;
+                if item != *local {
+                    ops.push(BatchOp::Update(item));
+                }
+            }
+            None => ops.push(BatchOp::Insert(NewItem::copy_of(&item))),
+        }
+    }
+    let count = ops.len();
+
+    let local_rules = recurrence::find_recurrences()?;
+    for rule in storage.read_recurrences()? {
+        if !local_rules.contains(&rule) {
+            ops.push(BatchOp::SaveRecurrence(rule));
+        }
+    }
+    let local_tags = tags::find_tags()?;
+    for tag in storage.read_tags()? {
+        if !local_tags.contains(&tag) {
+            ops.push(BatchOp::SaveTag(tag));
+        }
+    }
+    // after the items, they are linked by their uuids
+    let local_links = tags::find_all_item_tags()?;
+    for link in storage.memory.all_item_tags() {
+        if !local_links.contains(&link) {
+            ops.push(BatchOp::Tag(link));
+        }
+    }
+    let local_templates = templates::find_templates()?;
+    for template in storage.read_templates()? {
+        if !local_templates.contains(&template) {
+            ops.push(BatchOp::SaveTemplate(template));
+        }
+    }
+    if ops.is_empty() {
+        return Ok(0);
+    }
+    db_sqlite::apply_batch_journaled("import folder", &ops)?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::db_sqlite::{new_test_goal, TestDatabase};
+    use crate::storage::SqliteStorage;
+    use crate::templates::TemplateItem;
+
+    const DAY: i32 = 20000;
+
+    fn titles(storage: &dyn Storage) -> Vec<String> {
+        storage
+            .read_items_between_days(DAY, DAY)
+            .unwrap()
+            .into_iter()
+            .filter_map(|item| item.title)
+            .collect()
+    }
+
+    #[test]
+    fn test_folder_round_trip() {
+        let db = TestDatabase::new();
+        let first = db_sqlite::create_item(&new_test_goal(DAY, "first", "m")).unwrap();
+        let second = db_sqlite::create_item(&new_test_goal(DAY, "second", "t")).unwrap();
+        let trashed = db_sqlite::create_item(&new_test_goal(DAY, "trashed", "w")).unwrap();
+        db_sqlite::trash_item(trashed).unwrap();
+        tags::tag_item(&SqliteStorage, first, "home").unwrap();
+        let items = vec![TemplateItem {
+            kind: crate::models::ItemKind::Goal,
+            text: "plan".into(),
+            subitems: vec![],
+        }];
+        templates::save_template(&SqliteStorage, "weekly", items).unwrap();
+
+        let folder = db.file("folder");
+        assert_eq!(export_sqlite_to_folder(&folder).unwrap(), 3);
+        assert!(export_sqlite_to_folder(&folder).is_err());
+        let storage = FolderStorage::open(&folder).unwrap();
+        assert_eq!(titles(&storage), vec!["first", "second"]);
+        assert_eq!(storage.read_tags().unwrap()[0].name, "home");
+        assert_eq!(storage.read_templates().unwrap()[0].name, "weekly");
+
+        // the changes are written to the files
+        storage.edit_item_text(first, "changed".into()).unwrap();
+        let storage = FolderStorage::open(&folder).unwrap();
+        assert_eq!(titles(&storage), vec!["changed", "second"]);
+
+        let exported = db_sqlite::find_item(second).unwrap();
+        let other = db.file("other.db");
+        db_sqlite::create_db(&other).unwrap();
+        config::set_config(config::Config {
+            database: other,
+            ..config::get_config()
+        });
+        assert_eq!(import_folder_to_sqlite(&folder).unwrap(), 3);
+        assert_eq!(titles(&SqliteStorage), vec!["changed", "second"]);
+        // the trashed item stays in the trash, and the timestamps are kept
+        let trash = db_sqlite::list_trash().unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].title.as_deref(), Some("trashed"));
+        let imported = SqliteStorage
+            .read_items_by_uuids(&[exported.uuid.clone().unwrap()])
+            .unwrap()
+            .remove(0);
+        assert_eq!(imported.created_at, exported.created_at);
+        assert_eq!(imported.updated_at, exported.updated_at);
+        assert_eq!(tags::find_tags().unwrap().len(), 1);
+        assert_eq!(templates::find_templates().unwrap().len(), 1);
+        // nothing new the second time
+        assert_eq!(import_folder_to_sqlite(&folder).unwrap(), 0);
+        // the whole import is undone
+        assert_eq!(
+            crate::journal::undo().unwrap().as_deref(),
+            Some("import folder")
+        );
+        assert!(titles(&SqliteStorage).is_empty());
+        assert!(tags::find_tags().unwrap().is_empty());
+        assert!(tags::find_all_item_tags().unwrap().is_empty());
+        assert!(templates::find_templates().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_objectives_are_kept_per_period() {
+        let db = TestDatabase::new();
+        let folder = db.file("folder");
+        let storage = FolderStorage::open(&folder).unwrap();
+        let calendar = crate::calendar::CALENDAR_GREGORIAN;
+        for (season, month) in [(None, None), (Some(2), None), (None, Some(11))] {
+            let objective = NewItem::new(
+                calendar,
+                Some(2024),
+                season,
+                month,
+                0,
+                ItemKind::Goal,
+                "objective".into(),
+                "m".into(),
+            );
+            storage.create_item(&objective).unwrap();
+        }
+        let mut files: Vec<String> = fs::read_dir(Path::new(&folder).join(OBJECTIVES_FOLDER))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                "gregorian-2024-month11.toml",
+                "gregorian-2024-season2.toml",
+                "gregorian-2024.toml"
+            ]
+        );
+        let storage = FolderStorage::open(&folder).unwrap();
+        assert_eq!(
+            storage
+                .read_items_in_calendar_year(calendar, 2024)
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+}
@@ -0,0 +1,593 @@
+use super::Storage;
+use crate::db_sqlite::{BatchOp, BatchResult};
+use crate::journal::JOURNAL_MAX_ACTIONS;
+use crate::models::{Item, ItemStatus, NewItem};
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::recurrence::Recurrence;
+use crate::tags::{ItemTag, Tag};
+use crate::templates::Template;
+use crate::time;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Mutex, MutexGuard};
+
+/// everything is kept in memory and lost when dropped.
+/// it behaves like the sqlite storage: soft delete, timestamps and undo/redo.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    state: Mutex<MemoryState>,
+}
+
+// one record and its value, none when it's not there
+#[derive(Debug, Clone, PartialEq)]
+enum Record {
+    Item(i32, Option<Box<Item>>),
+    Recurrence(String, Option<Recurrence>),
+    Tag(String, Option<Tag>),
+    ItemTag(ItemTag, bool),
+    Template(String, Option<Template>),
+}
+
+impl Record {
+    fn is_same(&self, other: &Record) -> bool {
+        match (self, other) {
+            (Record::Item(a, _), Record::Item(b, _)) => a == b,
+            (Record::Recurrence(a, _), Record::Recurrence(b, _))
+            | (Record::Tag(a, _), Record::Tag(b, _))
+            | (Record::Template(a, _), Record::Template(b, _)) => a == b,
+            (Record::ItemTag(a, _), Record::ItemTag(b, _)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct MemoryData {
+    items: BTreeMap<i32, Item>,
+    last_id: i32,
+    recurrences: BTreeMap<String, Recurrence>,
+    tags: BTreeMap<String, Tag>,
+    item_tags: BTreeSet<ItemTag>,
+    templates: BTreeMap<String, Template>,
+    // the records changed by the running change, as they were before it
+    before: Vec<Record>,
+}
+
+// the data is changed in place. undo/redo keep the records changed by each
+// action, as they were before/after it.
+#[derive(Debug, Default)]
+struct MemoryState {
+    data: MemoryData,
+    undo: Vec<(String, Vec<Record>)>,
+    redo: Vec<(String, Vec<Record>)>,
+}
+
+impl MemoryData {
+    // the current value of the record
+    fn current(&self, record: &Record) -> Record {
+        match record {
+            Record::Item(id, _) => Record::Item(*id, self.items.get(id).cloned().map(Box::new)),
+            Record::Recurrence(uuid, _) => {
+                Record::Recurrence(uuid.clone(), self.recurrences.get(uuid).cloned())
+            }
+            Record::Tag(uuid, _) => Record::Tag(uuid.clone(), self.tags.get(uuid).cloned()),
+            Record::ItemTag(link, _) => {
+                Record::ItemTag(link.clone(), self.item_tags.contains(link))
+            }
+            Record::Template(uuid, _) => {
+                Record::Template(uuid.clone(), self.templates.get(uuid).cloned())
+            }
+        }
+    }
+
+    fn set(&mut self, record: Record) {
+        match record {
+            Record::Item(id, Some(item)) => {
+                self.items.insert(id, *item);
+            }
+            Record::Item(id, None) => {
+                self.items.remove(&id);
+            }
+            Record::Recurrence(uuid, Some(rule)) => {
+                self.recurrences.insert(uuid, rule);
+            }
+            Record::Recurrence(uuid, None) => {
+                self.recurrences.remove(&uuid);
+            }
+            Record::Tag(uuid, Some(tag)) => {
+                self.tags.insert(uuid, tag);
+            }
+            Record::Tag(uuid, None) => {
+                self.tags.remove(&uuid);
+            }
+            Record::ItemTag(link, true) => {
+                self.item_tags.insert(link);
+            }
+            Record::ItemTag(link, false) => {
+                self.item_tags.remove(&link);
+            }
+            Record::Template(uuid, Some(template)) => {
+                self.templates.insert(uuid, template);
+            }
+            Record::Template(uuid, None) => {
+                self.templates.remove(&uuid);
+            }
+        }
+    }
+
+    // set the record, keeping how it was before the first write of the change
+    fn write(&mut self, record: Record) {
+        if !self.before.iter().any(|before| before.is_same(&record)) {
+            let before = self.current(&record);
+            self.before.push(before);
+        }
+        self.set(record);
+    }
+
+    // set the records back, returns how they were
+    fn restore(&mut self, records: Vec<Record>) -> Vec<Record> {
+        records
+            .into_iter()
+            .rev()
+            .map(|record| {
+                let current = self.current(&record);
+                self.set(record);
+                current
+            })
+            .collect()
+    }
+
+    fn insert(&mut self, new_item: &NewItem) -> i32 {
+        let now = time::get_current_timestamp();
+        // a copy of an existing item keeps its timestamps
+        let is_copy = new_item.updated_at.is_some();
+        self.last_id += 1;
+        let item = Item {
+            id: self.last_id,
+            calendar: new_item.calendar,
+            year: new_item.year,
+            season: new_item.season,
+            month: new_item.month,
+            day: new_item.day,
+            kind: new_item.kind,
+            fixed_date: new_item.fixed_date,
+            all_day: new_item.all_day,
+            title: new_item.title.clone(),
+            note: new_item.note.clone(),
+            datetime: new_item.datetime.clone(),
+            duration: new_item.duration,
+            status: new_item.status,
+            order_in_week: new_item.order_in_week.clone(),
+            order_in_resolution: new_item.order_in_resolution.clone(),
+            sync: new_item.sync,
+            uuid: new_item.uuid.clone(),
+            deleted_at: new_item.deleted_at,
+            created_at: new_item.created_at.or(Some(now)),
+            updated_at: new_item.updated_at.or(Some(now)),
+            completed_at: if new_item.status != Some(ItemStatus::Done) {
+                None
+            } else if is_copy {
+                new_item.completed_at
+            } else {
+                new_item.completed_at.or(Some(now))
+            },
+            status_reason: new_item.status_reason.clone(),
+            parent_uuid: new_item.parent_uuid.clone(),
+            objective_uuid: new_item.objective_uuid.clone(),
+            carry_count: new_item.carry_count,
+            carried_from: new_item.carried_from.clone(),
+            carried_to: new_item.carried_to.clone(),
+            recurrence_uuid: new_item.recurrence_uuid.clone(),
+            occurrence_key: new_item.occurrence_key.clone(),
+        };
+        self.write(Record::Item(item.id, Some(Box::new(item))));
+        self.last_id
+    }
+
+    fn update(&mut self, item: &Item) -> usize {
+        let Some(before) = self.items.get(&item.id) else {
+            return 0;
+        };
+        let now = time::get_current_timestamp();
+        let mut item = item.clone();
+        item.created_at = before.created_at;
+        item.updated_at = before.updated_at;
+        let was_done = before.status == Some(ItemStatus::Done);
+        let is_done = item.status == Some(ItemStatus::Done);
+        item.completed_at = match (was_done, is_done) {
+            (false, true) => item.completed_at.or(Some(now)),
+            (true, true) => before.completed_at,
+            (_, false) => None,
+        };
+        if item != *before {
+            item.updated_at = Some(now);
+            self.write(Record::Item(item.id, Some(Box::new(item))));
+        }
+        1
+    }
+
+    fn apply(&mut self, ops: &[BatchOp]) -> AppResult<BatchResult> {
+        let mut result = BatchResult::default();
+        for op in ops {
+            match op {
+                BatchOp::Insert(new_item) => result.inserted_ids.push(self.insert(new_item)),
+                BatchOp::Update(item) => {
+                    if self.update(item) == 0 {
+                        return Err(AppError::DatabaseUpdateError(format!(
+                            "item not found. id: {}",
+                            item.id
+                        )));
+                    }
+                    result.updated += 1;
+                }
+                BatchOp::Delete(item_id) => {
+                    let Some(item) = self.items.get(item_id).cloned() else {
+                        return Err(AppError::DatabaseDeleteError(format!(
+                            "item not found. id: {item_id}"
+                        )));
+                    };
+                    // the occurrence is removed for good from its rule
+                    if let (Some(rule_uuid), Some(key)) =
+                        (&item.recurrence_uuid, &item.occurrence_key)
+                    {
+                        if let Some(mut rule) = self.recurrences.get(rule_uuid).cloned() {
+                            rule.skip(key);
+                            self.write(Record::Recurrence(rule.uuid.clone(), Some(rule)));
+                        }
+                    }
+                    self.write(Record::Item(*item_id, None));
+                    result.deleted += 1;
+                }
+                BatchOp::Tag(link) => self.write(Record::ItemTag(link.clone(), true)),
+                BatchOp::SaveTag(tag) => {
+                    self.write(Record::Tag(tag.uuid.clone(), Some(tag.clone())))
+                }
+                BatchOp::SaveRecurrence(rule) => {
+                    self.write(Record::Recurrence(rule.uuid.clone(), Some(rule.clone())))
+                }
+                BatchOp::SaveTemplate(template) => self.write(Record::Template(
+                    template.uuid.clone(),
+                    Some(template.clone()),
+                )),
+            }
+        }
+        self.remove_dangling_links();
+        Ok(result)
+    }
+
+    // the links of the items that are not here anymore (by undo or delete)
+    // are removed, like in the sqlite storage
+    fn remove_dangling_links(&mut self) {
+        let uuids: BTreeSet<&String> = self
+            .items
+            .values()
+            .filter_map(|item| item.uuid.as_ref())
+            .collect();
+        let dangling: Vec<ItemTag> = self
+            .item_tags
+            .iter()
+            .filter(|item_tag| !uuids.contains(&item_tag.item_uuid))
+            .cloned()
+            .collect();
+        for link in dangling {
+            self.write(Record::ItemTag(link, false));
+        }
+    }
+}
+
+impl MemoryState {
+    // run `f` on the data. if `f` fails, the records it changed are set back.
+    // with a label, the change is recorded as an undoable action.
+    fn change<T, F>(&mut self, label: Option<&str>, f: F) -> AppResult<T>
+    where
+        F: FnOnce(&mut MemoryData) -> AppResult<T>,
+    {
+        let result = f(&mut self.data);
+        let before = std::mem::take(&mut self.data.before);
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => {
+                self.data.restore(before);
+                return Err(e);
+            }
+        };
+        let before: Vec<Record> = before
+            .into_iter()
+            .filter(|record| self.data.current(record) != *record)
+            .collect();
+        if let Some(label) = label {
+            if !before.is_empty() {
+                self.redo.clear();
+                self.undo.push((label.to_string(), before));
+                if self.undo.len() > JOURNAL_MAX_ACTIONS as usize {
+                    self.undo.remove(0);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    // set the records of an undo/redo action back, and remove the links the
+    // items lost on the way. those are not part of the action.
+    fn restore(&mut self, records: Vec<Record>) -> Vec<Record> {
+        let records = self.data.restore(records);
+        self.data.remove_dangling_links();
+        self.data.before.clear();
+        records
+    }
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// a storage with these items, keeping their ids
+    pub(crate) fn with_items(items: Vec<Item>) -> Self {
+        let data = MemoryData {
+            last_id: items.iter().map(|item| item.id).max().unwrap_or(0),
+            items: items.into_iter().map(|item| (item.id, item)).collect(),
+            ..Default::default()
+        };
+        MemoryStorage {
+            state: Mutex::new(MemoryState {
+                data,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// all the items, the trashed ones too
+    pub(crate) fn all_items(&self) -> Vec<Item> {
+        self.state().data.items.values().cloned().collect()
+    }
+
+    /// all the links of the items and the tags
+    pub(crate) fn all_item_tags(&self) -> Vec<ItemTag> {
+        self.state().data.item_tags.iter().cloned().collect()
+    }
+
+    fn state(&self) -> MutexGuard<'_, MemoryState> {
+        // a panic while holding the lock can not leave the data half changed
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn create_item(&self, new_item: &NewItem) -> AppResult<i32> {
+        self.state()
+            .change(Some("add item"), |data| Ok(data.insert(new_item)))
+    }
+
+    fn get_item(&self, id: i32) -> AppResult<Item> {
+        self.state()
+            .data
+            .items
+            .get(&id)
+            .cloned()
+            .ok_or(AppError::DatabaseSelectError(format!(
+                "item not found. id: {id}"
+            )))
+    }
+
+    fn update_item_labeled(&self, label: &str, item: &Item) -> AppResult<usize> {
+        self.state()
+            .change(Some(label), |data| Ok(data.update(item)))
+    }
+
+    fn remove_item(&self, id: i32) -> AppResult<usize> {
+        self.state()
+            .change(Some("remove item"), |data| match data.items.get(&id) {
+                Some(item) if item.deleted_at.is_none() => {
+                    let mut item = item.clone();
+                    item.deleted_at = Some(time::get_current_timestamp());
+                    Ok(data.update(&item))
+                }
+                _ => Ok(0),
+            })
+    }
+
+    fn read_items_between_days(&self, start_day: i32, end_day: i32) -> AppResult<Vec<Item>> {
+        let mut items: Vec<Item> = self
+            .state()
+            .data
+            .items
+            .values()
+            .filter(|item| item.deleted_at.is_none())
+            .filter(|item| item.day >= start_day && item.day <= end_day)
+            .cloned()
+            .collect();
+        items.sort_by(|a, b| (&a.order_in_week, a.id).cmp(&(&b.order_in_week, b.id)));
+        Ok(items)
+    }
+
+    fn read_items_in_calendar_year(&self, calendar: i32, year: i32) -> AppResult<Vec<Item>> {
+        let mut items: Vec<Item> = self
+            .state()
+            .data
+            .items
+            .values()
+            .filter(|item| item.deleted_at.is_none())
+            .filter(|item| item.calendar == calendar && item.year == Some(year))
+            .cloned()
+            .collect();
+        items.sort_by(|a, b| (&a.order_in_resolution, a.id).cmp(&(&b.order_in_resolution, b.id)));
+        Ok(items)
+    }
+
+    fn apply_batch(&self, label: Option<&str>, ops: &[BatchOp]) -> AppResult<BatchResult> {
+        self.state().change(label, |data| data.apply(ops))
+    }
+
+    fn read_recurrences(&self) -> AppResult<Vec<Recurrence>> {
+        let mut recurrences: Vec<Recurrence> =
+            self.state().data.recurrences.values().cloned().collect();
+        recurrences.sort_by_key(|recurrence| recurrence.created_at);
+        Ok(recurrences)
+    }
+
+    fn save_recurrence(&self, recurrence: &Recurrence) -> AppResult<()> {
+        self.state().change(None, |data| {
+            data.write(Record::Recurrence(
+                recurrence.uuid.clone(),
+                Some(recurrence.clone()),
+            ));
+            Ok(())
+        })
+    }
+
+    fn read_occurrences(&self, recurrence_uuid: &str) -> AppResult<Vec<Item>> {
+        Ok(self
+            .state()
+            .data
+            .items
+            .values()
+            .filter(|item| item.recurrence_uuid.as_deref() == Some(recurrence_uuid))
+            .cloned()
+            .collect())
+    }
+
+    fn read_tags(&self) -> AppResult<Vec<Tag>> {
+        Ok(self.state().data.tags.values().cloned().collect())
+    }
+
+    fn save_tag(&self, tag: &Tag) -> AppResult<()> {
+        self.state().change(None, |data| {
+            data.write(Record::Tag(tag.uuid.clone(), Some(tag.clone())));
+            Ok(())
+        })
+    }
+
+    fn delete_tag(&self, uuid: &str) -> AppResult<()> {
+        self.state().change(None, |data| {
+            let links: Vec<ItemTag> = data
+                .item_tags
+                .iter()
+                .filter(|item_tag| item_tag.tag_uuid == uuid)
+                .cloned()
+                .collect();
+            for link in links {
+                data.write(Record::ItemTag(link, false));
+            }
+            data.write(Record::Tag(uuid.to_string(), None));
+            Ok(())
+        })
+    }
+
+    fn read_item_tags(&self, item_uuids: &[String]) -> AppResult<Vec<ItemTag>> {
+        Ok(self
+            .state()
+            .data
+            .item_tags
+            .iter()
+            .filter(|item_tag| item_uuids.contains(&item_tag.item_uuid))
+            .cloned()
+            .collect())
+    }
+
+    fn save_item_tag(&self, item_tag: &ItemTag) -> AppResult<()> {
+        self.state().change(None, |data| {
+            data.write(Record::ItemTag(item_tag.clone(), true));
+            Ok(())
+        })
+    }
+
+    fn delete_item_tag(&self, item_tag: &ItemTag) -> AppResult<()> {
+        self.state().change(None, |data| {
+            data.write(Record::ItemTag(item_tag.clone(), false));
+            Ok(())
+        })
+    }
+
+    fn read_tagged_items(&self, tag_uuid: &str) -> AppResult<Vec<Item>> {
+        let state = self.state();
+        let tagged: Vec<&String> = state
+            .data
+            .item_tags
+            .iter()
+            .filter(|item_tag| item_tag.tag_uuid == tag_uuid)
+            .map(|item_tag| &item_tag.item_uuid)
+            .collect();
+        Ok(state
+            .data
+            .items
+            .values()
+            .filter(|item| item.deleted_at.is_none())
+            .filter(|item| {
+                item.uuid
+                    .as_ref()
+                    .is_some_and(|uuid| tagged.contains(&uuid))
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn read_items_by_uuids(&self, uuids: &[String]) -> AppResult<Vec<Item>> {
+        Ok(self
+            .state()
+            .data
+            .items
+            .values()
+            .filter(|item| item.deleted_at.is_none())
+            .filter(|item| item.uuid.as_ref().is_some_and(|uuid| uuids.contains(uuid)))
+            .cloned()
+            .collect())
+    }
+
+    fn read_linked_goals(&self, objective_uuids: &[String]) -> AppResult<Vec<Item>> {
+        Ok(self
+            .state()
+            .data
+            .items
+            .values()
+            .filter(|item| item.deleted_at.is_none() && item.year.is_none())
+            .filter(|item| {
+                item.objective_uuid
+                    .as_ref()
+                    .is_some_and(|uuid| objective_uuids.contains(uuid))
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn read_templates(&self) -> AppResult<Vec<Template>> {
+        Ok(self.state().data.templates.values().cloned().collect())
+    }
+
+    fn save_template(&self, template: &Template) -> AppResult<()> {
+        self.state().change(None, |data| {
+            data.write(Record::Template(
+                template.uuid.clone(),
+                Some(template.clone()),
+            ));
+            Ok(())
+        })
+    }
+
+    fn delete_template(&self, uuid: &str) -> AppResult<()> {
+        self.state().change(None, |data| {
+            data.write(Record::Template(uuid.to_string(), None));
+            Ok(())
+        })
+    }
+
+    fn undo(&self) -> AppResult<Option<String>> {
+        let mut state = self.state();
+        let Some((label, before)) = state.undo.pop() else {
+            return Ok(None);
+        };
+        let after = state.restore(before);
+        state.redo.push((label.clone(), after));
+        Ok(Some(label))
+    }
+
+    fn redo(&self) -> AppResult<Option<String>> {
+        let mut state = self.state();
+        let Some((label, after)) = state.redo.pop() else {
+            return Ok(None);
+        };
+        let before = state.restore(after);
+        state.undo.push((label.clone(), before));
+        Ok(Some(label))
+    }
+}
@@ -0,0 +1,118 @@
+use super::Storage;
+use crate::db_sqlite::{self, BatchOp, BatchResult};
+use crate::journal;
+use crate::models::{Item, NewItem};
+use crate::objective_links;
+use crate::prelude::Result as AppResult;
+use crate::recurrence::{self, Recurrence};
+use crate::tags::{self, ItemTag, Tag};
+use crate::templates::{self, Template};
+
+/// the configured sqlite database, through the `db_sqlite` functions
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SqliteStorage;
+
+impl Storage for SqliteStorage {
+    fn create_item(&self, new_item: &NewItem) -> AppResult<i32> {
+        db_sqlite::create_item(new_item)
+    }
+
+    fn get_item(&self, id: i32) -> AppResult<Item> {
+        db_sqlite::find_item(id)
+    }
+
+    fn update_item_labeled(&self, label: &str, item: &Item) -> AppResult<usize> {
+        db_sqlite::update_item_journaled(label, item)
+    }
+
+    fn remove_item(&self, id: i32) -> AppResult<usize> {
+        db_sqlite::trash_item(id)
+    }
+
+    fn read_items_between_days(&self, start_day: i32, end_day: i32) -> AppResult<Vec<Item>> {
+        db_sqlite::read_items_between_days(start_day, end_day, true)
+    }
+
+    fn read_items_in_calendar_year(&self, calendar: i32, year: i32) -> AppResult<Vec<Item>> {
+        db_sqlite::find_items_in_calendar_year(calendar, year)
+    }
+
+    fn apply_batch(&self, label: Option<&str>, ops: &[BatchOp]) -> AppResult<BatchResult> {
+        match label {
+            Some(label) => db_sqlite::apply_batch_journaled(label, ops),
+            None => db_sqlite::apply_batch(ops),
+        }
+    }
+
+    fn undo(&self) -> AppResult<Option<String>> {
+        journal::undo()
+    }
+
+    fn redo(&self) -> AppResult<Option<String>> {
+        journal::redo()
+    }
+
+    fn read_recurrences(&self) -> AppResult<Vec<Recurrence>> {
+        recurrence::find_recurrences()
+    }
+
+    fn save_recurrence(&self, recurrence: &Recurrence) -> AppResult<()> {
+        recurrence::write_recurrence(recurrence)
+    }
+
+    fn read_occurrences(&self, recurrence_uuid: &str) -> AppResult<Vec<Item>> {
+        recurrence::find_occurrences(recurrence_uuid)
+    }
+
+    fn read_tags(&self) -> AppResult<Vec<Tag>> {
+        tags::find_tags()
+    }
+
+    fn save_tag(&self, tag: &Tag) -> AppResult<()> {
+        tags::write_tag(tag)
+    }
+
+    fn delete_tag(&self, uuid: &str) -> AppResult<()> {
+        tags::erase_tag(uuid)
+    }
+
+    fn read_item_tags(&self, item_uuids: &[String]) -> AppResult<Vec<ItemTag>> {
+        tags::find_item_tags(item_uuids)
+    }
+
+    fn save_item_tag(&self, item_tag: &ItemTag) -> AppResult<()> {
+        tags::write_item_tag(item_tag)
+    }
+
+    fn delete_item_tag(&self, item_tag: &ItemTag) -> AppResult<()> {
+        tags::erase_item_tag(item_tag)
+    }
+
+    fn read_tagged_items(&self, tag_uuid: &str) -> AppResult<Vec<Item>> {
+        tags::find_tagged_items(tag_uuid)
+    }
+
+    fn read_items_by_uuids(&self, uuids: &[String]) -> AppResult<Vec<Item>> {
+        objective_links::find_items_by_uuids(uuids)
+    }
+
+    fn read_linked_goals(&self, objective_uuids: &[String]) -> AppResult<Vec<Item>> {
+        objective_links::find_linked_goals(objective_uuids)
+    }
+
+    fn read_templates(&self) -> AppResult<Vec<Template>> {
+        templates::find_templates()
+    }
+
+    fn save_template(&self, template: &Template) -> AppResult<()> {
+        templates::write_template(template)
+    }
+
+    fn delete_template(&self, uuid: &str) -> AppResult<()> {
+        templates::erase_template(uuid)
+    }
+
+    fn update_items(&self, items: &[Item]) -> AppResult<usize> {
+        db_sqlite::update_items(items)
+    }
+}
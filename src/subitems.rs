@@ -0,0 +1,248 @@
+/* Subitems */
+
+// the goals and notes can have subitems, like the steps of a goal, and
+// those can have their own subitems. a subitem keeps the uuid of its parent
+// in `parent_uuid` and is in the same week (or objective period) as the
+// parent. the subitems of a parent are ordered among themselves, with the
+// ordering key of their list (`order_in_week` or `order_in_resolution`).
+// a subitem whose parent is not in the list anymore, like a trashed parent,
+// is shown as a top item.
+
+use crate::models::{Item, ItemKind, ItemStatus, ItemView, NewItem};
+use crate::ordering::{self, Ordering};
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::storage::Storage;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// the done goals under an item, at all levels. the cancelled ones are not counted.
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct Rollup {
+    pub done: usize,
+    pub total: usize,
+    /// all the goals under the item are done
+    pub complete: bool,
+}
+
+/// the subitems of a parent, as a list for the ordering keys
+#[derive(Debug)]
+pub struct Subitems {
+    pub items: Vec<Item>,
+    objectives: bool,
+    storage: Arc<dyn Storage>,
+}
+
+fn subitem_error<E: ToString>(e: E) -> AppError {
+    AppError::InvalidSubitemError(e.to_string())
+}
+
+impl Subitems {
+    /// the subitems of the parent uuid, in their order
+    pub fn of(
+        parent_uuid: Option<&str>,
+        subitems: &[Item],
+        objectives: bool,
+        storage: Arc<dyn Storage>,
+    ) -> Subitems {
+        Subitems {
+            items: subitems
+                .iter()
+                .filter(|item| {
+                    item.parent_uuid.is_some() && item.parent_uuid.as_deref() == parent_uuid
+                })
+                .cloned()
+                .collect(),
+            objectives,
+            storage,
+        }
+    }
+
+    fn key_of(&self, item: &Item) -> Option<String> {
+        if self.objectives {
+            item.order_in_resolution.clone()
+        } else {
+            item.order_in_week.clone()
+        }
+    }
+}
+
+impl Ordering for Subitems {
+    fn get_keys(&self) -> Vec<Option<String>> {
+        self.items.iter().map(|item| self.key_of(item)).collect()
+    }
+
+    fn set_ordering_key_of_posision(
+        &mut self,
+        i: usize,
+        key: Option<String>,
+    ) -> ordering::Result<()> {
+        let objectives = self.objectives;
+        let item = self.items.get_mut(i).ok_or("invalid pos".to_string())?;
+        if objectives {
+            item.order_in_resolution = key;
+        } else {
+            item.order_in_week = key;
+        }
+        Ok(())
+    }
+
+    fn get_ordering_key_of_id(&self, id: i32) -> Option<Option<String>> {
+        let item = self.items.iter().find(|item| item.id == id)?;
+        Some(self.key_of(item))
+    }
+
+    fn new_ordering_finished(&self) -> AppResult<()> {
+        self.storage.update_items(&self.items)?;
+        Ok(())
+    }
+}
+
+/// split the items of a list into the top items and the subitems, keeping
+/// their order. an item is a subitem when its parent is in the list, and
+/// it's not in a loop of parents.
+pub fn split_subitems(items: Vec<Item>) -> (Vec<Item>, Vec<Item>) {
+    let parents: HashMap<String, Option<String>> = items
+        .iter()
+        .filter_map(|item| {
+            item.uuid
+                .clone()
+                .map(|uuid| (uuid, item.parent_uuid.clone()))
+        })
+        .collect();
+    let is_subitem = |item: &Item| {
+        let mut seen: HashSet<&str> = HashSet::new();
+        let mut parent = item.parent_uuid.as_deref();
+        let mut depth = 0;
+        while let Some(uuid) = parent {
+            let Some(next) = parents.get(uuid) else {
+                break;
+            };
+            if !seen.insert(uuid) || Some(uuid) == item.uuid.as_deref() {
+                return false;
+            }
+            depth += 1;
+            parent = next.as_deref();
+        }
+        depth > 0
+    };
+    items.into_iter().partition(|item| !is_subitem(item))
+}
+
+/// the subitems under the item, at all levels
+pub fn descendants_of(item: &Item, subitems: &[Item]) -> Vec<Item> {
+    let mut descendants: Vec<Item> = Vec::new();
+    let mut parents: Vec<Option<String>> = vec![item.uuid.clone()];
+    while let Some(parent) = parents.pop() {
+        for subitem in subitems {
+            let is_child = parent.is_some() && subitem.parent_uuid == parent;
+            if is_child && subitem.id != item.id && !descendants.iter().any(|d| d.id == subitem.id)
+            {
+                parents.push(subitem.uuid.clone());
+                descendants.push(subitem.clone());
+            }
+        }
+    }
+    descendants
+}
+
+fn view_of(item: &Item, subitems: &[Item]) -> ItemView {
+    let mut view = ItemView::from(item);
+    view.children = subitems
+        .iter()
+        .filter(|subitem| item.uuid.is_some() && subitem.parent_uuid == item.uuid)
+        .map(|subitem| view_of(subitem, subitems))
+        .collect();
+    if !view.children.is_empty() {
+        let mut rollup = Rollup::default();
+        for child in view.children.iter() {
+            if child.kind == ItemKind::Goal && child.state != ItemStatus::Cancelled {
+                rollup.total += 1;
+                if child.state == ItemStatus::Done {
+                    rollup.done += 1;
+                }
+            }
+            if let Some(below) = &child.rollup {
+                rollup.done += below.done;
+                rollup.total += below.total;
+            }
+        }
+        rollup.complete = rollup.total > 0 && rollup.done == rollup.total;
+        view.rollup = Some(rollup);
+    }
+    view
+}
+
+/// the views of the top items, with their subitems nested
+pub fn nested_views(items: &[Item], subitems: &[Item]) -> Vec<ItemView> {
+    items.iter().map(|item| view_of(item, subitems)).collect()
+}
+
+/// give keys to the subitems without one, in each list of siblings
+pub(crate) fn fix_ordering(
+    subitems: &mut [Item],
+    objectives: bool,
+    storage: &Arc<dyn Storage>,
+) -> AppResult<()> {
+    let mut parents: Vec<Option<String>> = Vec::new();
+    for subitem in subitems.iter() {
+        if !parents.contains(&subitem.parent_uuid) {
+            parents.push(subitem.parent_uuid.clone());
+        }
+    }
+    for parent in parents {
+        let mut siblings = Subitems::of(parent.as_deref(), subitems, objectives, storage.clone());
+        if !siblings.needs_reordering() {
+            continue;
+        }
+        siblings.new_ordering()?;
+        for item in siblings.items {
+            if let Some(subitem) = subitems.iter_mut().find(|subitem| subitem.id == item.id) {
+                *subitem = item;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// a new subitem of the parent, in its week or objective period
+pub fn new_subitem(parent: &Item, kind: ItemKind, text: String, key: String) -> AppResult<NewItem> {
+    if parent.kind == ItemKind::Event || kind == ItemKind::Event {
+        return Err(subitem_error("only goals and notes can have subitems"));
+    }
+    let Some(parent_uuid) = parent.uuid.clone() else {
+        return Err(subitem_error(format!("item {} has no uuid", parent.id)));
+    };
+    let mut new_item = NewItem::new(
+        parent.calendar,
+        parent.year,
+        parent.season,
+        parent.month,
+        parent.day,
+        kind,
+        text,
+        key,
+    );
+    new_item.parent_uuid = Some(parent_uuid);
+    Ok(new_item)
+}
+
+/// check that the item can be put under the parent: not under itself or
+/// one of its own subitems, and no events
+pub fn check_new_parent(item: &Item, parent: &Item, subitems: &[Item]) -> AppResult<()> {
+    if item.kind == ItemKind::Event || parent.kind == ItemKind::Event {
+        return Err(subitem_error("only goals and notes can have subitems"));
+    }
+    if parent.uuid.is_none() {
+        return Err(subitem_error(format!("item {} has no uuid", parent.id)));
+    }
+    if item.id == parent.id
+        || descendants_of(item, subitems)
+            .iter()
+            .any(|d| d.id == parent.id)
+    {
+        return Err(subitem_error("an item can not be under itself"));
+    }
+    Ok(())
+}
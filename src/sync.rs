@@ -0,0 +1,769 @@
+/* Sync */
+
+// offline-first sync of the items between devices, through a remote.
+//   - every local change of an item field is logged in `sync_log`, and the
+//     item is marked dirty (the `sync` column) until the change is pushed.
+//     nothing is logged before the first sync: then all the items are logged
+//     at once, and pushing removes the pushed entries.
+//   - push sends the logged changes to the remote, pull gets the changes of
+//     the other devices
+//   - conflicts are resolved per field: the latest write of each field wins.
+//     `sync_clock` keeps when (and on which device) each field was written.
+// items are matched by their uuid. hard deletes are synced as the "purged" field.
+// only the items are synced: the tags, the links of the items to them, the
+// recurrence rules and the templates stay on each device. a purged item loses
+// its links to the local tags.
+// a remote is anything that implements `Remote`: `FolderRemote` uses a shared
+// folder (like Dropbox), `MemoryRemote` is a stand-in server for tests.
+
+use crate::config;
+use crate::db_sqlite;
+use crate::history;
+use crate::models::Item;
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::recurrence;
+use crate::schema::{items, sync_clock, sync_log, sync_state};
+use crate::tags;
+use crate::time;
+use diesel::prelude::*;
+use diesel::sql_types::Integer;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const SYNC_CLEAN: i32 = 0;
+pub const SYNC_DIRTY: i32 = 1;
+
+const PURGED_FIELD: &str = "purged";
+const INITIALIZED_KEY: &str = "initialized";
+
+// the synced fields of an item. the id, uuid and bookkeeping columns are local.
+const SYNC_FIELDS: [&str; 26] = [
+    "calendar",
+    "year",
+    "season",
+    "month",
+    "day",
+    "kind",
+    "fixed_date",
+    "all_day",
+    "title",
+    "note",
+    "datetime",
+    "duration",
+    "status",
+    "status_reason",
+    "parent_uuid",
+    "objective_uuid",
+    "carry_count",
+    "carried_from",
+    "carried_to",
+    "recurrence_uuid",
+    "occurrence_key",
+    "order_in_week",
+    "order_in_resolution",
+    "deleted_at",
+    "created_at",
+    "completed_at",
+];
+
+/// a new value of one field of an item.
+/// the value is the toml text of the field, `None` is null.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Change {
+    pub uuid: String,
+    pub field: String,
+    pub value: Option<String>,
+    pub changed_at: i64,
+    pub device: String,
+}
+
+/// how far the changes of each device are pulled
+pub type SyncCursor = BTreeMap<String, i64>;
+
+pub trait Remote {
+    /// identifies the remote. the pull cursor is kept per remote.
+    fn id(&self) -> String;
+    /// store the changes of this device
+    fn push(&self, device: &str, changes: &[Change]) -> AppResult<()>;
+    /// the changes of the other devices after the cursor, and the new cursor
+    fn pull(&self, device: &str, cursor: &SyncCursor) -> AppResult<(Vec<Change>, SyncCursor)>;
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct SyncReport {
+    pub pushed: usize,
+    pub pulled: usize,
+    // remote changes written to the local items
+    pub applied: usize,
+    // remote changes older than the local value of the field
+    pub rejected: usize,
+    pub created: usize,
+    pub purged: usize,
+    // remote items that could not be created (missing fields), or
+    // occurrences that are made here already
+    pub skipped: usize,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = crate::schema::sync_log)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct LogEntry {
+    id: i32,
+    uuid: String,
+    field: String,
+    value: Option<String>,
+    changed_at: i64,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::sync_log)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct NewLogEntry<'a> {
+    uuid: &'a str,
+    field: &'a str,
+    value: Option<String>,
+    changed_at: i64,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug)]
+#[diesel(table_name = crate::schema::sync_clock)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct Clock {
+    uuid: String,
+    field: String,
+    changed_at: i64,
+    device: String,
+}
+
+fn sync_error<E: ToString>(e: E) -> AppError {
+    AppError::SyncError(e.to_string())
+}
+
+/// the id of this device. it's generated and saved in the config on first use.
+pub fn device_id() -> AppResult<String> {
+    let mut config = config::get_config();
+    if let Some(id) = config.device_id.clone() {
+        return Ok(id);
+    }
+    let id = cuid2::create_id();
+    config.device_id = Some(id.clone());
+    config::set_config(config.clone());
+    config::save_config(config)?;
+    Ok(id)
+}
+
+/* field values */
+
+fn item_fields(item: &Item) -> AppResult<toml::Table> {
+    match toml::Value::try_from(item).map_err(sync_error)? {
+        toml::Value::Table(table) => Ok(table),
+        _ => Err(sync_error("item is not a table")),
+    }
+}
+
+fn parse_value(text: &str) -> AppResult<toml::Value> {
+    format!("v = {text}")
+        .parse::<toml::Table>()
+        .map_err(sync_error)?
+        .remove("v")
+        .ok_or(sync_error("invalid value"))
+}
+
+fn changed_fields(
+    before: Option<&Item>,
+    after: &Item,
+) -> AppResult<Vec<(&'static str, Option<String>)>> {
+    let before = before.map(item_fields).transpose()?.unwrap_or_default();
+    let after = item_fields(after)?;
+    Ok(SYNC_FIELDS
+        .iter()
+        .filter(|field| before.get(**field) != after.get(**field))
+        .map(|field| (*field, after.get(*field).map(|value| value.to_string())))
+        .collect())
+}
+
+/* local changes */
+
+fn write_clock_on(conn: &mut SqliteConnection, clock: &Clock) -> AppResult<()> {
+    diesel::replace_into(sync_clock::table)
+        .values(clock)
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| AppError::DatabaseInsertError(e.to_string()))
+}
+
+fn log_change_on(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+    field: &str,
+    value: Option<String>,
+    changed_at: i64,
+) -> AppResult<()> {
+    diesel::insert_into(sync_log::table)
+        .values(NewLogEntry {
+            uuid,
+            field,
+            value,
+            changed_at,
+        })
+        .execute(conn)
+        .map_err(|e| AppError::DatabaseInsertError(e.to_string()))?;
+    write_clock_on(
+        conn,
+        &Clock {
+            uuid: uuid.to_string(),
+            field: field.to_string(),
+            changed_at,
+            device: config::get_config().device_id.unwrap_or_default(),
+        },
+    )
+}
+
+/// log the changed fields of a local item change, to be pushed on the next sync.
+/// `after` is `None` when the item is deleted from the database.
+/// this should run in the same transaction as the change itself.
+pub(crate) fn record_local_change_on(
+    conn: &mut SqliteConnection,
+    before: Option<&Item>,
+    after: Option<&Item>,
+    changed_at: i64,
+) -> AppResult<()> {
+    if read_state_on(conn, INITIALIZED_KEY)?.is_none() {
+        // sync is not used (yet), the first sync logs the items anyway
+        return Ok(());
+    }
+    let Some(uuid) = after.or(before).and_then(|item| item.uuid.clone()) else {
+        // items without uuid can not be synced
+        return Ok(());
+    };
+    match after {
+        Some(after) => {
+            for (field, value) in changed_fields(before, after)? {
+                log_change_on(conn, &uuid, field, value, changed_at)?;
+            }
+            Ok(())
+        }
+        None => log_change_on(conn, &uuid, PURGED_FIELD, Some("true".into()), changed_at),
+    }
+}
+
+// the items are logged once on the first sync, as if they were just created.
+// so the ones created before sync was used are pushed too.
+fn initialize_on(conn: &mut SqliteConnection) -> AppResult<()> {
+    if read_state_on(conn, INITIALIZED_KEY)?.is_some() {
+        return Ok(());
+    }
+    diesel::delete(sync_log::table)
+        .execute(conn)
+        .map_err(|e| AppError::DatabaseDeleteError(e.to_string()))?;
+    // from now on the local changes are logged
+    write_state_on(
+        conn,
+        INITIALIZED_KEY,
+        &time::get_current_timestamp().to_string(),
+    )?;
+    let all_items: Vec<Item> = items::table
+        .select(Item::as_select())
+        .load(conn)
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))?;
+    for item in all_items.iter() {
+        let changed_at = item.updated_at.or(item.created_at).unwrap_or(0);
+        record_local_change_on(conn, None, Some(item), changed_at)?;
+    }
+    Ok(())
+}
+
+fn read_state_on(conn: &mut SqliteConnection, key: &str) -> AppResult<Option<String>> {
+    sync_state::table
+        .filter(sync_state::key.eq(key))
+        .select(sync_state::value)
+        .first(conn)
+        .optional()
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+}
+
+fn write_state_on(conn: &mut SqliteConnection, key: &str, value: &str) -> AppResult<()> {
+    diesel::replace_into(sync_state::table)
+        .values((sync_state::key.eq(key), sync_state::value.eq(value)))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| AppError::DatabaseInsertError(e.to_string()))
+}
+
+/// the number of local changes not pushed yet
+pub fn pending_changes() -> AppResult<usize> {
+    db_sqlite::with_connection(|conn| {
+        sync_log::table
+            .count()
+            .get_result::<i64>(conn)
+            .map(|count| count as usize)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+    })
+}
+
+/* remote changes */
+
+fn find_item_by_uuid_on(conn: &mut SqliteConnection, uuid: &str) -> AppResult<Option<Item>> {
+    items::table
+        .filter(items::uuid.eq(uuid))
+        .select(Item::as_select())
+        .first(conn)
+        .optional()
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+}
+
+fn next_item_id_on(conn: &mut SqliteConnection) -> AppResult<i32> {
+    let last_id: Option<i32> = items::table
+        .select(diesel::dsl::max(items::id))
+        .first(conn)
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))?;
+    Ok(last_id.unwrap_or(0) + 1)
+}
+
+// is the remote change newer than the last write of the field here?
+// the changes of one device in the same second are applied in their order.
+fn accept_on(conn: &mut SqliteConnection, change: &Change) -> AppResult<bool> {
+    let clock: Option<Clock> = sync_clock::table
+        .filter(sync_clock::uuid.eq(&change.uuid))
+        .filter(sync_clock::field.eq(&change.field))
+        .select(Clock::as_select())
+        .first(conn)
+        .optional()
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))?;
+    Ok(match clock {
+        Some(clock) => (change.changed_at, &change.device) >= (clock.changed_at, &clock.device),
+        None => true,
+    })
+}
+
+// apply the remote changes of one item. they are not logged as local changes.
+fn apply_item_changes_on(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+    changes: &[&Change],
+    report: &mut SyncReport,
+) -> AppResult<()> {
+    let existing = find_item_by_uuid_on(conn, uuid)?;
+    let mut fields = match &existing {
+        Some(item) => item_fields(item)?,
+        None => {
+            let mut table = toml::Table::new();
+            table.insert("id".into(), toml::Value::Integer(0));
+            table.insert("uuid".into(), toml::Value::String(uuid.to_string()));
+            table
+        }
+    };
+    let mut purged = false;
+    let mut accepted = 0;
+    for change in changes {
+        if !accept_on(conn, change)? {
+            report.rejected += 1;
+            continue;
+        }
+        accepted += 1;
+        write_clock_on(
+            conn,
+            &Clock {
+                uuid: uuid.to_string(),
+                field: change.field.clone(),
+                changed_at: change.changed_at,
+                device: change.device.clone(),
+            },
+        )?;
+        if change.field == PURGED_FIELD {
+            purged = true;
+            continue;
+        }
+        match &change.value {
+            Some(text) => fields.insert(change.field.clone(), parse_value(text)?),
+            None => fields.remove(&change.field),
+        };
+    }
+    if accepted == 0 {
+        return Ok(());
+    }
+    report.applied += accepted;
+
+    let now = time::get_current_timestamp();
+    match existing {
+        Some(before) if purged => {
+            history::remove_item_history_on(conn, before.id)?;
+            recurrence::skip_occurrence_on(conn, &before)?;
+            if let Some(uuid) = &before.uuid {
+                tags::erase_links_of_item_on(conn, uuid)?;
+            }
+            diesel::delete(items::table.filter(items::id.eq(before.id)))
+                .execute(conn)
+                .map_err(|e| AppError::DatabaseDeleteError(e.to_string()))?;
+            report.purged += 1;
+        }
+        Some(before) => {
+            let mut item: Item = toml::Value::Table(fields).try_into().map_err(sync_error)?;
+            item.sync = before.sync;
+            item.updated_at = Some(now);
+            history::record_changes_on(conn, &before, &item, now)?;
+            diesel::update(&item)
+                .set(&item)
+                .execute(conn)
+                .map_err(|e| AppError::DatabaseUpdateError(e.to_string()))?;
+        }
+        None if purged => {}
+        None => {
+            // the first changes of an item have all its required fields
+            let Ok(mut item) = toml::Value::Table(fields).try_into::<Item>() else {
+                report.skipped += 1;
+                return Ok(());
+            };
+            // the same occurrence made here, under another uuid
+            if recurrence::occurrence_exists_on(conn, &item)? {
+                report.skipped += 1;
+                return Ok(());
+            }
+            item.id = next_item_id_on(conn)?;
+            item.sync = Some(SYNC_CLEAN);
+            item.updated_at = Some(now);
+            diesel::insert_into(items::table)
+                .values(&item)
+                .execute(conn)
+                .map_err(|e| AppError::DatabaseInsertError(e.to_string()))?;
+            report.created += 1;
+        }
+    }
+    Ok(())
+}
+
+fn apply_remote_changes_on(
+    conn: &mut SqliteConnection,
+    changes: &[Change],
+    report: &mut SyncReport,
+) -> AppResult<()> {
+    let mut by_item: BTreeMap<&str, Vec<&Change>> = BTreeMap::new();
+    for change in changes {
+        by_item.entry(&change.uuid).or_default().push(change);
+    }
+    for (uuid, mut item_changes) in by_item {
+        item_changes.sort_by(|a, b| (a.changed_at, &a.device).cmp(&(b.changed_at, &b.device)));
+        apply_item_changes_on(conn, uuid, &item_changes, report)?;
+    }
+    Ok(())
+}
+
+/* sync */
+
+fn pull(remote: &dyn Remote, device: &str, report: &mut SyncReport) -> AppResult<()> {
+    let cursor_key = format!("cursor:{}", remote.id());
+    let cursor: SyncCursor = db_sqlite::with_connection(|conn| read_state_on(conn, &cursor_key))?
+        .map(|text| toml::from_str(&text).map_err(sync_error))
+        .transpose()?
+        .unwrap_or_default();
+    let (changes, new_cursor) = remote.pull(device, &cursor)?;
+    report.pulled = changes.len();
+    let new_cursor = toml::to_string(&new_cursor).map_err(sync_error)?;
+    db_sqlite::with_connection(|conn| {
+        conn.immediate_transaction(|conn| {
+            apply_remote_changes_on(conn, &changes, report)?;
+            write_state_on(conn, &cursor_key, &new_cursor)
+        })
+    })
+}
+
+fn push(remote: &dyn Remote, device: &str, report: &mut SyncReport) -> AppResult<()> {
+    let entries: Vec<LogEntry> = db_sqlite::with_connection(|conn| {
+        sync_log::table
+            .order(sync_log::id.asc())
+            .select(LogEntry::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+    })?;
+    let Some(last_id) = entries.last().map(|entry| entry.id) else {
+        return Ok(());
+    };
+    let changes: Vec<Change> = entries
+        .into_iter()
+        .map(|entry| Change {
+            uuid: entry.uuid,
+            field: entry.field,
+            value: entry.value,
+            changed_at: entry.changed_at,
+            device: device.to_string(),
+        })
+        .collect();
+    remote.push(device, &changes)?;
+    report.pushed = changes.len();
+
+    db_sqlite::with_connection(|conn| {
+        conn.immediate_transaction(|conn| {
+            diesel::delete(sync_log::table.filter(sync_log::id.le(last_id)))
+                .execute(conn)
+                .map_err(|e| AppError::DatabaseDeleteError(e.to_string()))?;
+            // the items changed while pushing stay dirty
+            diesel::sql_query(
+                "UPDATE items SET sync = ? WHERE sync = ? \
+                 AND uuid NOT IN (SELECT uuid FROM sync_log);",
+            )
+            .bind::<Integer, _>(SYNC_CLEAN)
+            .bind::<Integer, _>(SYNC_DIRTY)
+            .execute(conn)
+            .map(|_| ())
+            .map_err(|e| AppError::DatabaseUpdateError(e.to_string()))
+        })
+    })
+}
+
+/// pull the changes of the other devices, then push the local changes.
+pub fn sync(remote: &dyn Remote) -> AppResult<SyncReport> {
+    let device = device_id()?;
+    db_sqlite::with_connection(|conn| conn.immediate_transaction(initialize_on))?;
+    let mut report = SyncReport::default();
+    pull(remote, &device, &mut report)?;
+    push(remote, &device, &mut report)?;
+    Ok(report)
+}
+
+/* remotes */
+
+#[derive(Serialize, Deserialize, Default)]
+struct ChangeBatch {
+    changes: Vec<Change>,
+}
+
+/// a shared folder. each device writes its pushes as numbered files in its
+/// own sub folder, so the devices never write the same file:
+///     <folder>/<device id>/<batch number>.toml
+#[derive(Debug, Clone)]
+pub struct FolderRemote {
+    pub path: PathBuf,
+}
+
+impl FolderRemote {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        FolderRemote { path: path.into() }
+    }
+
+    // the batch numbers in the device folder, sorted
+    fn batches(&self, device: &str) -> Vec<i64> {
+        let Ok(entries) = fs::read_dir(self.path.join(device)) else {
+            return vec![];
+        };
+        let mut numbers: Vec<i64> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                name.strip_suffix(".toml")?.parse::<i64>().ok()
+            })
+            .collect();
+        numbers.sort();
+        numbers
+    }
+}
+
+impl Remote for FolderRemote {
+    fn id(&self) -> String {
+        format!("folder:{}", self.path.to_string_lossy())
+    }
+
+    fn push(&self, device: &str, changes: &[Change]) -> AppResult<()> {
+        let directory = self.path.join(device);
+        fs::create_dir_all(&directory).map_err(sync_error)?;
+        let number = self.batches(device).last().unwrap_or(&0) + 1;
+        let batch = ChangeBatch {
+            changes: changes.to_vec(),
+        };
+        let text = toml::to_string(&batch).map_err(sync_error)?;
+        // write and rename, so the others never read a half written file
+        let temp = directory.join(format!("{number:010}.tmp"));
+        fs::write(&temp, text).map_err(sync_error)?;
+        fs::rename(&temp, directory.join(format!("{number:010}.toml"))).map_err(sync_error)
+    }
+
+    fn pull(&self, device: &str, cursor: &SyncCursor) -> AppResult<(Vec<Change>, SyncCursor)> {
+        let mut changes: Vec<Change> = Vec::new();
+        let mut new_cursor = cursor.clone();
+        let Ok(entries) = fs::read_dir(&self.path) else {
+            return Ok((changes, new_cursor));
+        };
+        for entry in entries.flatten() {
+            let other = entry.file_name().to_string_lossy().to_string();
+            if other == device || !entry.path().is_dir() {
+                continue;
+            }
+            let pulled = cursor.get(&other).copied().unwrap_or(0);
+            for number in self.batches(&other).into_iter().filter(|n| *n > pulled) {
+                let path = entry.path().join(format!("{number:010}.toml"));
+                let text = fs::read_to_string(path).map_err(sync_error)?;
+                let batch: ChangeBatch = toml::from_str(&text).map_err(sync_error)?;
+                changes.extend(batch.changes);
+                new_cursor.insert(other.clone(), number);
+            }
+        }
+        Ok((changes, new_cursor))
+    }
+}
+
+/// keeps the pushed changes in memory. a stand-in server for tests.
+#[derive(Debug, Default)]
+pub struct MemoryRemote {
+    batches: Mutex<BTreeMap<String, Vec<Vec<Change>>>>,
+}
+
+impl MemoryRemote {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Remote for MemoryRemote {
+    fn id(&self) -> String {
+        format!("memory:{:p}", self)
+    }
+
+    fn push(&self, device: &str, changes: &[Change]) -> AppResult<()> {
+        let mut batches = self.batches.lock().map_err(sync_error)?;
+        batches
+            .entry(device.to_string())
+            .or_default()
+            .push(changes.to_vec());
+        Ok(())
+    }
+
+    fn pull(&self, device: &str, cursor: &SyncCursor) -> AppResult<(Vec<Change>, SyncCursor)> {
+        let batches = self.batches.lock().map_err(sync_error)?;
+        let mut changes: Vec<Change> = Vec::new();
+        let mut new_cursor = cursor.clone();
+        for (other, other_batches) in batches.iter().filter(|(other, _)| *other != device) {
+            let pulled = cursor.get(other).copied().unwrap_or(0) as usize;
+            for batch in other_batches.iter().skip(pulled) {
+                changes.extend(batch.iter().cloned());
+            }
+            new_cursor.insert(other.clone(), other_batches.len() as i64);
+        }
+        Ok((changes, new_cursor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_sqlite::{new_test_goal, BatchOp, TestDatabase};
+    use crate::models::ItemStatus;
+    use crate::storage::SqliteStorage;
+
+    const DAY: i32 = 20000;
+
+    // switch the configured database and device, to play both sides
+    fn use_device(database: &str, device: &str) {
+        config::set_config(config::Config {
+            database: database.into(),
+            backup_schedule: "off".into(),
+            device_id: Some(device.into()),
+            ..config::Config::default()
+        });
+    }
+
+    fn the_item() -> Option<Item> {
+        db_sqlite::read_items_between_days(DAY, DAY, true)
+            .unwrap()
+            .into_iter()
+            .next()
+    }
+
+    // a goal made on device "a" and synced to device "b", returns the path of b
+    fn two_synced_devices(db: &TestDatabase, remote: &MemoryRemote) -> String {
+        let other = db.file("other.db");
+        db_sqlite::create_db(&other).unwrap();
+        use_device(&db.path, "a");
+        db_sqlite::create_item(&new_test_goal(DAY, "plan", "m")).unwrap();
+        // nothing is logged before the first sync
+        assert_eq!(pending_changes().unwrap(), 0);
+        let report = sync(remote).unwrap();
+        assert!(report.pushed > 0);
+        assert_eq!(pending_changes().unwrap(), 0);
+
+        use_device(&other, "b");
+        let report = sync(remote).unwrap();
+        assert_eq!(report.created, 1);
+        assert_eq!(the_item().unwrap().title.as_deref(), Some("plan"));
+        other
+    }
+
+    #[test]
+    fn test_sync_resolves_conflicts_per_field() {
+        let db = TestDatabase::new();
+        let remote = MemoryRemote::new();
+        let other = two_synced_devices(&db, &remote);
+
+        use_device(&db.path, "a");
+        let id = the_item().unwrap().id;
+        db_sqlite::edit_item_text(id, "plan a".into()).unwrap();
+        db_sqlite::toggle_item_state(id).unwrap();
+        use_device(&other, "b");
+        let id = the_item().unwrap().id;
+        db_sqlite::edit_item_text(id, "plan b".into()).unwrap();
+        assert!(pending_changes().unwrap() > 0);
+
+        use_device(&db.path, "a");
+        sync(&remote).unwrap();
+        // both titles are written in the same second, so the device id breaks
+        // the tie and b wins. the status is written only on a.
+        use_device(&other, "b");
+        let report = sync(&remote).unwrap();
+        assert!(report.rejected > 0);
+        use_device(&db.path, "a");
+        sync(&remote).unwrap();
+
+        for database in [&db.path, &other] {
+            use_device(database, "a");
+            let item = the_item().unwrap();
+            assert_eq!(item.title.as_deref(), Some("plan b"));
+            assert_eq!(item.status, Some(ItemStatus::Done));
+        }
+    }
+
+    #[test]
+    fn test_sync_delete_wins_over_edit() {
+        let db = TestDatabase::new();
+        let remote = MemoryRemote::new();
+        let other = two_synced_devices(&db, &remote);
+
+        use_device(&db.path, "a");
+        let id = the_item().unwrap().id;
+        db_sqlite::apply_batch(&[BatchOp::Delete(id)]).unwrap();
+        use_device(&other, "b");
+        let id = the_item().unwrap().id;
+        db_sqlite::edit_item_text(id, "plan b".into()).unwrap();
+        tags::tag_item(&SqliteStorage, id, "work").unwrap();
+
+        use_device(&db.path, "a");
+        sync(&remote).unwrap();
+        use_device(&other, "b");
+        let report = sync(&remote).unwrap();
+        assert_eq!(report.purged, 1);
+        assert!(the_item().is_none());
+        assert!(tags::find_all_item_tags().unwrap().is_empty());
+        // the edit of a deleted item is dropped
+        use_device(&db.path, "a");
+        let report = sync(&remote).unwrap();
+        assert_eq!(report.skipped, 1);
+        assert!(the_item().is_none());
+    }
+
+    #[test]
+    fn test_sync_keeps_tags_on_each_device() {
+        let db = TestDatabase::new();
+        let remote = MemoryRemote::new();
+        let other = two_synced_devices(&db, &remote);
+
+        use_device(&db.path, "a");
+        let id = the_item().unwrap().id;
+        tags::tag_item(&SqliteStorage, id, "work").unwrap();
+        sync(&remote).unwrap();
+        use_device(&other, "b");
+        sync(&remote).unwrap();
+        assert!(tags::find_tags().unwrap().is_empty());
+        assert!(tags::find_all_item_tags().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,427 @@
+/* Tags */
+
+// tags like "health", "work" or "family" on the items. a tag is linked to
+// the items by their uuids (see `ItemTag`), and an item can have any number
+// of tags. the tags are matched by their key (see `tag_key()`): the case,
+// the Unicode forms, the extra spaces and the Arabic forms of the Persian
+// letters don't make a different tag. they are sorted by the collation of
+// the language, like the alphabet order in Persian and pinyin in Chinese.
+// the changes of the tags can not be undone, and the tags are not synced.
+// the links of an item go when the item is removed from the database.
+
+use crate::config;
+use crate::db_sqlite;
+use crate::language::Language;
+use crate::models::{Item, ItemView};
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::schema::{item_tags, items, tags};
+use crate::storage::Storage;
+use crate::time;
+use diesel::prelude::*;
+use icu::casemap::CaseMapper;
+use icu::collator::{Collator, CollatorOptions};
+use icu::locid::{locale, Locale};
+use icu::normalizer::ComposingNormalizer;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(
+    Queryable, Selectable, Insertable, AsChangeset, Debug, Serialize, Deserialize, Clone, PartialEq,
+)]
+#[diesel(table_name = crate::schema::tags)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+#[diesel(treat_none_as_null = true)]
+pub struct Tag {
+    pub uuid: String,
+    /// the name as written by the user
+    pub name: String,
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
+}
+
+/// the link of an item and a tag
+#[derive(
+    Queryable,
+    Selectable,
+    Insertable,
+    Debug,
+    Serialize,
+    Deserialize,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+#[diesel(table_name = crate::schema::item_tags)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ItemTag {
+    pub item_uuid: String,
+    pub tag_uuid: String,
+}
+
+fn tag_error<E: ToString>(e: E) -> AppError {
+    AppError::InvalidTagError(e.to_string())
+}
+
+// the name without the extra spaces, in the composed Unicode form
+fn clean_name(name: &str) -> AppResult<String> {
+    let name = ComposingNormalizer::new_nfc().normalize(name);
+    let name = name.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if name.is_empty() {
+        return Err(tag_error("the tag has no name"));
+    }
+    Ok(name)
+}
+
+/// the key of a tag name, the names with the same key are the same tag.
+/// the compatibility forms are folded (like the full width Latin letters),
+/// the case too, and the Arabic yeh and kaf are taken as the Persian ones.
+/// the kashida and the zero width non-joiner don't count.
+pub fn tag_key(name: &str) -> String {
+    let name = ComposingNormalizer::new_nfkc().normalize(name);
+    let name = CaseMapper::new().fold_string(&name);
+    let name: String = name
+        .chars()
+        .filter(|c| *c != '\u{0640}' && *c != '\u{200C}')
+        .map(|c| match c {
+            '\u{064A}' | '\u{0649}' => '\u{06CC}',
+            '\u{0643}' => '\u{06A9}',
+            c => c,
+        })
+        .collect();
+    name.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn locale_of(language: &Language) -> Locale {
+    match language {
+        Language::English => locale!("en"),
+        Language::Farsi => locale!("fa"),
+        Language::Chinese => locale!("zh"),
+        Language::Arabic => locale!("ar"),
+    }
+}
+
+/// sort the tags by their names, in the order of the language
+pub fn sort_tags(tags: &mut [Tag], language: &Language) {
+    match Collator::try_new(&(&locale_of(language)).into(), CollatorOptions::new()) {
+        Ok(collator) => tags.sort_by(|a, b| {
+            collator
+                .compare(&a.name, &b.name)
+                .then_with(|| a.uuid.cmp(&b.uuid))
+        }),
+        Err(_) => tags.sort_by(|a, b| (&a.name, &a.uuid).cmp(&(&b.name, &b.uuid))),
+    }
+}
+
+fn main_language() -> Language {
+    config::get_config().main_calendar_language.into()
+}
+
+/// all the tags, sorted in the order of the main calendar language
+pub fn read_tags(storage: &dyn Storage) -> AppResult<Vec<Tag>> {
+    let mut tags = storage.read_tags()?;
+    sort_tags(&mut tags, &main_language());
+    Ok(tags)
+}
+
+/// the tag with the name, by its key
+pub fn find_tag(storage: &dyn Storage, name: &str) -> AppResult<Option<Tag>> {
+    let key = tag_key(name);
+    Ok(storage
+        .read_tags()?
+        .into_iter()
+        .find(|tag| tag_key(&tag.name) == key))
+}
+
+/// the tag with the name, it's made if there is none
+pub fn add_tag(storage: &dyn Storage, name: &str) -> AppResult<Tag> {
+    let name = clean_name(name)?;
+    if let Some(tag) = find_tag(storage, &name)? {
+        return Ok(tag);
+    }
+    let now = time::get_current_timestamp();
+    let tag = Tag {
+        uuid: cuid2::create_id(),
+        name,
+        created_at: Some(now),
+        updated_at: Some(now),
+    };
+    storage.save_tag(&tag)?;
+    Ok(tag)
+}
+
+/// give the tag a new name, it can not be the name of another tag
+pub fn rename_tag(storage: &dyn Storage, uuid: &str, name: &str) -> AppResult<Tag> {
+    let name = clean_name(name)?;
+    let tags = storage.read_tags()?;
+    let Some(mut tag) = tags.iter().find(|tag| tag.uuid == uuid).cloned() else {
+        return Err(tag_error(format!("no tag {uuid}")));
+    };
+    let key = tag_key(&name);
+    if tags
+        .iter()
+        .any(|other| other.uuid != uuid && tag_key(&other.name) == key)
+    {
+        return Err(tag_error(format!("there is a tag named {name}")));
+    }
+    tag.name = name;
+    tag.updated_at = Some(time::get_current_timestamp());
+    storage.save_tag(&tag)?;
+    Ok(tag)
+}
+
+/// remove the tag from all the items and delete it
+pub fn remove_tag(storage: &dyn Storage, uuid: &str) -> AppResult<()> {
+    storage.delete_tag(uuid)
+}
+
+fn item_uuid(storage: &dyn Storage, item_id: i32) -> AppResult<String> {
+    storage
+        .get_item(item_id)?
+        .uuid
+        .ok_or(tag_error(format!("item {item_id} has no uuid")))
+}
+
+/// put the tag with the name on the item, the tag is made if there is none
+pub fn tag_item(storage: &dyn Storage, item_id: i32, name: &str) -> AppResult<Tag> {
+    let item_uuid = item_uuid(storage, item_id)?;
+    let tag = add_tag(storage, name)?;
+    storage.save_item_tag(&ItemTag {
+        item_uuid,
+        tag_uuid: tag.uuid.clone(),
+    })?;
+    Ok(tag)
+}
+
+/// take the tag off the item, the tag stays
+pub fn untag_item(storage: &dyn Storage, item_id: i32, tag_uuid: &str) -> AppResult<()> {
+    let item_uuid = item_uuid(storage, item_id)?;
+    storage.delete_item_tag(&ItemTag {
+        item_uuid,
+        tag_uuid: tag_uuid.to_string(),
+    })
+}
+
+/// the tags of the items by the item uuids, each sorted in the order of
+/// the main calendar language
+pub fn tags_of_items<'a, I>(storage: &dyn Storage, items: I) -> AppResult<HashMap<String, Vec<Tag>>>
+where
+    I: IntoIterator<Item = &'a Item>,
+{
+    let uuids: Vec<String> = items
+        .into_iter()
+        .filter_map(|item| item.uuid.clone())
+        .collect();
+    let mut tagged: HashMap<String, Vec<Tag>> = HashMap::new();
+    if uuids.is_empty() {
+        return Ok(tagged);
+    }
+    let links = storage.read_item_tags(&uuids)?;
+    if links.is_empty() {
+        return Ok(tagged);
+    }
+    let tags: HashMap<String, Tag> = storage
+        .read_tags()?
+        .into_iter()
+        .map(|tag| (tag.uuid.clone(), tag))
+        .collect();
+    for link in links {
+        if let Some(tag) = tags.get(&link.tag_uuid) {
+            tagged.entry(link.item_uuid).or_default().push(tag.clone());
+        }
+    }
+    let language = main_language();
+    for tags in tagged.values_mut() {
+        sort_tags(tags, &language);
+    }
+    Ok(tagged)
+}
+
+/// put the tags on the views and their subitems
+pub fn fill_views(views: &mut [ItemView], tags: &HashMap<String, Vec<Tag>>) {
+    for view in views.iter_mut() {
+        if let Some(item_tags) = view.uuid.as_ref().and_then(|uuid| tags.get(uuid)) {
+            view.tags = item_tags.clone();
+        }
+        fill_views(&mut view.children, tags);
+    }
+}
+
+/// the weekly items and events with the tag in the days range (inclusive),
+/// by their day and week order
+pub fn items_with_tag_between_days(
+    storage: &dyn Storage,
+    tag_uuid: &str,
+    start_day: i32,
+    end_day: i32,
+) -> AppResult<Vec<Item>> {
+    if start_day > end_day {
+        return Err(AppError::BadDaysRangeError);
+    }
+    let mut items: Vec<Item> = storage
+        .read_tagged_items(tag_uuid)?
+        .into_iter()
+        .filter(|item| item.year.is_none() && (start_day..=end_day).contains(&item.day))
+        .collect();
+    items.sort_by(|a, b| (a.day, &a.order_in_week, a.id).cmp(&(b.day, &b.order_in_week, b.id)));
+    Ok(items)
+}
+
+/// the objectives with the tag in the years range (inclusive) of the calendar,
+/// by their year and objective order
+pub fn objectives_with_tag_in_calendar_years(
+    storage: &dyn Storage,
+    tag_uuid: &str,
+    calendar: i32,
+    start_year: i32,
+    end_year: i32,
+) -> AppResult<Vec<Item>> {
+    let mut items: Vec<Item> = storage
+        .read_tagged_items(tag_uuid)?
+        .into_iter()
+        .filter(|item| {
+            item.calendar == calendar
+                && item
+                    .year
+                    .is_some_and(|year| (start_year..=end_year).contains(&year))
+        })
+        .collect();
+    items.sort_by(|a, b| {
+        (a.year, &a.order_in_resolution, a.id).cmp(&(b.year, &b.order_in_resolution, b.id))
+    });
+    Ok(items)
+}
+
+/* Sqlite */
+
+pub(crate) fn find_tags_on(conn: &mut SqliteConnection) -> AppResult<Vec<Tag>> {
+    tags::table
+        .select(Tag::as_select())
+        .load(conn)
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+}
+
+pub(crate) fn find_tags() -> AppResult<Vec<Tag>> {
+    db_sqlite::with_connection(find_tags_on)
+}
+
+pub(crate) fn find_tag_on(conn: &mut SqliteConnection, uuid: &str) -> AppResult<Option<Tag>> {
+    tags::table
+        .filter(tags::uuid.eq(uuid))
+        .select(Tag::as_select())
+        .first(conn)
+        .optional()
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+}
+
+pub(crate) fn write_tag_on(conn: &mut SqliteConnection, tag: &Tag) -> AppResult<()> {
+    diesel::replace_into(tags::table)
+        .values(tag)
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| AppError::DatabaseInsertError(e.to_string()))
+}
+
+pub(crate) fn write_tag(tag: &Tag) -> AppResult<()> {
+    db_sqlite::with_connection(|conn| write_tag_on(conn, tag))
+}
+
+// the tag and its links. the caller should run this in a transaction
+pub(crate) fn erase_tag_on(conn: &mut SqliteConnection, uuid: &str) -> AppResult<()> {
+    diesel::delete(item_tags::table.filter(item_tags::tag_uuid.eq(uuid)))
+        .execute(conn)
+        .map_err(|e| AppError::DatabaseDeleteError(e.to_string()))?;
+    diesel::delete(tags::table.filter(tags::uuid.eq(uuid)))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| AppError::DatabaseDeleteError(e.to_string()))
+}
+
+pub(crate) fn erase_tag(uuid: &str) -> AppResult<()> {
+    db_sqlite::with_connection(|conn| conn.immediate_transaction(|conn| erase_tag_on(conn, uuid)))
+}
+
+pub(crate) fn find_item_tags(item_uuids: &[String]) -> AppResult<Vec<ItemTag>> {
+    db_sqlite::with_connection(|conn| {
+        item_tags::table
+            .filter(item_tags::item_uuid.eq_any(item_uuids))
+            .select(ItemTag::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+    })
+}
+
+pub(crate) fn find_all_item_tags_on(conn: &mut SqliteConnection) -> AppResult<Vec<ItemTag>> {
+    item_tags::table
+        .select(ItemTag::as_select())
+        .load(conn)
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+}
+
+pub(crate) fn find_all_item_tags() -> AppResult<Vec<ItemTag>> {
+    db_sqlite::with_connection(find_all_item_tags_on)
+}
+
+pub(crate) fn write_item_tag_on(conn: &mut SqliteConnection, link: &ItemTag) -> AppResult<()> {
+    diesel::replace_into(item_tags::table)
+        .values(link)
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| AppError::DatabaseInsertError(e.to_string()))
+}
+
+pub(crate) fn write_item_tag(link: &ItemTag) -> AppResult<()> {
+    db_sqlite::with_connection(|conn| write_item_tag_on(conn, link))
+}
+
+// the links of an item that is removed from the database
+pub(crate) fn erase_links_of_item_on(conn: &mut SqliteConnection, uuid: &str) -> AppResult<()> {
+    diesel::delete(item_tags::table.filter(item_tags::item_uuid.eq(uuid)))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| AppError::DatabaseDeleteError(e.to_string()))
+}
+
+pub(crate) fn item_tag_exists_on(conn: &mut SqliteConnection, link: &ItemTag) -> AppResult<bool> {
+    item_tags::table
+        .filter(item_tags::item_uuid.eq(&link.item_uuid))
+        .filter(item_tags::tag_uuid.eq(&link.tag_uuid))
+        .count()
+        .get_result::<i64>(conn)
+        .map(|count| count > 0)
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+}
+
+pub(crate) fn erase_item_tag_on(conn: &mut SqliteConnection, link: &ItemTag) -> AppResult<()> {
+    diesel::delete(
+        item_tags::table
+            .filter(item_tags::item_uuid.eq(&link.item_uuid))
+            .filter(item_tags::tag_uuid.eq(&link.tag_uuid)),
+    )
+    .execute(conn)
+    .map(|_| ())
+    .map_err(|e| AppError::DatabaseDeleteError(e.to_string()))
+}
+
+pub(crate) fn erase_item_tag(link: &ItemTag) -> AppResult<()> {
+    db_sqlite::with_connection(|conn| erase_item_tag_on(conn, link))
+}
+
+// the items with the tag, not the trashed ones
+pub(crate) fn find_tagged_items(tag_uuid: &str) -> AppResult<Vec<Item>> {
+    db_sqlite::with_connection(|conn| {
+        let tagged: Vec<String> = item_tags::table
+            .filter(item_tags::tag_uuid.eq(tag_uuid))
+            .select(item_tags::item_uuid)
+            .load(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))?;
+        items::table
+            .filter(items::deleted_at.is_null())
+            .filter(items::uuid.eq_any(tagged))
+            .select(Item::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DatabaseSelectError(e.to_string()))
+    })
+}
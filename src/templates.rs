@@ -0,0 +1,308 @@
+/* Templates */
+
+// named sets of goals and notes (with their subitems) to start a week with.
+// applying a template adds its items at the end of the week, as one action. the texts can have placeholders,
+// replaced by the dates of the week:
+//   - `{start}` and `{end}`: the first and the last day, like "12 March"
+//   - `{mon}`, `{tue}`, ... `{sun}`: the day of the weekday in the week
+//   - `{month}` and `{year}`: of the first day of the week
+// in the main calendar. with the `_aux` suffix (like `{start_aux}`) they are
+// in the secondary calendar, and empty without one. the other placeholders
+// are kept as they are.
+// the templates are kept in the database, and can be exported to (and
+// imported from) `templates.toml`, next to `config.toml`.
+// the changes of the templates can not be undone, and they are not synced.
+
+use crate::calendar::CalendarLanguagePair;
+use crate::config;
+use crate::db_sqlite;
+use crate::models::ItemKind;
+use crate::prelude::Error as AppError;
+use crate::prelude::Result as AppResult;
+use crate::schema::templates;
+use crate::storage::Storage;
+use crate::time;
+use crate::weekdays::{WeekDaysUnixOffset, SEVEN_DAY_WEEK_SIZE};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const TEMPLATES_FILE: &str = "templates.toml";
+const AUX_SUFFIX: &str = "_aux";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TemplateItem {
+    pub kind: ItemKind,
+    /// the text, with the placeholders
+    pub text: String,
+    /// the subitems, added under the item
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subitems: Vec<TemplateItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Template {
+    pub uuid: String,
+    pub name: String,
+    #[serde(default)]
+    pub items: Vec<TemplateItem>,
+    #[serde(default)]
+    pub created_at: Option<i64>,
+    #[serde(default)]
+    pub updated_at: Option<i64>,
+}
+
+// a template as kept in the database, the items as toml
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = crate::schema::templates)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct TemplateRow {
+    uuid: String,
+    name: String,
+    items: String,
+    created_at: Option<i64>,
+    updated_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct TemplateItems {
+    #[serde(default)]
+    items: Vec<TemplateItem>,
+}
+
+/// the content of `templates.toml`
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub(crate) struct TemplatesFile {
+    #[serde(default)]
+    pub templates: Vec<Template>,
+}
+
+fn template_error<E: ToString>(e: E) -> AppError {
+    AppError::InvalidTemplateError(e.to_string())
+}
+
+// an event in the items, at any level
+fn has_events(items: &[TemplateItem]) -> bool {
+    items
+        .iter()
+        .any(|item| item.kind == ItemKind::Event || has_events(&item.subitems))
+}
+
+/// all the templates, by their names
+pub fn read_templates(storage: &dyn Storage) -> AppResult<Vec<Template>> {
+    let mut templates = storage.read_templates()?;
+    templates.sort_by(|a, b| (&a.name, &a.uuid).cmp(&(&b.name, &b.uuid)));
+    Ok(templates)
+}
+
+/// save the items as the template with the name. a template with the same
+/// name gets the new items.
+pub fn save_template(
+    storage: &dyn Storage,
+    name: &str,
+    items: Vec<TemplateItem>,
+) -> AppResult<Template> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(template_error("the template has no name"));
+    }
+    if has_events(&items) {
+        return Err(template_error("a template has only goals and notes"));
+    }
+    let now = time::get_current_timestamp();
+    let template = match storage
+        .read_templates()?
+        .into_iter()
+        .find(|template| template.name == name)
+    {
+        Some(template) => Template {
+            items,
+            updated_at: Some(now),
+            ..template
+        },
+        None => Template {
+            uuid: cuid2::create_id(),
+            name,
+            items,
+            created_at: Some(now),
+            updated_at: Some(now),
+        },
+    };
+    storage.save_template(&template)?;
+    Ok(template)
+}
+
+pub fn delete_template(storage: &dyn Storage, uuid: &str) -> AppResult<()> {
+    storage.delete_template(uuid)
+}
+
+// the value of a placeholder name (without the `_aux` suffix) in the calendar
+fn placeholder_value(
+    name: &str,
+    pair: &CalendarLanguagePair,
+    start_day: i32,
+    end_day: i32,
+) -> Option<String> {
+    let day_month = |day: i32| {
+        let date = pair.calendar.get_date_view(day, &pair.language);
+        format!("{} {}", date.day, date.month)
+    };
+    let start = pair.calendar.get_date_view(start_day, &pair.language);
+    match name {
+        "start" => Some(day_month(start_day)),
+        "end" => Some(day_month(end_day)),
+        "month" => Some(start.month),
+        "year" => Some(start.year),
+        weekday => (start_day..=end_day)
+            .find(|day| {
+                let offset = WeekDaysUnixOffset::from(day.rem_euclid(SEVEN_DAY_WEEK_SIZE));
+                format!("{offset:?}").to_lowercase() == weekday
+            })
+            .map(day_month),
+    }
+}
+
+/// replace the placeholders of the text with the dates of the week
+pub fn expand_placeholders(text: &str, start_day: i32, end_day: i32) -> String {
+    let main_pair = config::get_main_cal_lang_pair();
+    let aux_pair = config::get_second_cal_lang_pair();
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find('{') {
+        expanded.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('}').map(|close| open + close) else {
+            break;
+        };
+        let name = &rest[open + 1..close];
+        let value = match name.strip_suffix(AUX_SUFFIX) {
+            Some(name) => match &aux_pair {
+                Some(pair) => placeholder_value(name, pair, start_day, end_day),
+                // a known placeholder is empty without a secondary calendar
+                None => placeholder_value(name, &main_pair, start_day, end_day).map(|_| "".into()),
+            },
+            None => placeholder_value(name, &main_pair, start_day, end_day),
+        };
+        match value {
+            Some(value) => expanded.push_str(&value),
+            None => expanded.push_str(&rest[open..=close]),
+        }
+        rest = &rest[close + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// where the templates are exported, next to `config.toml`
+pub fn default_templates_path() -> PathBuf {
+    config::get_config_path().with_file_name(TEMPLATES_FILE)
+}
+
+/// write all the templates into the toml file
+pub fn export_templates<P: AsRef<Path>>(storage: &dyn Storage, path: P) -> AppResult<usize> {
+    let content = TemplatesFile {
+        templates: read_templates(storage)?,
+    };
+    let text = toml::to_string_pretty(&content).map_err(template_error)?;
+    if let Some(parent) = path.as_ref().parent() {
+        fs::create_dir_all(parent).map_err(template_error)?;
+    }
+    fs::write(path, text).map_err(template_error)?;
+    Ok(content.templates.len())
+}
+
+/// add the templates of the toml file. the ones already here (by uuid) are
+/// replaced. returns the number of imported templates.
+pub fn import_templates<P: AsRef<Path>>(storage: &dyn Storage, path: P) -> AppResult<usize> {
+    let text = fs::read_to_string(path).map_err(template_error)?;
+    let content: TemplatesFile = toml::from_str(&text).map_err(template_error)?;
+    for template in content.templates.iter() {
+        if has_events(&template.items) {
+            return Err(template_error(format!(
+                "{}: a template has only goals and notes",
+                template.name
+            )));
+        }
+    }
+    for template in content.templates.iter() {
+        storage.save_template(template)?;
+    }
+    Ok(content.templates.len())
+}
+
+/* Sqlite */
+
+impl TemplateRow {
+    fn from(template: &Template) -> AppResult<TemplateRow> {
+        let items = TemplateItems {
+            items: template.items.clone(),
+        };
+        Ok(TemplateRow {
+            uuid: template.uuid.clone(),
+            name: template.name.clone(),
+            items: toml::to_string(&items).map_err(template_error)?,
+            created_at: template.created_at,
+            updated_at: template.updated_at,
+        })
+    }
+
+    fn into_template(self) -> AppResult<Template> {
+        let items: TemplateItems = toml::from_str(&self.items).map_err(template_error)?;
+        Ok(Template {
+            uuid: self.uuid,
+            name: self.name,
+            items: items.items,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        })
+    }
+}
+
+pub(crate) fn find_templates_on(conn: &mut SqliteConnection) -> AppResult<Vec<Template>> {
+    let rows: Vec<TemplateRow> = templates::table
+        .select(TemplateRow::as_select())
+        .load(conn)
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))?;
+    rows.into_iter().map(TemplateRow::into_template).collect()
+}
+
+pub(crate) fn find_templates() -> AppResult<Vec<Template>> {
+    db_sqlite::with_connection(find_templates_on)
+}
+
+pub(crate) fn find_template_on(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+) -> AppResult<Option<Template>> {
+    let row: Option<TemplateRow> = templates::table
+        .filter(templates::uuid.eq(uuid))
+        .select(TemplateRow::as_select())
+        .first(conn)
+        .optional()
+        .map_err(|e| AppError::DatabaseSelectError(e.to_string()))?;
+    row.map(TemplateRow::into_template).transpose()
+}
+
+pub(crate) fn write_template_on(conn: &mut SqliteConnection, template: &Template) -> AppResult<()> {
+    let row = TemplateRow::from(template)?;
+    diesel::replace_into(templates::table)
+        .values(&row)
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| AppError::DatabaseInsertError(e.to_string()))
+}
+
+pub(crate) fn write_template(template: &Template) -> AppResult<()> {
+    db_sqlite::with_connection(|conn| write_template_on(conn, template))
+}
+
+pub(crate) fn erase_template_on(conn: &mut SqliteConnection, uuid: &str) -> AppResult<()> {
+    diesel::delete(templates::table.filter(templates::uuid.eq(uuid)))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(|e| AppError::DatabaseDeleteError(e.to_string()))
+}
+
+pub(crate) fn erase_template(uuid: &str) -> AppResult<()> {
+    db_sqlite::with_connection(|conn| erase_template_on(conn, uuid))
+}
@@ -1,5 +1,10 @@
 use chrono::{DateTime, Local};
 
+/// current unix timestamp in seconds
+pub fn get_current_timestamp() -> i64 {
+    Local::now().timestamp()
+}
+
 pub fn get_unix_day_from_local_datetime(datetime: DateTime<Local>) -> i32 {
     // get the unix timestamp, add the local timezone offset, then calculate the day index
     let utc_epoch = datetime.to_utc().timestamp(); // Seconds since Unix epoch
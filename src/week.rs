@@ -8,42 +8,92 @@
 // use std::time;
 
 use crate::calendar::Calendar;
+use crate::carry_over::{self, CarryOverMode};
 use crate::config;
-use crate::db_sqlite;
+use crate::db_sqlite::BatchOp;
+use crate::event::{self, EventSchedule};
 use crate::language::Language;
 use crate::models::*;
-use crate::ordering::Ordering;
+use crate::objective_links;
 use crate::ordering::Result;
+use crate::ordering::{self, Ordering};
+use crate::prelude::Error as AppError;
 use crate::prelude::Result as AppResult;
+use crate::recurrence::{self, Recurrence};
+use crate::storage::{self, Storage};
+use crate::subitems::{self, Subitems};
+use crate::tags::{self, Tag};
+use crate::templates::{self, Template, TemplateItem};
 use crate::today;
 use crate::week_info::WeekInfo;
 use crate::weekdays::WeekDaysUnixOffset;
 use crate::weekdays::SEVEN_DAY_WEEK_SIZE;
 use ptime;
 use serde::Serialize;
+use std::sync::Arc;
 use time::Timespec;
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct Week {
     pub reference_day: i32,
     pub start_day: i32,
     pub middle_day: i32,
     pub end_day: i32,
+    // the goals and notes, in their order
     pub items: Vec<Item>,
+    // the subitems of the goals and notes, at all levels, in their order
+    pub subitems: Vec<Item>,
+    // the events, sorted by their time
+    pub events: Vec<Item>,
+    // where the items are read from and written to
+    pub storage: Arc<dyn Storage>,
     // for frontend view only
     pub week_view: WeekView,
 }
 
+impl Default for Week {
+    fn default() -> Self {
+        Week {
+            reference_day: 0,
+            start_day: 0,
+            middle_day: 0,
+            end_day: 0,
+            items: vec![],
+            subitems: vec![],
+            events: vec![],
+            storage: storage::default_storage(),
+            week_view: WeekView::default(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Clone, Default)]
 pub struct WeekView {
     pub week_info_main: WeekInfo,
     pub week_info_aux: Option<WeekInfo>,
     pub items: Vec<ItemView>,
+    // the events of each day of the week, from the first day
+    pub days: Vec<DayView>,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct DayView {
+    pub unix_day: i32,
+    // the goals and notes pinned to the day, they are in `items` too
+    pub items: Vec<ItemView>,
+    pub events: Vec<ItemView>,
 }
 
 impl Week {
     pub fn new() -> Self {
-        let mut week = Week::default();
+        Week::with_storage(storage::default_storage())
+    }
+
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Self {
+        let mut week = Week {
+            storage,
+            ..Default::default()
+        };
         let _ = week.current();
         week
     }
@@ -75,23 +125,27 @@ impl Week {
 
     pub fn update(&mut self) -> AppResult<()> {
         // update general week start/middle/end unix days
-        let start_week_day: WeekDaysUnixOffset =
-            config::get_config().main_calendar_start_weekday.into();
-        let start_week_day_offset: i32 = start_week_day as i32;
-        let (start_day, middle_day, end_day) = Self::calculate_week_start_middle_end_unix_day(
-            self.reference_day,
-            start_week_day_offset,
-            SEVEN_DAY_WEEK_SIZE,
-        );
+        let (start_day, middle_day, end_day) =
+            get_week_start_middle_end_unix_day(self.reference_day);
         self.start_day = start_day;
         self.middle_day = middle_day;
         self.end_day = end_day;
 
         // update items
-        let items = db_sqlite::read_items_between_days(self.start_day, self.end_day, true)?;
+        let items = self
+            .storage
+            .read_items_between_days(self.start_day, self.end_day)?;
         // todo: exclude the objectives, include the ones that are fixed date
+        let (mut events, items): (Vec<Item>, Vec<Item>) = items
+            .into_iter()
+            .partition(|item| item.kind == ItemKind::Event);
+        event::sort_events(&mut events);
+        let (items, subitems) = subitems::split_subitems(items);
         self.items = items;
-        self.check_and_fix_ordering();
+        self.subitems = subitems;
+        self.events = events;
+        self.check_and_fix_ordering()?;
+        subitems::fix_ordering(&mut self.subitems, false, &self.storage)?;
 
         // update view items
         let today = today::get_unix_day();
@@ -121,7 +175,43 @@ impl Week {
             )
             .unwrap_or_default()
         });
-        self.week_view.items = self.items.iter().map(ItemView::from).collect();
+        self.week_view.items = subitems::nested_views(&self.items, &self.subitems);
+        self.week_view.days = (self.start_day..=self.end_day)
+            .map(|day| DayView {
+                unix_day: day,
+                items: self
+                    .items
+                    .iter()
+                    .filter(|item| item.fixed_date && item.day == day)
+                    .map(ItemView::from)
+                    .collect(),
+                events: self
+                    .events
+                    .iter()
+                    .filter(|event| event.day == day)
+                    .map(ItemView::from)
+                    .collect(),
+            })
+            .collect();
+        let item_tags = tags::tags_of_items(
+            self.storage.as_ref(),
+            self.items
+                .iter()
+                .chain(self.subitems.iter())
+                .chain(self.events.iter()),
+        )?;
+        tags::fill_views(&mut self.week_view.items, &item_tags);
+        for day in self.week_view.days.iter_mut() {
+            tags::fill_views(&mut day.items, &item_tags);
+            tags::fill_views(&mut day.events, &item_tags);
+        }
+        let goals: Vec<&Item> = self.items.iter().chain(self.subitems.iter()).collect();
+        let objectives =
+            objective_links::parent_objectives(self.storage.as_ref(), goals.iter().copied())?;
+        objective_links::fill_parent_objectives(&mut self.week_view.items, &goals, &objectives);
+        for day in self.week_view.days.iter_mut() {
+            objective_links::fill_parent_objectives(&mut day.items, &goals, &objectives);
+        }
         Ok(())
     }
 
@@ -140,21 +230,133 @@ impl Week {
         self.update()
     }
 
+    /// show the current week. the unfinished goals of last week are carried
+    /// over, if the `carry_over` setting says "move" or "copy".
     pub fn current(&mut self) -> AppResult<()> {
         self.reference_day = today::get_unix_day();
+        if let Some(mode) = carry_over::configured_mode() {
+            let start_day = get_week_start_middle_end_unix_day(self.reference_day).0;
+            carry_over::carry_over(
+                self.storage.as_ref(),
+                start_day - SEVEN_DAY_WEEK_SIZE,
+                start_day,
+                mode,
+            )?;
+        }
         self.update()
     }
 
+    /// the unfinished goals of the week before, that `carry_over()` brings to this week
+    pub fn goals_to_carry_over(&self) -> AppResult<Vec<ItemView>> {
+        let goals = carry_over::goals_to_carry_over(
+            self.storage.as_ref(),
+            self.start_day - SEVEN_DAY_WEEK_SIZE,
+        )?;
+        Ok(goals.iter().map(ItemView::from).collect())
+    }
+
+    /// move or copy the unfinished goals of the week before to the end of
+    /// this week. returns the number of carried goals.
+    pub fn carry_over(&mut self, mode: CarryOverMode) -> AppResult<usize> {
+        let count = carry_over::carry_over(
+            self.storage.as_ref(),
+            self.start_day - SEVEN_DAY_WEEK_SIZE,
+            self.start_day,
+            mode,
+        )?;
+        self.update()?;
+        Ok(count)
+    }
+
+    /// add the items of the template (with their subitems) at the end of the
+    /// week, with their placeholders replaced, as one action. returns the
+    /// number of added items.
+    pub fn apply_template(&mut self, uuid: &str) -> AppResult<usize> {
+        let template = templates::read_templates(self.storage.as_ref())?
+            .into_iter()
+            .find(|template| template.uuid == uuid)
+            .ok_or(AppError::InvalidTemplateError(format!(
+                "no template with uuid {uuid}"
+            )))?;
+        let last_key = self
+            .get_keys()
+            .last()
+            .cloned()
+            .flatten()
+            .unwrap_or_default();
+        let mut ops: Vec<BatchOp> = Vec::new();
+        self.template_ops(&template.items, None, &last_key, &mut ops);
+        let result = self.storage.apply_batch(Some("apply template"), &ops);
+        self.update()?;
+        Ok(result?.inserted_ids.len())
+    }
+
+    // the inserts of the template items after the key, each followed by the
+    // inserts of its subitems
+    fn template_ops(
+        &self,
+        items: &[TemplateItem],
+        parent_uuid: Option<&str>,
+        last_key: &str,
+        ops: &mut Vec<BatchOp>,
+    ) {
+        let keys = ordering::ordering_keys_after(last_key, items.len());
+        for (item, key) in items.iter().zip(keys) {
+            let text = templates::expand_placeholders(&item.text, self.start_day, self.end_day);
+            let mut new_item = self.new_item(item.kind, text, key);
+            new_item.parent_uuid = parent_uuid.map(String::from);
+            let uuid = new_item.uuid.clone();
+            ops.push(BatchOp::Insert(new_item));
+            self.template_ops(&item.subitems, uuid.as_deref(), "", ops);
+        }
+    }
+
+    /// save the goals and notes of the week, with their subitems, as the
+    /// template with the name. the occurrences of the recurrences are left
+    /// out, they come from their rules.
+    pub fn save_as_template(&self, name: &str) -> AppResult<Template> {
+        let items = self.template_items_of(&self.items);
+        templates::save_template(self.storage.as_ref(), name, items)
+    }
+
+    // the template items of the items in the list, with their subitems
+    fn template_items_of(&self, items: &[Item]) -> Vec<TemplateItem> {
+        items
+            .iter()
+            .filter(|item| item.kind != ItemKind::Event)
+            .filter(|item| recurrence::occurrence_of(item).is_none())
+            .map(|item| {
+                let subitems = Subitems::of(
+                    item.uuid.as_deref(),
+                    &self.subitems,
+                    false,
+                    self.storage.clone(),
+                );
+                TemplateItem {
+                    kind: item.kind,
+                    text: ItemView::from(item).text,
+                    subitems: self.template_items_of(&subitems.items),
+                }
+            })
+            .collect()
+    }
+
     pub fn add_new_item(
         &mut self,
-        kind: i32,
+        kind: ItemKind,
         text: String,
         after_id: Option<i32>,
     ) -> AppResult<i32> {
+        let ordering_key: String = self.get_new_ordering_key(after_id);
+        let new_item = self.new_item(kind, text, ordering_key);
+        self.storage.create_item(&new_item)
+    }
+
+    // a new item of the week, in the main calendar
+    fn new_item(&self, kind: ItemKind, text: String, ordering_key: String) -> NewItem {
         let main_cal: Calendar = config::get_config().main_calendar_type.into();
         let calendar: i32 = main_cal.into();
-        let ordering_key: String = self.get_new_ordering_key(after_id);
-        let new_item = NewItem::new(
+        NewItem::new(
             calendar,
             None, //year,
             None, //season,
@@ -163,16 +365,275 @@ impl Week {
             kind,
             text,
             ordering_key,
-        );
-        db_sqlite::create_item(&new_item)
+        )
+    }
+
+    /// add a subitem to the item, after `after_id` or at the end
+    pub fn add_new_subitem(
+        &mut self,
+        parent_id: i32,
+        kind: ItemKind,
+        text: String,
+        after_id: Option<i32>,
+    ) -> AppResult<i32> {
+        let parent = self
+            .find_item(parent_id)
+            .cloned()
+            .ok_or(AppError::InvalidSubitemError(format!(
+                "item {parent_id} is not in the week"
+            )))?;
+        let ordering_key = self.subitems_of(parent_id).get_new_ordering_key(after_id);
+        let new_item = subitems::new_subitem(&parent, kind, text, ordering_key)?;
+        self.storage.create_item(&new_item)
+    }
+
+    /// the subitems of the item, as a list for their ordering keys
+    pub fn subitems_of(&self, parent_id: i32) -> Subitems {
+        let parent_uuid = self.find_item(parent_id).and_then(|item| item.uuid.clone());
+        Subitems::of(
+            parent_uuid.as_deref(),
+            &self.subitems,
+            false,
+            self.storage.clone(),
+        )
+    }
+
+    /// put the item (with its subitems) at the end of the parent's subitems.
+    /// without a parent, it becomes a top item at the end of the week.
+    pub fn set_item_parent(&mut self, id: i32, parent_id: Option<i32>) -> Result<usize> {
+        let Some(mut item) = self.find_item(id).cloned() else {
+            let _ = self.update();
+            return Err("id not in list!".into());
+        };
+        match parent_id {
+            Some(parent_id) => {
+                let parent = self
+                    .find_item(parent_id)
+                    .cloned()
+                    .ok_or("parent id not in list!")?;
+                subitems::check_new_parent(&item, &parent, &self.subitems)
+                    .map_err(|e| e.to_string())?;
+                item.order_in_week = Some(self.subitems_of(parent_id).get_new_ordering_key(None));
+                item.parent_uuid = parent.uuid;
+            }
+            None => {
+                item.order_in_week = Some(self.get_new_ordering_key(None));
+                item.parent_uuid = None;
+            }
+        }
+        let result = self
+            .storage
+            .apply_batch(Some("change item parent"), &[BatchOp::Update(item)])
+            .map(|result| result.updated)
+            .map_err(|e| e.to_string());
+        let _ = self.update();
+        result
+    }
+
+    // a goal or note of the week, a top item or a subitem
+    fn find_item(&self, id: i32) -> Option<&Item> {
+        self.items
+            .iter()
+            .chain(self.subitems.iter())
+            .find(|item| item.id == id)
+    }
+
+    /// add an event on a day, it doesn't need to be a day of this week
+    pub fn add_new_event(&mut self, title: String, schedule: &EventSchedule) -> AppResult<i32> {
+        let new_item = event::new_event(title, schedule)?;
+        self.storage.create_item(&new_item)
+    }
+
+    /// change the day or time of the event
+    pub fn reschedule_event(&mut self, id: i32, schedule: &EventSchedule) -> AppResult<usize> {
+        event::reschedule_event(self.storage.as_ref(), id, schedule)
+    }
+
+    /// add a recurrence rule and refresh the week with its occurrences
+    pub fn add_recurrence(&mut self, recurrence: &Recurrence) -> AppResult<()> {
+        recurrence::add_recurrence(self.storage.as_ref(), recurrence)?;
+        self.materialize_occurrences().map(|_| ())
+    }
+
+    /// change the text of this occurrence and the following ones
+    pub fn edit_following_occurrences(&mut self, id: i32, text: String) -> AppResult<()> {
+        recurrence::edit_following_occurrences(self.storage.as_ref(), id, |rule| rule.text = text)?;
+        self.materialize_occurrences().map(|_| ())
+    }
+
+    /// make the occurrences of the recurrence rules in the week, the ones
+    /// not made yet. called when the week is opened, `update()` only reads.
+    /// returns the number of new items.
+    pub fn materialize_occurrences(&mut self) -> AppResult<usize> {
+        let count = recurrence::materialize_between_days(
+            self.storage.as_ref(),
+            self.start_day,
+            self.end_day,
+        )?;
+        self.update()?;
+        Ok(count)
+    }
+
+    /// stop the recurrence from this occurrence on
+    pub fn stop_recurrence(&mut self, id: i32) -> AppResult<usize> {
+        let count = recurrence::stop_recurrence(self.storage.as_ref(), id)?;
+        self.update()?;
+        Ok(count)
+    }
+
+    /// put the tag with the name on the item, the tag is made if there is none
+    pub fn tag_item(&mut self, id: i32, name: &str) -> AppResult<Tag> {
+        let tag = tags::tag_item(self.storage.as_ref(), id, name)?;
+        self.update()?;
+        Ok(tag)
+    }
+
+    /// take the tag off the item
+    pub fn untag_item(&mut self, id: i32, tag_uuid: &str) -> AppResult<()> {
+        tags::untag_item(self.storage.as_ref(), id, tag_uuid)?;
+        self.update()
+    }
+
+    /// link the goal to the objective, or unlink it without one
+    pub fn link_goal_to_objective(
+        &mut self,
+        id: i32,
+        objective_id: Option<i32>,
+    ) -> AppResult<usize> {
+        let result =
+            objective_links::link_goal_to_objective(self.storage.as_ref(), id, objective_id);
+        self.update()?;
+        result
+    }
+
+    /// undo the last item change and refresh the week.
+    /// returns the label of the undone action.
+    pub fn undo(&mut self) -> AppResult<Option<String>> {
+        let label = self.storage.undo()?;
+        self.update()?;
+        Ok(label)
+    }
+
+    /// redo the last undone item change and refresh the week.
+    /// returns the label of the redone action.
+    pub fn redo(&mut self) -> AppResult<Option<String>> {
+        let label = self.storage.redo()?;
+        self.update()?;
+        Ok(label)
     }
 
     pub fn move_item_to_other_time_period_offset(&mut self, id: i32, offset: i32) -> Result<usize> {
-        if let Some(pos) = self.items.iter().position(|item| item.id == id) {
-            let mut item = self.items[pos].clone();
-            item.day += SEVEN_DAY_WEEK_SIZE * offset;
-            item.order_in_week = None;
-            let result = db_sqlite::update_item(&item);
+        self.move_item_with(id, offset, "move item", |_| {})
+    }
+
+    /// pin the item to the day. the day can be in another week, then the item
+    /// moves to the end of that week, with its subitems. events keep their time.
+    pub fn move_item_to_day(&mut self, id: i32, day: i32) -> Result<usize> {
+        if let Some(event) = self.events.iter().find(|event| event.id == id) {
+            let mut schedule = EventSchedule::of(event).ok_or("not an event")?;
+            schedule.day = day;
+            let result = self
+                .reschedule_event(id, &schedule)
+                .map_err(|e| e.to_string());
+            let _ = self.update();
+            return result;
+        }
+        let Some(pos) = self.items.iter().position(|item| item.id == id) else {
+            let _ = self.update();
+            return Err("id not in list!".into());
+        };
+        let mut item = self.items[pos].clone();
+        let shift = get_week_start_middle_end_unix_day(day).0 - self.start_day;
+        let mut ops: Vec<BatchOp> = Vec::new();
+        if shift != 0 {
+            item.order_in_week = Some(self.key_at_end_of_week(day)?);
+            for mut subitem in subitems::descendants_of(&item, &self.subitems) {
+                subitem.day += shift;
+                ops.push(BatchOp::Update(subitem));
+            }
+        }
+        item.day = day;
+        item.fixed_date = true;
+        ops.insert(0, BatchOp::Update(item));
+        let result = self
+            .storage
+            .apply_batch(Some("move item to day"), &ops)
+            .map(|result| result.updated)
+            .map_err(|e| e.to_string());
+        let _ = self.update();
+        result
+    }
+
+    /// the item is not on a day anymore, only in the week
+    pub fn unpin_item(&mut self, id: i32) -> Result<usize> {
+        let Some(pos) = self.items.iter().position(|item| item.id == id) else {
+            let _ = self.update();
+            return Err("id not in list!".into());
+        };
+        let mut item = self.items[pos].clone();
+        item.day = self.middle_day;
+        item.fixed_date = false;
+        let result = self
+            .storage
+            .apply_batch(Some("unpin item"), &[BatchOp::Update(item)])
+            .map(|result| result.updated)
+            .map_err(|e| e.to_string());
+        let _ = self.update();
+        result
+    }
+
+    /// move the item `offset` weeks later, marked as deferred
+    pub fn defer_item(&mut self, id: i32, offset: i32) -> Result<usize> {
+        if offset <= 0 {
+            return Err("an item can only be deferred to a later week".into());
+        }
+        self.move_item_with(id, offset, "defer item", |item| {
+            item.status = Some(ItemStatus::Deferred);
+            item.status_reason = None;
+        })
+    }
+
+    // a new ordering key after the last top item of the week of the day
+    fn key_at_end_of_week(&self, day: i32) -> Result<String> {
+        let (start_day, _, end_day) = get_week_start_middle_end_unix_day(day);
+        let items = self
+            .storage
+            .read_items_between_days(start_day, end_day)
+            .map_err(|e| e.to_string())?;
+        let last = items
+            .into_iter()
+            .filter(|item| item.kind != ItemKind::Event && item.parent_uuid.is_none())
+            .filter_map(|item| item.order_in_week)
+            .max()
+            .unwrap_or_default();
+        Ok(ordering::ordering_keys_after(&last, 1).remove(0))
+    }
+
+    // move the item and its subitems `offset` weeks, with the other changes
+    // of `edit`, as one action. a moved subitem is a top item in its new week.
+    fn move_item_with<F>(&mut self, id: i32, offset: i32, label: &str, edit: F) -> Result<usize>
+    where
+        F: FnOnce(&mut Item),
+    {
+        if let Some(mut item) = self.find_item(id).cloned() {
+            let shift = SEVEN_DAY_WEEK_SIZE * offset;
+            let mut ops: Vec<BatchOp> = subitems::descendants_of(&item, &self.subitems)
+                .into_iter()
+                .map(|mut subitem| {
+                    subitem.day += shift;
+                    BatchOp::Update(subitem)
+                })
+                .collect();
+            item.day += shift;
+            item.order_in_week = Some(self.key_at_end_of_week(item.day)?);
+            item.parent_uuid = None;
+            edit(&mut item);
+            ops.insert(0, BatchOp::Update(item));
+            let result = self
+                .storage
+                .apply_batch(Some(label), &ops)
+                .map(|result| result.updated)
+                .map_err(|e| e.to_string());
             let _ = self.update();
             result
         } else {
@@ -182,6 +643,18 @@ impl Week {
     }
 }
 
+/// start, middle and end unix days of the week that contains `unix_day`,
+/// based on the configured start weekday of the main calendar.
+pub fn get_week_start_middle_end_unix_day(unix_day: i32) -> (i32, i32, i32) {
+    let start_week_day: WeekDaysUnixOffset =
+        config::get_config().main_calendar_start_weekday.into();
+    Week::calculate_week_start_middle_end_unix_day(
+        unix_day,
+        start_week_day as i32,
+        SEVEN_DAY_WEEK_SIZE,
+    )
+}
+
 impl Ordering for Week {
     fn get_keys(&self) -> Vec<Option<String>> {
         self.items.iter().map(|i| i.order_in_week.clone()).collect()
@@ -208,15 +681,38 @@ impl Ordering for Week {
         Some(self.items.get(pos).unwrap().order_in_week.clone())
     }
 
-    fn new_ordering_finished(&self) {
-        let _ = db_sqlite::update_items(&self.items);
+    fn new_ordering_finished(&self) -> AppResult<()> {
+        self.storage.update_items(&self.items)?;
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::carry_over::CarryOverMode;
+    use crate::config::{self, Config};
+    use crate::db_sqlite::BatchOp;
+    use crate::event::EventSchedule;
+    use crate::language::Language;
+    use crate::models::{ItemKind, ItemStatus};
+    use crate::recurrence::Recurrence;
+    use crate::storage::MemoryStorage;
+    use crate::tags;
+    use crate::templates::{self, TemplateItem};
     use crate::week::Week;
     use crate::weekdays::{WeekDaysUnixOffset, SEVEN_DAY_WEEK_SIZE};
+    use crate::year::Year;
+    use std::sync::{Arc, MutexGuard};
+
+    // the guard keeps the config of the test until its end
+    fn memory_week() -> (MutexGuard<'static, ()>, Week) {
+        let guard = config::set_test_config(Config::default());
+        (guard, Week::with_storage(Arc::new(MemoryStorage::new())))
+    }
+
+    fn week_texts(week: &Week) -> Vec<String> {
+        week.get_view().items.into_iter().map(|i| i.text).collect()
+    }
 
     fn check_correct_reference_from_persian_dates(
         dates: Vec<ptime::Tm>,
@@ -301,4 +797,553 @@ mod tests {
         pt_vec.push(pt);
         assert!(check_correct_reference_from_persian_dates(pt_vec, 19927));
     }
+
+    #[test]
+    fn test_week_navigation_and_ordering_in_memory() {
+        let (_config, mut week) = memory_week();
+        let first = week
+            .add_new_item(ItemKind::Goal, "first".into(), None)
+            .unwrap();
+        week.update().unwrap();
+        week.add_new_item(ItemKind::Goal, "last".into(), None)
+            .unwrap();
+        week.update().unwrap();
+        week.add_new_item(ItemKind::Goal, "second".into(), Some(first))
+            .unwrap();
+        week.update().unwrap();
+        assert_eq!(week_texts(&week), vec!["first", "second", "last"]);
+
+        week.next().unwrap();
+        assert!(week.items.is_empty());
+        week.previous().unwrap();
+        assert_eq!(week_texts(&week), vec!["first", "second", "last"]);
+    }
+
+    #[test]
+    fn test_week_move_item_and_undo_in_memory() {
+        let (_config, mut week) = memory_week();
+        let id = week
+            .add_new_item(ItemKind::Goal, "moving".into(), None)
+            .unwrap();
+        week.update().unwrap();
+
+        week.move_item_to_other_time_period_offset(id, 1).unwrap();
+        assert!(week.items.is_empty());
+        week.next().unwrap();
+        assert_eq!(week_texts(&week), vec!["moving"]);
+        // the moved item got a new ordering key in its new week
+        assert!(week.items[0].order_in_week.is_some());
+
+        assert_eq!(week.undo().unwrap(), Some("move item".to_string()));
+        assert!(week.items.is_empty());
+        week.previous().unwrap();
+        assert_eq!(week_texts(&week), vec!["moving"]);
+        assert_eq!(week.items[0].id, id);
+    }
+
+    #[test]
+    fn test_year_move_item_and_undo_in_memory() {
+        let (_config, week) = memory_week();
+        let mut year = Year::with_storage(week.storage.clone());
+        let id = year
+            .add_new_item(ItemKind::Goal, "moving".into(), None)
+            .unwrap();
+        year.next().unwrap();
+        year.add_new_item(ItemKind::Goal, "there".into(), None)
+            .unwrap();
+        year.previous().unwrap();
+
+        year.move_item_to_other_time_period_offset(id, 1).unwrap();
+        assert!(year.items.is_empty());
+        year.next().unwrap();
+        // at the end of the list of the next year, no key is repaired
+        let texts: Vec<String> = year.items.iter().filter_map(|i| i.title.clone()).collect();
+        assert_eq!(texts, vec!["there", "moving"]);
+
+        assert_eq!(year.undo().unwrap(), Some("move item".to_string()));
+        assert_eq!(year.items.len(), 1);
+        year.previous().unwrap();
+        assert_eq!(year.items[0].id, id);
+        assert_eq!(year.undo().unwrap(), Some("add item".to_string()));
+    }
+
+    #[test]
+    fn test_item_status_changes_in_memory() {
+        let (_config, mut week) = memory_week();
+        let id = week
+            .add_new_item(ItemKind::Goal, "waiting".into(), None)
+            .unwrap();
+        let storage = week.storage.clone();
+
+        assert!(storage
+            .set_item_status(id, ItemStatus::Blocked, None)
+            .is_err());
+        storage
+            .set_item_status(id, ItemStatus::Blocked, Some("the review".into()))
+            .unwrap();
+        let item = storage.get_item(id).unwrap();
+        assert_eq!(item.status, Some(ItemStatus::Blocked));
+        assert_eq!(item.status_reason.as_deref(), Some("the review"));
+
+        storage
+            .set_item_status(id, ItemStatus::Cancelled, None)
+            .unwrap();
+        assert_eq!(storage.get_item(id).unwrap().status_reason, None);
+        // closed items can only be opened again
+        assert!(storage
+            .set_item_status(id, ItemStatus::Blocked, Some("x".into()))
+            .is_err());
+        storage
+            .set_item_status(id, ItemStatus::InProgress, None)
+            .unwrap();
+
+        week.update().unwrap();
+        week.defer_item(id, 2).unwrap();
+        assert!(week.items.is_empty());
+        week.next().unwrap();
+        week.next().unwrap();
+        assert_eq!(week.items[0].status, Some(ItemStatus::Deferred));
+        assert_eq!(week.get_view().items[0].state, ItemStatus::Deferred);
+    }
+
+    #[test]
+    fn test_week_events_by_day_in_memory() {
+        let (_config, mut week) = memory_week();
+        week.add_new_item(ItemKind::Note, "a note".into(), None)
+            .unwrap();
+        let day = week.start_day + 2;
+        let late = week
+            .add_new_event("late".into(), &EventSchedule::at(day, "18:00", Some(30)))
+            .unwrap();
+        week.add_new_event("early".into(), &EventSchedule::at(day, "08:15", None))
+            .unwrap();
+        week.add_new_event("holiday".into(), &EventSchedule::all_day(day))
+            .unwrap();
+        assert!(week
+            .add_new_event("bad".into(), &EventSchedule::at(day, "25:00", None))
+            .is_err());
+        week.update().unwrap();
+
+        assert_eq!(week_texts(&week), vec!["a note"]);
+        let view = week.get_view();
+        assert_eq!(view.days.len(), 7);
+        let texts: Vec<String> = view.days[2].events.iter().map(|e| e.text.clone()).collect();
+        assert_eq!(texts, vec!["holiday", "early", "late"]);
+
+        week.reschedule_event(late, &EventSchedule::at(day + 1, "07:00", Some(60)))
+            .unwrap();
+        week.update().unwrap();
+        let view = week.get_view();
+        assert_eq!(view.days[3].events[0].text, "late");
+        let schedule = view.days[3].events[0].event.clone().unwrap();
+        assert_eq!(schedule.start_time.as_deref(), Some("07:00"));
+        assert_eq!(schedule.duration, Some(60));
+    }
+
+    #[test]
+    fn test_week_pinned_items_in_memory() {
+        let (_config, mut week) = memory_week();
+        let id = week
+            .add_new_item(ItemKind::Goal, "on tuesday".into(), None)
+            .unwrap();
+        week.update().unwrap();
+        assert!(week.get_view().items[0].fixed_day_tag.is_none());
+
+        week.move_item_to_day(id, week.start_day + 1).unwrap();
+        let view = week.get_view();
+        assert_eq!(view.days[1].items[0].text, "on tuesday");
+        assert!(view.items[0].fixed_day_tag.is_some());
+
+        // dragged to another day of the week, then moved with the week
+        week.move_item_to_day(id, week.start_day + 4).unwrap();
+        assert!(week.get_view().days[1].items.is_empty());
+        assert_eq!(week.get_view().days[4].items.len(), 1);
+        week.move_item_to_other_time_period_offset(id, 1).unwrap();
+        week.next().unwrap();
+        assert_eq!(week.get_view().days[4].items[0].text, "on tuesday");
+
+        week.unpin_item(id).unwrap();
+        assert!(week.get_view().days.iter().all(|day| day.items.is_empty()));
+        assert_eq!(week.items[0].day, week.middle_day);
+    }
+
+    #[test]
+    fn test_week_recurring_items_in_memory() {
+        let (_config, mut week) = memory_week();
+        let start = week.start_day;
+        let id_of = |week: &Week, text: &str| {
+            let view = week.get_view();
+            view.items.iter().find(|item| item.text == text).unwrap().id
+        };
+        let weekday = WeekDaysUnixOffset::from((start + 1).rem_euclid(SEVEN_DAY_WEEK_SIZE));
+        week.add_recurrence(&Recurrence::weekly(
+            ItemKind::Goal,
+            "gym".into(),
+            start,
+            1,
+            &[],
+        ))
+        .unwrap();
+        week.add_recurrence(&Recurrence::weekly(
+            ItemKind::Note,
+            "review".into(),
+            start,
+            2,
+            &[weekday],
+        ))
+        .unwrap();
+        // both rules may be added in the same second
+        let mut texts = week_texts(&week);
+        texts.sort();
+        assert_eq!(texts, vec!["gym", "review"]);
+        assert_eq!(week.get_view().days[1].items[0].text, "review");
+        assert!(week.get_view().items.iter().all(|item| item.recurring));
+
+        // each occurrence is done on its own
+        let gym = id_of(&week, "gym");
+        week.storage.toggle_item_state(gym).unwrap();
+        week.next().unwrap();
+        // made only when the week is opened
+        assert!(week.items.is_empty());
+        assert_eq!(week.materialize_occurrences().unwrap(), 1);
+        assert_eq!(week_texts(&week), vec!["gym"]);
+        assert_eq!(week.items[0].status, Some(ItemStatus::Undone));
+
+        // a trashed occurrence is not made again, nor a removed one
+        let removed = week.items[0].id;
+        week.storage.remove_item(removed).unwrap();
+        assert_eq!(week.materialize_occurrences().unwrap(), 0);
+        week.storage
+            .apply_batch(None, &[BatchOp::Delete(removed)])
+            .unwrap();
+        assert_eq!(week.materialize_occurrences().unwrap(), 0);
+        assert!(week.items.is_empty());
+
+        // the following occurrences keep their state
+        week.next().unwrap();
+        week.materialize_occurrences().unwrap();
+        let review = id_of(&week, "review");
+        let gym = id_of(&week, "gym");
+        week.storage.toggle_item_state(gym).unwrap();
+        week.edit_following_occurrences(gym, "gym 4x".into())
+            .unwrap();
+        let mut texts = week_texts(&week);
+        texts.sort();
+        assert_eq!(texts, vec!["gym 4x", "review"]);
+        assert_eq!(id_of(&week, "gym 4x"), gym);
+        assert_eq!(
+            week.storage.get_item(gym).unwrap().status,
+            Some(ItemStatus::Done)
+        );
+        week.stop_recurrence(review).unwrap();
+        assert_eq!(week_texts(&week), vec!["gym 4x"]);
+        week.next().unwrap();
+        week.next().unwrap();
+        week.materialize_occurrences().unwrap();
+        assert_eq!(week_texts(&week), vec!["gym 4x"]);
+
+        // the past keeps its text
+        week.current().unwrap();
+        let gym = id_of(&week, "gym");
+        assert_eq!(
+            week.storage.get_item(gym).unwrap().status,
+            Some(ItemStatus::Done)
+        );
+    }
+
+    #[test]
+    fn test_week_subitems_in_memory() {
+        let (_config, mut week) = memory_week();
+        let parent = week
+            .add_new_item(ItemKind::Goal, "trip".into(), None)
+            .unwrap();
+        week.update().unwrap();
+        let tickets = week
+            .add_new_subitem(parent, ItemKind::Goal, "tickets".into(), None)
+            .unwrap();
+        week.update().unwrap();
+        week.add_new_subitem(parent, ItemKind::Goal, "hotel".into(), None)
+            .unwrap();
+        week.update().unwrap();
+        week.add_new_subitem(parent, ItemKind::Goal, "visa".into(), Some(tickets))
+            .unwrap();
+        week.update().unwrap();
+        let bag = week
+            .add_new_subitem(tickets, ItemKind::Note, "bag".into(), None)
+            .unwrap();
+        week.update().unwrap();
+
+        let view = week.get_view();
+        assert_eq!(view.items.len(), 1);
+        let children: Vec<&str> = view.items[0]
+            .children
+            .iter()
+            .map(|child| child.text.as_str())
+            .collect();
+        assert_eq!(children, vec!["tickets", "visa", "hotel"]);
+        assert_eq!(view.items[0].children[0].children[0].text, "bag");
+        let rollup = view.items[0].rollup.clone().unwrap();
+        assert_eq!((rollup.done, rollup.total, rollup.complete), (0, 3, false));
+
+        for child in view.items[0].children.iter() {
+            week.storage.toggle_item_state(child.id).unwrap();
+        }
+        week.update().unwrap();
+        assert!(week.get_view().items[0].rollup.clone().unwrap().complete);
+
+        // not under its own subitem
+        assert!(week.set_item_parent(tickets, Some(bag)).is_err());
+        week.set_item_parent(bag, None).unwrap();
+        assert_eq!(week.get_view().items.len(), 2);
+
+        // the subitems move with their parent
+        week.move_item_to_other_time_period_offset(parent, 1)
+            .unwrap();
+        assert_eq!(week_texts(&week), vec!["bag"]);
+        week.next().unwrap();
+        assert_eq!(week.get_view().items[0].children.len(), 3);
+        assert_eq!(week.subitems.len(), 3);
+    }
+
+    #[test]
+    fn test_week_tags_in_memory() {
+        let (_config, mut week) = memory_week();
+        let run = week
+            .add_new_item(ItemKind::Goal, "run".into(), None)
+            .unwrap();
+        let call = week
+            .add_new_item(ItemKind::Note, "call".into(), None)
+            .unwrap();
+        week.update().unwrap();
+        let health = week.tag_item(run, " Health ").unwrap();
+        assert_eq!(health.name, "Health");
+        // the same tag, by its key
+        assert_eq!(week.tag_item(call, "HEALTH").unwrap().uuid, health.uuid);
+        // the Arabic yeh and kaf are the Persian ones
+        let family = week
+            .tag_item(call, "\u{06A9}\u{0627}\u{0631}\u{06CC}")
+            .unwrap();
+        assert_eq!(
+            week.tag_item(run, "\u{0643}\u{0627}\u{0631}\u{064A}")
+                .unwrap()
+                .uuid,
+            family.uuid
+        );
+        assert_eq!(week.get_view().items[1].tags.len(), 2);
+
+        week.move_item_to_other_time_period_offset(call, 3).unwrap();
+        let storage = week.storage.clone();
+        let tagged = tags::items_with_tag_between_days(
+            storage.as_ref(),
+            &health.uuid,
+            week.start_day,
+            week.end_day + 3 * SEVEN_DAY_WEEK_SIZE,
+        )
+        .unwrap();
+        assert_eq!(tagged.len(), 2);
+        assert_eq!(tagged[1].id, call);
+
+        assert!(tags::rename_tag(storage.as_ref(), &health.uuid, &family.name).is_err());
+        tags::rename_tag(storage.as_ref(), &health.uuid, "sport").unwrap();
+        // the Persian script comes first in Persian
+        let mut all = tags::read_tags(storage.as_ref()).unwrap();
+        assert_eq!(all[0].name, "sport");
+        tags::sort_tags(&mut all, &Language::Farsi);
+        assert_eq!(all[0].uuid, family.uuid);
+        tags::remove_tag(storage.as_ref(), &family.uuid).unwrap();
+        week.update().unwrap();
+        assert_eq!(week.get_view().items[0].tags[0].name, "sport");
+        assert_eq!(week.get_view().items[0].tags.len(), 1);
+    }
+
+    #[test]
+    fn test_week_goals_for_objectives_in_memory() {
+        let (_config, mut week) = memory_week();
+        let mut year = Year::with_storage(week.storage.clone());
+        let objective = year
+            .add_new_item(ItemKind::Goal, "learn piano".into(), None)
+            .unwrap();
+        year.update().unwrap();
+        assert!(year.get_view().items[0].progress.is_none());
+
+        let first = week
+            .add_new_item(ItemKind::Goal, "scales".into(), None)
+            .unwrap();
+        let note = week
+            .add_new_item(ItemKind::Note, "a note".into(), None)
+            .unwrap();
+        week.update().unwrap();
+        assert!(week.link_goal_to_objective(note, Some(objective)).is_err());
+        assert!(week.link_goal_to_objective(first, Some(first)).is_err());
+        week.link_goal_to_objective(first, Some(objective)).unwrap();
+        let parent = week.get_view().items[0].parent_objective.clone().unwrap();
+        assert_eq!(parent.text, "learn piano");
+        assert!(parent.objective_tag.is_some());
+
+        week.storage.toggle_item_state(first).unwrap();
+        week.next().unwrap();
+        let second = week
+            .add_new_item(ItemKind::Goal, "a song".into(), None)
+            .unwrap();
+        week.link_goal_to_objective(second, Some(objective))
+            .unwrap();
+        year.update().unwrap();
+        let progress = year.get_view().items[0].progress.clone().unwrap();
+        assert_eq!(
+            (progress.done, progress.total, progress.complete),
+            (1, 2, false)
+        );
+
+        week.link_goal_to_objective(second, None).unwrap();
+        assert!(week.get_view().items[0].parent_objective.is_none());
+        year.update().unwrap();
+        assert!(year.get_view().items[0].progress.clone().unwrap().complete);
+
+        // a copied goal counts once, as its copy
+        week.link_goal_to_objective(second, Some(objective))
+            .unwrap();
+        week.next().unwrap();
+        assert_eq!(week.carry_over(CarryOverMode::Copy).unwrap(), 1);
+        year.update().unwrap();
+        let progress = year.get_view().items[0].progress.clone().unwrap();
+        assert_eq!((progress.done, progress.total), (1, 2));
+        week.storage.toggle_item_state(week.items[0].id).unwrap();
+        year.update().unwrap();
+        assert!(year.get_view().items[0].progress.clone().unwrap().complete);
+    }
+
+    #[test]
+    fn test_week_carry_over_in_memory() {
+        let (_config, mut week) = memory_week();
+        week.previous().unwrap();
+        let trip = week
+            .add_new_item(ItemKind::Goal, "trip".into(), None)
+            .unwrap();
+        week.update().unwrap();
+        week.add_new_subitem(trip, ItemKind::Goal, "tickets".into(), None)
+            .unwrap();
+        let done = week
+            .add_new_item(ItemKind::Goal, "done".into(), None)
+            .unwrap();
+        week.add_new_item(ItemKind::Note, "a note".into(), None)
+            .unwrap();
+        week.storage.toggle_item_state(done).unwrap();
+        week.tag_item(trip, "travel").unwrap();
+
+        week.next().unwrap();
+        week.add_new_item(ItemKind::Goal, "new".into(), None)
+            .unwrap();
+        week.update().unwrap();
+        assert_eq!(week.goals_to_carry_over().unwrap().len(), 1);
+        assert_eq!(week.carry_over(CarryOverMode::Copy).unwrap(), 1);
+        let copy_uuid = week.get_view().items[1].uuid.clone().unwrap();
+        // undone with the tags of the copies
+        assert_eq!(week.undo().unwrap().as_deref(), Some("carry over goals"));
+        assert!(week
+            .storage
+            .read_item_tags(&[copy_uuid])
+            .unwrap()
+            .is_empty());
+        assert_eq!(week.carry_over(CarryOverMode::Copy).unwrap(), 1);
+        // copied only once
+        assert_eq!(week.carry_over(CarryOverMode::Copy).unwrap(), 0);
+        assert_eq!(week_texts(&week), vec!["new", "trip"]);
+        let copy = week.get_view().items[1].clone();
+        assert_eq!(copy.carry_count, 1);
+        assert_eq!(copy.children[0].text, "tickets");
+        assert_eq!(copy.tags[0].name, "travel");
+        // not again when the copy is trashed
+        week.storage.remove_item(copy.id).unwrap();
+        assert!(week.goals_to_carry_over().unwrap().is_empty());
+        week.undo().unwrap();
+
+        week.previous().unwrap();
+        week.add_new_item(ItemKind::Goal, "call".into(), None)
+            .unwrap();
+        week.update().unwrap();
+        assert_eq!(week_texts(&week), vec!["trip", "done", "a note", "call"]);
+
+        // moved when the current week is shown, by the config
+        config::set_config(Config {
+            carry_over: "move".into(),
+            ..Config::default()
+        });
+        week.current().unwrap();
+        assert_eq!(week_texts(&week), vec!["new", "trip", "call"]);
+        assert_eq!(week.get_view().items[2].carry_count, 1);
+        assert_eq!(week.undo().unwrap().as_deref(), Some("carry over goals"));
+        week.previous().unwrap();
+        assert_eq!(week_texts(&week), vec!["trip", "done", "a note", "call"]);
+    }
+
+    #[test]
+    fn test_week_templates_in_memory() {
+        let (_config, mut week) = memory_week();
+        week.add_new_item(ItemKind::Goal, "existing".into(), None)
+            .unwrap();
+        week.update().unwrap();
+        let items = vec![
+            TemplateItem {
+                kind: ItemKind::Goal,
+                text: "review {start} to {end}".into(),
+                subitems: vec![TemplateItem {
+                    kind: ItemKind::Goal,
+                    text: "inbox".into(),
+                    subitems: vec![],
+                }],
+            },
+            TemplateItem {
+                kind: ItemKind::Note,
+                text: "plan {unknown}{start_aux}".into(),
+                subitems: vec![],
+            },
+        ];
+        let template = templates::save_template(week.storage.as_ref(), "weekly", items).unwrap();
+        assert_eq!(week.apply_template(&template.uuid).unwrap(), 3);
+        let texts = week_texts(&week);
+        assert_eq!(texts.len(), 3);
+        assert_eq!(texts[0], "existing");
+        assert!(texts[1].starts_with("review ") && !texts[1].contains('{'));
+        assert_eq!(texts[2], "plan {unknown}");
+        assert_eq!(week.subitems.len(), 1);
+        assert_eq!(week.subitems[0].parent_uuid, week.items[1].uuid);
+
+        // the whole template is undone at once
+        assert_eq!(week.undo().unwrap().as_deref(), Some("apply template"));
+        assert_eq!(week_texts(&week), vec!["existing"]);
+        assert!(week.subitems.is_empty());
+        week.redo().unwrap();
+
+        // the same name replaces the items, without the occurrences
+        let start = week.start_day;
+        week.add_recurrence(&Recurrence::weekly(
+            ItemKind::Goal,
+            "weekly review".into(),
+            start,
+            1,
+            &[],
+        ))
+        .unwrap();
+        assert_eq!(week_texts(&week).len(), 4);
+        let saved = week.save_as_template(" weekly ").unwrap();
+        assert_eq!(saved.uuid, template.uuid);
+        assert_eq!(saved.items.len(), 3);
+        assert_eq!(saved.items[1].subitems.len(), 1);
+
+        let path = std::env::temp_dir().join(format!("templates-{}.toml", template.uuid));
+        assert_eq!(
+            templates::export_templates(week.storage.as_ref(), &path).unwrap(),
+            1
+        );
+        let other = Week::with_storage(Arc::new(MemoryStorage::new()));
+        assert_eq!(
+            templates::import_templates(other.storage.as_ref(), &path).unwrap(),
+            1
+        );
+        assert_eq!(
+            templates::read_templates(other.storage.as_ref()).unwrap(),
+            vec![saved]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
 }
@@ -1,3 +1,4 @@
+use crate::config;
 use crate::{calendar::Calendar, language::Language, prelude::Result as AppResult};
 use serde::Serialize;
 
@@ -87,3 +88,33 @@ impl WeekInfo {
         )
     }
 }
+
+/// a short text of the day in the main calendar, and the secondary one if
+/// it's set. like "Tue 12 Mar / ۲۲ اسفند"
+pub fn day_tag(day: i32) -> String {
+    let main_pair = config::get_main_cal_lang_pair();
+    let main = main_pair.calendar.get_date_view(day, &main_pair.language);
+    let mut tag = format!(
+        "{} {} {}",
+        short_name(&main.weekday, &main_pair.language),
+        main.day,
+        short_name(&main.month, &main_pair.language)
+    );
+    if let Some(pair) = config::get_second_cal_lang_pair() {
+        let aux = pair.calendar.get_date_view(day, &pair.language);
+        tag = format!(
+            "{tag} / {} {}",
+            aux.day,
+            short_name(&aux.month, &pair.language)
+        );
+    }
+    tag
+}
+
+// english names are shortened to three letters, the others are kept
+fn short_name(name: &str, language: &Language) -> String {
+    match language {
+        Language::English => name.chars().take(3).collect(),
+        _ => name.to_string(),
+    }
+}
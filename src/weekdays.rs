@@ -12,6 +12,7 @@ pub const SEVEN_DAY_WEEK_SIZE: i32 = 7;
 // pub const WEEKDAY_UNIX_OFFSET_WED: i32 = 6;
 
 #[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum WeekDaysUnixOffset {
     Thu = 0,
     Fri = 1,
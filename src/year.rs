@@ -1,28 +1,54 @@
 use crate::calendar::Calendar;
 use crate::config;
-use crate::db_sqlite;
+use crate::db_sqlite::BatchOp;
 use crate::language::Language;
+use crate::objective_links;
 use crate::ordering::Result;
+use crate::prelude::Error as AppError;
 use crate::prelude::Result as AppResult;
+use crate::recurrence::{self, Recurrence};
+use crate::storage::{self, Storage};
+use crate::subitems::{self, Subitems};
+use crate::tags::{self, Tag};
 use crate::today;
-use crate::{models::*, ordering::Ordering};
+use crate::{models::*, ordering, ordering::Ordering};
 use serde::Serialize;
+use std::sync::Arc;
 
 const MAIN_CALENDAR: u32 = 0;
 const SECONDARY_CALENDAR: u32 = 1;
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct Year {
     pub reference_year: i32,
     pub reference_calendar: u32,
     pub calendar: Calendar,
     pub language: Language,
     pub items: Vec<Item>,
+    // the subitems of the objectives, at all levels, in their order
+    pub subitems: Vec<Item>,
+    // where the items are read from and written to
+    pub storage: Arc<dyn Storage>,
 
     // for view only
     pub year_view: YearView,
 }
 
+impl Default for Year {
+    fn default() -> Self {
+        Year {
+            reference_year: 0,
+            reference_calendar: MAIN_CALENDAR,
+            calendar: Calendar::default(),
+            language: Language::default(),
+            items: vec![],
+            subitems: vec![],
+            storage: storage::default_storage(),
+            year_view: YearView::default(),
+        }
+    }
+}
+
 #[derive(Serialize, Clone, Debug, Default)]
 pub struct YearView {
     pub year: String,
@@ -33,8 +59,13 @@ pub struct YearView {
 
 impl Year {
     pub fn new() -> Year {
+        Year::with_storage(storage::default_storage())
+    }
+
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Year {
         let mut year = Year {
             reference_calendar: MAIN_CALENDAR,
+            storage,
             ..Default::default()
         };
         let _ = year.current();
@@ -44,26 +75,51 @@ impl Year {
     pub fn update(&mut self) -> Result<()> {
         let main_pair = config::get_main_cal_lang_pair();
         let second_pair = config::get_second_cal_lang_pair();
-        if self.reference_calendar == SECONDARY_CALENDAR && second_pair.is_some() {
-            self.calendar = second_pair.clone().unwrap().calendar;
-            self.language = second_pair.unwrap().language;
-        } else {
-            // MAIN_CALENDAR
-            self.calendar = main_pair.calendar;
-            self.language = main_pair.language;
+        let previous_calendar = self.calendar.clone();
+        match second_pair {
+            Some(pair) if self.reference_calendar == SECONDARY_CALENDAR => {
+                self.calendar = pair.calendar;
+                self.language = pair.language;
+            }
+            _ => {
+                // MAIN_CALENDAR
+                self.calendar = main_pair.calendar;
+                self.language = main_pair.language;
+            }
+        }
+        // the year number belongs to the calendar. when the calendar is changed
+        // in the config (like by switching the profile), go to its current year.
+        if self.calendar != previous_calendar {
+            self.reference_year = today::get_today_date(&self.calendar).year;
         }
 
         // update items
-        let items = db_sqlite::read_items_in_calendar_year(
-            self.calendar.clone().into(),
-            self.reference_year,
-        )?;
+        let items = self
+            .storage
+            .read_items_in_calendar_year(self.calendar.clone().into(), self.reference_year)
+            .map_err(|e| e.to_string())?;
+        let (items, subitems) = subitems::split_subitems(items);
         self.items = items;
-        self.check_and_fix_ordering();
+        self.subitems = subitems;
+        self.check_and_fix_ordering().map_err(|e| e.to_string())?;
+        subitems::fix_ordering(&mut self.subitems, true, &self.storage)
+            .map_err(|e| e.to_string())?;
 
         // update yearly view
         self.update_year_title_info();
-        self.year_view.items = self.items.iter().map(ItemView::from).collect();
+        self.year_view.items = subitems::nested_views(&self.items, &self.subitems);
+        let item_tags = tags::tags_of_items(
+            self.storage.as_ref(),
+            self.items.iter().chain(self.subitems.iter()),
+        )
+        .map_err(|e| e.to_string())?;
+        tags::fill_views(&mut self.year_view.items, &item_tags);
+        let progress = objective_links::progress_of_objectives(
+            self.storage.as_ref(),
+            self.items.iter().chain(self.subitems.iter()),
+        )
+        .map_err(|e| e.to_string())?;
+        objective_links::fill_progress(&mut self.year_view.items, &progress);
         Ok(())
     }
 
@@ -105,7 +161,7 @@ impl Year {
 
     pub fn add_new_item(
         &mut self,
-        kind: i32,
+        kind: ItemKind,
         text: String,
         after_id: Option<i32>,
     ) -> AppResult<i32> {
@@ -123,7 +179,78 @@ impl Year {
             text,
             ordering_key,
         );
-        db_sqlite::create_item(&new_item)
+        self.storage.create_item(&new_item)
+    }
+
+    /// add a subitem to the objective, after `after_id` or at the end
+    pub fn add_new_subitem(
+        &mut self,
+        parent_id: i32,
+        kind: ItemKind,
+        text: String,
+        after_id: Option<i32>,
+    ) -> AppResult<i32> {
+        let parent = self
+            .find_item(parent_id)
+            .cloned()
+            .ok_or(AppError::InvalidSubitemError(format!(
+                "item {parent_id} is not in the year"
+            )))?;
+        let ordering_key = self.subitems_of(parent_id).get_new_ordering_key(after_id);
+        let new_item = subitems::new_subitem(&parent, kind, text, ordering_key)?;
+        self.storage.create_item(&new_item)
+    }
+
+    /// the subitems of the objective, as a list for their ordering keys
+    pub fn subitems_of(&self, parent_id: i32) -> Subitems {
+        let parent_uuid = self.find_item(parent_id).and_then(|item| item.uuid.clone());
+        Subitems::of(
+            parent_uuid.as_deref(),
+            &self.subitems,
+            true,
+            self.storage.clone(),
+        )
+    }
+
+    /// put the item (with its subitems) at the end of the parent's subitems.
+    /// without a parent, it becomes a top objective at the end of the year.
+    pub fn set_item_parent(&mut self, id: i32, parent_id: Option<i32>) -> Result<usize> {
+        let Some(mut item) = self.find_item(id).cloned() else {
+            let _ = self.update();
+            return Err("id not in list!".into());
+        };
+        match parent_id {
+            Some(parent_id) => {
+                let parent = self
+                    .find_item(parent_id)
+                    .cloned()
+                    .ok_or("parent id not in list!")?;
+                subitems::check_new_parent(&item, &parent, &self.subitems)
+                    .map_err(|e| e.to_string())?;
+                item.order_in_resolution =
+                    Some(self.subitems_of(parent_id).get_new_ordering_key(None));
+                item.parent_uuid = parent.uuid;
+            }
+            None => {
+                item.order_in_resolution = Some(self.get_new_ordering_key(None));
+                item.parent_uuid = None;
+            }
+        }
+        let result = self
+            .storage
+            .apply_batch(Some("change item parent"), &[BatchOp::Update(item)])
+            .map(|result| result.updated)
+            .map_err(|e| e.to_string());
+        let _ = self.update();
+        result
+    }
+
+    // an objective of the year, a top item or a subitem
+    fn find_item(&self, id: i32) -> Option<&Item> {
+        self.items
+            .iter()
+            .chain(self.subitems.iter())
+            .find(|item| item.id == id)
     }
 
     pub fn switch_calendar(&mut self) -> Result<()> {
@@ -131,24 +258,140 @@ impl Year {
         let aux_cal: Option<Calendar> = config::get_config()
             .secondary_calendar_type
             .map(|s| s.into());
-        if self.reference_calendar == MAIN_CALENDAR && aux_cal.is_some() {
-            self.reference_calendar = SECONDARY_CALENDAR;
-            self.calendar = aux_cal.unwrap();
-        } else {
-            self.reference_calendar = MAIN_CALENDAR;
-            self.calendar = main_cal;
+        match aux_cal {
+            Some(aux_cal) if self.reference_calendar == MAIN_CALENDAR => {
+                self.reference_calendar = SECONDARY_CALENDAR;
+                self.calendar = aux_cal;
+            }
+            _ => {
+                self.reference_calendar = MAIN_CALENDAR;
+                self.calendar = main_cal;
+            }
         }
         // self.update()
         self.current()
     }
 
+    /// add a recurrence rule and refresh the year with its occurrences
+    pub fn add_recurrence(&mut self, recurrence: &Recurrence) -> Result<()> {
+        recurrence::add_recurrence(self.storage.as_ref(), recurrence).map_err(|e| e.to_string())?;
+        self.materialize_occurrences().map(|_| ())
+    }
+
+    /// change the text of this occurrence and the following ones
+    pub fn edit_following_occurrences(&mut self, id: i32, text: String) -> Result<()> {
+        recurrence::edit_following_occurrences(self.storage.as_ref(), id, |rule| rule.text = text)
+            .map_err(|e| e.to_string())?;
+        self.materialize_occurrences().map(|_| ())
+    }
+
+    /// make the objective occurrences of the recurrence rules in the year, the
+    /// ones not made yet. called when the year is opened, `update()` only
+    /// reads. returns the number of new items.
+    pub fn materialize_occurrences(&mut self) -> Result<usize> {
+        let count = recurrence::materialize_in_calendar_year(
+            self.storage.as_ref(),
+            self.calendar.clone().into(),
+            self.reference_year,
+        )
+        .map_err(|e| e.to_string())?;
+        self.update()?;
+        Ok(count)
+    }
+
+    /// stop the recurrence from this occurrence on
+    pub fn stop_recurrence(&mut self, id: i32) -> Result<usize> {
+        let count =
+            recurrence::stop_recurrence(self.storage.as_ref(), id).map_err(|e| e.to_string())?;
+        self.update()?;
+        Ok(count)
+    }
+
+    /// put the tag with the name on the objective, the tag is made if there is none
+    pub fn tag_item(&mut self, id: i32, name: &str) -> Result<Tag> {
+        let tag = tags::tag_item(self.storage.as_ref(), id, name).map_err(|e| e.to_string())?;
+        self.update()?;
+        Ok(tag)
+    }
+
+    /// take the tag off the objective
+    pub fn untag_item(&mut self, id: i32, tag_uuid: &str) -> Result<()> {
+        tags::untag_item(self.storage.as_ref(), id, tag_uuid).map_err(|e| e.to_string())?;
+        self.update()?;
+        Ok(())
+    }
+
+    /// undo the last item change and refresh the year.
+    /// returns the label of the undone action.
+    pub fn undo(&mut self) -> Result<Option<String>> {
+        let label = self.storage.undo().map_err(|e| e.to_string())?;
+        self.update()?;
+        Ok(label)
+    }
+
+    /// redo the last undone item change and refresh the year.
+    /// returns the label of the redone action.
+    pub fn redo(&mut self) -> Result<Option<String>> {
+        let label = self.storage.redo().map_err(|e| e.to_string())?;
+        self.update()?;
+        Ok(label)
+    }
+
     pub fn move_item_to_other_time_period_offset(&mut self, id: i32, offset: i32) -> Result<usize> {
-        if let Some(pos) = self.items.iter().position(|item| item.id == id) {
-            let mut item = self.items[pos].clone();
+        self.move_item_with(id, offset, "move item", |_| {})
+    }
+
+    /// move the objective `offset` years later, marked as deferred
+    pub fn defer_item(&mut self, id: i32, offset: i32) -> Result<usize> {
+        if offset <= 0 {
+            return Err("an item can only be deferred to a later year".into());
+        }
+        self.move_item_with(id, offset, "defer item", |item| {
+            item.status = Some(ItemStatus::Deferred);
+            item.status_reason = None;
+        })
+    }
+
+    // a new ordering key after the last top objective of the calendar year
+    fn key_at_end_of_year(&self, calendar: i32, year: i32) -> Result<String> {
+        let items = self
+            .storage
+            .read_items_in_calendar_year(calendar, year)
+            .map_err(|e| e.to_string())?;
+        let last = items
+            .into_iter()
+            .filter(|item| item.parent_uuid.is_none())
+            .filter_map(|item| item.order_in_resolution)
+            .max()
+            .unwrap_or_default();
+        Ok(ordering::ordering_keys_after(&last, 1).remove(0))
+    }
+
+    // move the item and its subitems `offset` years, with the other changes
+    // of `edit`, as one action. a moved subitem is a top item in its new year.
+    fn move_item_with<F>(&mut self, id: i32, offset: i32, label: &str, edit: F) -> Result<usize>
+    where
+        F: FnOnce(&mut Item),
+    {
+        if let Some(mut item) = self.find_item(id).cloned() {
             let year = item.year.unwrap_or(self.reference_year) + offset;
+            let mut ops: Vec<BatchOp> = subitems::descendants_of(&item, &self.subitems)
+                .into_iter()
+                .map(|mut subitem| {
+                    subitem.year = Some(year);
+                    BatchOp::Update(subitem)
+                })
+                .collect();
+            item.order_in_resolution = Some(self.key_at_end_of_year(item.calendar, year)?);
             item.year = Some(year);
-            item.order_in_resolution = None;
-            let result = db_sqlite::update_item(&item);
+            item.parent_uuid = None;
+            edit(&mut item);
+            ops.insert(0, BatchOp::Update(item));
+            let result = self
+                .storage
+                .apply_batch(Some(label), &ops)
+                .map(|result| result.updated)
+                .map_err(|e| e.to_string());
             let _ = self.update();
             result
         } else {
@@ -187,7 +430,8 @@ impl Ordering for Year {
         Some(self.items.get(pos).unwrap().order_in_resolution.clone())
     }
 
-    fn new_ordering_finished(&self) {
-        let _ = db_sqlite::update_items(&self.items);
+    fn new_ordering_finished(&self) -> AppResult<()> {
+        self.storage.update_items(&self.items)?;
+        Ok(())
     }
 }